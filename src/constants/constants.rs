@@ -1,7 +1,14 @@
 pub const CONFIG_FILE: &str = ".tinycache.conf";
+pub const USERS_FILE: &str = "users.json";
+pub const TOKENS_FILE: &str = "tokens.json";
+pub const SESSIONS_FILE: &str = "sessions.json";
+pub const AUDIT_FILE: &str = "audit.log";
 pub const KEY_VALUE: &str = "kv";
 pub const DEFAULT_PORT: &str = "6379";
+pub const DEFAULT_HTTP_PORT: &str = "8080";
 pub const LRU: &str = "LRU";
 pub const LFU: &str = "LFU";
 pub const LFRU: &str = "LFRU";
+pub const PUBSUB_DROP: &str = "drop";
+pub const PUBSUB_DISCONNECT: &str = "disconnect";
 pub const HOME_FOLDER: &str = ".tinycache";
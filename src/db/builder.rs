@@ -0,0 +1,114 @@
+use std::{io, path::PathBuf};
+
+use crate::{
+    db::db::TinyCache, persistance::persistance::PersistenceConfig, security::config::DBConfig,
+};
+
+/// Builder for an embedded `TinyCache` instance
+///
+/// Only the data directory is required, it holds the logs and, unless the instance is in-memory
+/// only, the WAL segments. The configurations default to `DBConfig::default()` and
+/// `PersistenceConfig::default(&data_dir)`.
+pub struct TinyCacheBuilder {
+    data_dir: PathBuf,
+    config: Option<DBConfig>,
+    persistence: Option<PersistenceConfig>,
+    in_memory: bool,
+}
+
+impl TinyCacheBuilder {
+    pub fn new(data_dir: impl Into<PathBuf>) -> Self {
+        Self {
+            data_dir: data_dir.into(),
+            config: None,
+            persistence: None,
+            in_memory: false,
+        }
+    }
+
+    pub fn config(mut self, config: DBConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    pub fn persistence(mut self, persistence: PersistenceConfig) -> Self {
+        self.persistence = Some(persistence);
+        self
+    }
+
+    /// *in_memory* disables the WAL: nothing is written to or recovered from disk
+    pub fn in_memory(mut self, in_memory: bool) -> Self {
+        self.in_memory = in_memory;
+        self
+    }
+
+    /// *build* creates the data directory and starts the instance, recovering the databases
+    /// from the WAL unless the instance is in-memory only
+    pub async fn build(self) -> io::Result<TinyCache> {
+        tokio::fs::create_dir_all(&self.data_dir).await?;
+
+        let config = self.config.unwrap_or_default();
+        let mut persistence = self
+            .persistence
+            .unwrap_or_else(|| PersistenceConfig::default(&self.data_dir));
+        if self.in_memory {
+            persistence.wal_enabled = false;
+        }
+
+        TinyCache::new(self.data_dir, config, persistence).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::db::DataValue;
+    use serde_json::json;
+    use tinycache_protocol::AggregationOperation;
+
+    #[tokio::test]
+    async fn test_in_memory_instance() {
+        let data_dir = std::env::temp_dir().join(format!("tinycache-{}", uuid::Uuid::new_v4()));
+        let db = TinyCacheBuilder::new(&data_dir)
+            .in_memory(true)
+            .build()
+            .await
+            .unwrap();
+
+        db.create_key_value(
+            "users",
+            "u1".to_string(),
+            DataValue::Json(json!({"age": 40})),
+        )
+        .await
+        .unwrap();
+        db.create_key_value(
+            "users",
+            "u2".to_string(),
+            DataValue::Json(json!({"age": 20})),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            db.get_key_value("users", "u1").await,
+            Some(DataValue::Json(json!({"age": 40})))
+        );
+        assert_eq!(
+            db.query("users", vec![AggregationOperation::Count]).await,
+            json!({"count": 2})
+        );
+        db.shutdown().await.unwrap();
+
+        // nothing was persisted, so a new instance starts empty
+        assert!(!data_dir.join("data").exists());
+        let db = TinyCacheBuilder::new(&data_dir)
+            .in_memory(true)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(db.get_key_value("users", "u1").await, None);
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+}
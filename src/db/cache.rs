@@ -47,6 +47,17 @@ pub struct CacheItem {
     pub frequency: u32,      // the frequency in which that value is accessed
 }
 
+impl CacheItem {
+    /// *evictable* tells whether the eviction policy may drop the item, streams and queues hold
+    /// messages that are only removed by their own commands
+    fn evictable(&self) -> bool {
+        !matches!(
+            self.value,
+            CacheValue::KeyValue(DataValue::Stream(_) | DataValue::Queue(_), _)
+        )
+    }
+}
+
 // LFRUCache implementation
 
 /// LFRUCache is going to be useful for caching data (keeping it in memory).
@@ -191,6 +202,12 @@ impl Cache {
 
     // TODO: update the ttl since it has been updated
     pub async fn get(&self, key: &CacheKey) -> Option<CacheValue> {
+        self.read(key, |value| value.cloned()).await
+    }
+
+    /// *read* hands the value of the key to `read` under the shard lock, so large values are not
+    /// copied to be read. `read` gets `None` when the key is missing or expired.
+    async fn read<T>(&self, key: &CacheKey, read: impl FnOnce(Option<&CacheValue>) -> T) -> T {
         let shard_idx = self.get_shard_index(key);
         let mut shard = self.shards[shard_idx].write().await;
 
@@ -206,7 +223,7 @@ impl Cache {
                 shard.remove(key);
                 lru_queue.retain(|k| k != key);
                 self.metrics.record_miss();
-                return None;
+                return read(None);
             }
             item.frequency += 1;
             item.last_access = now;
@@ -214,10 +231,10 @@ impl Cache {
             lru_queue.retain(|k| k != key);
             lru_queue.push_back(key.clone());
             self.metrics.record_hit();
-            Some(item.value.clone())
+            read(Some(&item.value))
         } else {
             self.metrics.record_miss();
-            None
+            read(None)
         }
     }
 
@@ -254,11 +271,14 @@ impl Cache {
             lru_queue.retain(|k| k != &key);
         }
 
-        // eviction based on policy
+        // eviction based on policy, a shard that only holds items it cannot evict grows instead
         while shard.len() >= self.max_size / self.shard_count {
             match self.eviction_policy.as_str() {
                 LRU => {
-                    if let Some(key) = lru_queue.pop_front() {
+                    let evictable = lru_queue
+                        .iter()
+                        .position(|k| shard.get(k).is_none_or(CacheItem::evictable));
+                    if let Some(key) = evictable.and_then(|index| lru_queue.remove(index)) {
                         shard.remove(&key);
                         lru_queue.retain(|k| k != &key);
                     } else {
@@ -267,18 +287,28 @@ impl Cache {
                 }
 
                 LFU => {
-                    if let Some((key, _)) = shard.iter().min_by_key(|(_, item)| item.frequency) {
+                    if let Some((key, _)) = shard
+                        .iter()
+                        .filter(|(_, item)| item.evictable())
+                        .min_by_key(|(_, item)| item.frequency)
+                    {
                         let key = key.clone();
                         shard.remove(&key);
                         lru_queue.retain(|k| k != &key);
+                    } else {
+                        break;
                     }
                 }
 
                 LFRU => {
                     let mut candidates: Vec<(CacheKey, u32, u64)> = shard
                         .iter()
+                        .filter(|(_, item)| item.evictable())
                         .map(|(k, v)| (k.clone(), v.frequency, v.last_access))
                         .collect();
+                    if candidates.is_empty() {
+                        break;
+                    }
 
                     // Sort by combined score: frequency (ascending) + age penalty (descending by last_access)
                     candidates.sort_by(|a, b| {
@@ -355,7 +385,7 @@ impl Cache {
         .await;
     }
 
-    pub async fn get_key_value(&mut self, database: &str, key: &str) -> Option<DataValue> {
+    pub async fn get_key_value(&self, database: &str, key: &str) -> Option<DataValue> {
         let cache_key = CacheKey {
             database: database.to_string(),
             key: key.to_string(),
@@ -367,6 +397,28 @@ impl Cache {
         })
     }
 
+    /// *read_key_value* hands the value of the key to `read` without copying it, `None` when
+    /// the key is missing or expired
+    pub async fn read_key_value<T>(
+        &self,
+        database: &str,
+        key: &str,
+        read: impl FnOnce(Option<&DataValue>) -> T,
+    ) -> T {
+        let cache_key = CacheKey {
+            database: database.to_string(),
+            key: key.to_string(),
+            entry_type: CacheEntryType::KeyValue,
+        };
+
+        self.read(&cache_key, |value| {
+            read(value.map(|value| match value {
+                CacheValue::KeyValue(value, _) => value,
+            }))
+        })
+        .await
+    }
+
     pub async fn update_key_value(
         &mut self,
         database: &str,
@@ -442,6 +494,70 @@ impl Cache {
         None
     }
 
+    /// *modify_key_value* hands the value of the key to `apply` under the shard lock, so reading
+    /// and writing it is a single step. `apply` gets `None` when the key is missing or expired,
+    /// and leaves `None` behind to delete the key. Keys it creates expire after `ttl`.
+    pub async fn modify_key_value<T>(
+        &mut self,
+        database: &str,
+        key: &str,
+        ttl: Option<Duration>,
+        apply: impl FnOnce(&mut Option<DataValue>) -> T,
+    ) -> T {
+        let cache_key = CacheKey {
+            database: database.to_string(),
+            key: key.to_string(),
+            entry_type: CacheEntryType::KeyValue,
+        };
+        let shard_idx = self.get_shard_index(&cache_key);
+        let mut shard = self.shards[shard_idx].write().await;
+        let mut lru_queue = self.lru_queues[shard_idx].write().await;
+        let now = compute_now_timestamp();
+
+        if shard
+            .get(&cache_key)
+            .is_some_and(|item| item.expiry.is_some_and(|e| now > e))
+        {
+            shard.remove(&cache_key);
+            lru_queue.retain(|k| k != &cache_key);
+        }
+
+        if let Some(item) = shard.get_mut(&cache_key) {
+            let CacheValue::KeyValue(value, _) = &mut item.value;
+            // the value is moved out, so large collections are never copied
+            let mut slot = Some(std::mem::replace(value, DataValue::String(String::new())));
+            let result = apply(&mut slot);
+
+            lru_queue.retain(|k| k != &cache_key);
+            match slot {
+                Some(new_value) => {
+                    *value = new_value;
+                    item.frequency += 1;
+                    item.last_access = now;
+                    lru_queue.push_back(cache_key);
+                }
+                None => {
+                    shard.remove(&cache_key);
+                }
+            }
+            return result;
+        }
+        drop(shard);
+        drop(lru_queue);
+
+        let mut slot = None;
+        let result = apply(&mut slot);
+        if let Some(value) = slot {
+            self.insert(
+                cache_key,
+                CacheValue::KeyValue(value, ttl.map(|d| d.as_secs())),
+                ttl,
+            )
+            .await;
+        }
+        result
+    }
+
     // pub fn _decr_key_value(&mut self, database: &str, key: &str, amount: f64) -> Option<f64> {
     //     self.incr_key_value(database, key, -amount).await
     // }
@@ -499,3 +615,34 @@ impl CacheMetrics {
         self.shard_loads[shard_idx].load(Ordering::Relaxed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{queue::Queue, stream::Stream};
+
+    #[tokio::test]
+    async fn test_eviction_skips_streams_and_queues() {
+        for policy in [LRU, LFU, LFRU] {
+            let mut cache = Cache::new(3, policy.to_string(), 1);
+            let stream = DataValue::Stream(Stream::default());
+            cache
+                .insert_key_value("db", "events".into(), stream, None)
+                .await;
+            let queue = DataValue::Queue(Queue::default());
+            cache
+                .insert_key_value("db", "jobs".into(), queue, None)
+                .await;
+            for key in ["a", "b", "c"] {
+                let value = DataValue::String(key.to_string());
+                cache
+                    .insert_key_value("db", key.to_string(), value, None)
+                    .await;
+            }
+            assert!(cache.get_key_value("db", "events").await.is_some());
+            assert!(cache.get_key_value("db", "jobs").await.is_some());
+            assert!(cache.get_key_value("db", "c").await.is_some());
+            assert!(cache.get_key_value("db", "a").await.is_none());
+        }
+    }
+}
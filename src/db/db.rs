@@ -1,10 +1,35 @@
 use crate::{
-    db::cache::{Cache, CacheEntryType, CacheKey, CacheValue},
+    constants::constants::{AUDIT_FILE, SESSIONS_FILE, TOKENS_FILE, USERS_FILE},
+    db::{
+        builder::TinyCacheBuilder,
+        cache::{Cache, CacheEntryType, CacheKey, CacheValue},
+        document::{self, PatchOperation},
+        events::{KeyEvent, KeyEventKind, EVENT_CHANNEL_CAPACITY},
+        hash::{self, Fields},
+        list::{self, ListEnd},
+        pubsub::{PubSub, SlowConsumerPolicy},
+        queue::{Delivery, Queue},
+        schema::{Schema, SchemaRegistry},
+        set::{self, Members, SetOperation},
+        sorted_set::{AddOptions, SortedSet},
+        stream::{Stream, StreamEntry, StreamId},
+        vector::{IndexConfig, Metric, Vector, VectorIndex, VectorMatch},
+    },
     persistance::persistance::{PersistenceConfig, PersistenceManager, WalOperation},
-    security::{auth::AuthManager, config::DBConfig},
+    query::query::{aggregate, apply_filter, AggregationOperation, FilterCondition},
+    requests::connections::ConnectionRegistry,
+    security::{
+        acl::KeyAcl,
+        audit::{AuditAction, AuditEvent, AuditLog},
+        auth::AuthManager,
+        config::DBConfig,
+        rate_limit::{RateLimiter, ThrottleStats},
+        tokens::TokenStore,
+        users::UserStore,
+    },
     utils::{
         logs::{LogLevel, Logger},
-        utils::compute_expiry,
+        utils::{compute_expiry, compute_now_millis, compute_now_timestamp},
     },
 };
 use dashmap::DashMap;
@@ -12,14 +37,17 @@ use dashmap::DashMap;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, VecDeque},
     io::{self},
     path::PathBuf,
     sync::Arc,
     time::Duration,
 };
 
-use tokio::sync::RwLock;
+use tokio::{
+    sync::{broadcast, watch, RwLock},
+    time::{sleep_until, Instant},
+};
 
 #[derive(Debug, PartialEq)]
 pub enum DatabaseType {
@@ -40,9 +68,227 @@ impl DatabaseType {
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub enum DataValue {
     String(String),
-    List(Vec<String>),
+    List(VecDeque<String>),
     Set(HashMap<String, ()>),
     Json(JsonValue),
+    Hash(HashMap<String, String>),
+    SortedSet(SortedSet),
+    Vector(Vector),
+    Stream(Stream),
+    Queue(Queue),
+}
+
+/// Why a command on a value of a given type failed
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValueError {
+    WrongType,               // the key holds a value of another type
+    NotFound,                // the key does not exist
+    OutOfRange,              // the index lies outside the value
+    NotANumber,              // the value is not a number, or the result would not be a finite one
+    PathNotFound,            // nothing lies at the path in the document
+    TestFailed,              // a `test` operation of a JSON patch did not match
+    DimensionMismatch,       // the vector has another dimension than the index of the database
+    IdTooSmall,              // the stream ID is not greater than the last ID of the stream
+    GroupExists,             // the consumer group already exists
+    NoSuchGroup,             // the consumer group does not exist
+    QueueFull,               // the queue holds `max_queue_size` messages
+    InvalidReceipt, // no message was delivered with the receipt, or it was delivered again since
+    SchemaViolation(String), // the document would not conform to its schema, with the violations
+}
+
+impl ValueError {
+    /// *code* is the error code returned to clients, a schema violation carries its details
+    pub fn code(&self) -> String {
+        match self {
+            ValueError::WrongType => "WRONGTYPE".to_string(),
+            ValueError::NotFound => "NOT_FOUND".to_string(),
+            ValueError::OutOfRange => "INDEX_OUT_OF_RANGE".to_string(),
+            ValueError::NotANumber => "NOT_A_NUMBER".to_string(),
+            ValueError::PathNotFound => "PATH_NOT_FOUND".to_string(),
+            ValueError::TestFailed => "PATCH_TEST_FAILED".to_string(),
+            ValueError::DimensionMismatch => "DIMENSION_MISMATCH".to_string(),
+            ValueError::IdTooSmall => "STREAM_ID_TOO_SMALL".to_string(),
+            ValueError::GroupExists => "GROUP_EXISTS".to_string(),
+            ValueError::NoSuchGroup => "NO_SUCH_GROUP".to_string(),
+            ValueError::QueueFull => "QUEUE_FULL".to_string(),
+            ValueError::InvalidReceipt => "INVALID_RECEIPT".to_string(),
+            ValueError::SchemaViolation(violations) => violations.clone(),
+        }
+    }
+}
+
+/// A collection held by a `DataValue`, the list, set, hash, sorted set, stream and queue commands
+/// share their read and write paths through it.
+/// A missing key behaves like an empty collection, and a write that leaves the collection
+/// `removable` removes its key, so no command has to tell a missing key from an empty value.
+trait Collection: Default {
+    /// *from_value* unwraps the collection, or gives the value back when it is of another type
+    fn from_value(value: DataValue) -> Result<Self, DataValue>;
+    /// *from_ref* borrows the collection, `None` when the value is of another type
+    fn from_ref(value: &DataValue) -> Option<&Self>;
+    fn into_value(self) -> DataValue;
+
+    /// *removable* tells whether the key can go, which a collection left empty can unless it
+    /// keeps state past its elements
+    fn removable(&self) -> bool;
+
+    /// *durable* tells whether the collection holds messages that must not be lost, such
+    /// collections are created without a TTL and the cache never evicts them
+    fn durable() -> bool {
+        false
+    }
+}
+
+impl Collection for VecDeque<String> {
+    fn from_value(value: DataValue) -> Result<Self, DataValue> {
+        match value {
+            DataValue::List(list) => Ok(list),
+            other => Err(other),
+        }
+    }
+
+    fn from_ref(value: &DataValue) -> Option<&Self> {
+        match value {
+            DataValue::List(list) => Some(list),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> DataValue {
+        DataValue::List(self)
+    }
+
+    fn removable(&self) -> bool {
+        VecDeque::is_empty(self)
+    }
+}
+
+impl Collection for Members {
+    fn from_value(value: DataValue) -> Result<Self, DataValue> {
+        match value {
+            DataValue::Set(set) => Ok(set),
+            other => Err(other),
+        }
+    }
+
+    fn from_ref(value: &DataValue) -> Option<&Self> {
+        match value {
+            DataValue::Set(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> DataValue {
+        DataValue::Set(self)
+    }
+
+    fn removable(&self) -> bool {
+        HashMap::is_empty(self)
+    }
+}
+
+impl Collection for Fields {
+    fn from_value(value: DataValue) -> Result<Self, DataValue> {
+        match value {
+            DataValue::Hash(hash) => Ok(hash),
+            other => Err(other),
+        }
+    }
+
+    fn from_ref(value: &DataValue) -> Option<&Self> {
+        match value {
+            DataValue::Hash(hash) => Some(hash),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> DataValue {
+        DataValue::Hash(self)
+    }
+
+    fn removable(&self) -> bool {
+        HashMap::is_empty(self)
+    }
+}
+
+impl Collection for SortedSet {
+    fn from_value(value: DataValue) -> Result<Self, DataValue> {
+        match value {
+            DataValue::SortedSet(set) => Ok(set),
+            other => Err(other),
+        }
+    }
+
+    fn from_ref(value: &DataValue) -> Option<&Self> {
+        match value {
+            DataValue::SortedSet(set) => Some(set),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> DataValue {
+        DataValue::SortedSet(self)
+    }
+
+    fn removable(&self) -> bool {
+        SortedSet::is_empty(self)
+    }
+}
+
+impl Collection for Stream {
+    fn from_value(value: DataValue) -> Result<Self, DataValue> {
+        match value {
+            DataValue::Stream(stream) => Ok(stream),
+            other => Err(other),
+        }
+    }
+
+    fn from_ref(value: &DataValue) -> Option<&Self> {
+        match value {
+            DataValue::Stream(stream) => Some(stream),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> DataValue {
+        DataValue::Stream(self)
+    }
+
+    fn removable(&self) -> bool {
+        self.is_new()
+    }
+
+    fn durable() -> bool {
+        true
+    }
+}
+
+impl Collection for Queue {
+    fn from_value(value: DataValue) -> Result<Self, DataValue> {
+        match value {
+            DataValue::Queue(queue) => Ok(queue),
+            other => Err(other),
+        }
+    }
+
+    fn from_ref(value: &DataValue) -> Option<&Self> {
+        match value {
+            DataValue::Queue(queue) => Some(queue),
+            _ => None,
+        }
+    }
+
+    fn into_value(self) -> DataValue {
+        DataValue::Queue(self)
+    }
+
+    fn removable(&self) -> bool {
+        self.is_new()
+    }
+
+    fn durable() -> bool {
+        true
+    }
 }
 
 /// Statistics for a single database instance.
@@ -50,6 +296,8 @@ pub enum DataValue {
 pub struct DatabaseStats {
     pub entry_count: usize,      // Number of entries in the cache
     pub eviction_policy: String, // Current eviction policy
+    #[serde(default)]
+    pub rate_limited: ThrottleStats, // Requests rejected by the rate limits
 }
 
 // This is the backborne of this server
@@ -60,9 +308,16 @@ pub struct TinyCache {
     pub auth_manager: Arc<AuthManager>, // *auth_manager* initialised, and tracks the database auth
     pub config: Arc<DBConfig>,          // *config* holds the database configurations
     pub logger: Logger,                 // *logger* is the internal logger for the system
-    pub active_connections: Arc<RwLock<usize>>, // *active_connections* tracks the number of active connections
+    pub connections: Arc<ConnectionRegistry>, // *connections* tracks the open client connections
+    pub rate_limiter: Arc<RateLimiter>, // *rate_limiter* throttles the users and databases
+    pub audit: Arc<AuditLog>, // *audit* records security relevant and destructive operations
     pub current_database: Arc<RwLock<Option<String>>>, // *current_database* sets the current database
     pub persistence: Arc<PersistenceManager>,
+    pub events: broadcast::Sender<KeyEvent>, // *events* broadcasts every change made to a key
+    pub schemas: Arc<SchemaRegistry>,        // *schemas* holds the JSON Schemas of the databases
+    pub vector_indexes: Arc<DashMap<String, VectorIndex>>, // *vector_indexes* holds the HNSW index of every database with vectors
+    pub pubsub: Arc<PubSub>, // *pubsub* holds the pub/sub subscribers of the connections
+    pub shutdown_signal: Arc<watch::Sender<bool>>, // *shutdown_signal* flips to true once the instance shuts down
 }
 
 impl TinyCache {
@@ -75,18 +330,39 @@ impl TinyCache {
         config: DBConfig,
         persist_config: PersistenceConfig,
     ) -> io::Result<Self> {
-        let auth_manager = AuthManager::new(config.clone());
+        let users = UserStore::load(data_dir.join(USERS_FILE)).await?;
+        let tokens = TokenStore::load(data_dir.join(TOKENS_FILE)).await?;
+        let sessions_path = config
+            .persist_sessions
+            .then(|| data_dir.join(SESSIONS_FILE));
+        let auth_manager = AuthManager::new(config.clone(), users, tokens, sessions_path);
+        auth_manager.load_sessions().await?;
+        let audit = AuditLog::open(data_dir.join(AUDIT_FILE)).await?;
+        audit.record_config(&config).await?;
         let logger = Logger::new(data_dir.clone()).await?;
         let persistence = PersistenceManager::new(persist_config).await?;
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+        let (shutdown_signal, _) = watch::channel(false);
 
         let tinycache = TinyCache {
             databases: Arc::new(DashMap::new()),
             auth_manager: Arc::new(auth_manager),
             config: Arc::new(config.clone()),
             logger,
-            active_connections: Arc::new(RwLock::new(0)),
+            connections: Arc::new(ConnectionRegistry::new()),
+            rate_limiter: Arc::new(RateLimiter::new(&config)),
+            audit: Arc::new(audit),
             current_database: Arc::new(RwLock::new(None)),
             persistence: Arc::new(persistence),
+            events,
+            schemas: Arc::new(SchemaRegistry::new()),
+            vector_indexes: Arc::new(DashMap::new()),
+            pubsub: Arc::new(PubSub::new(
+                config.pubsub_buffer_size,
+                SlowConsumerPolicy::parse(&config.pubsub_slow_consumer)
+                    .unwrap_or(SlowConsumerPolicy::Drop),
+            )),
+            shutdown_signal: Arc::new(shutdown_signal),
         };
 
         tinycache.ensure_db_exists("default").await;
@@ -96,6 +372,62 @@ impl TinyCache {
         Ok(tinycache)
     }
 
+    /// *builder* starts building an instance that stores its data under `data_dir`
+    pub fn builder(data_dir: impl Into<PathBuf>) -> TinyCacheBuilder {
+        TinyCacheBuilder::new(data_dir)
+    }
+
+    /// *begin_shutdown* tells the servers to stop accepting clients and to drain connections
+    pub fn begin_shutdown(&self) {
+        self.shutdown_signal.send_replace(true);
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        *self.shutdown_signal.borrow()
+    }
+
+    /// *shutdown_requested* resolves once `begin_shutdown` has been called
+    pub async fn shutdown_requested(&self) {
+        let mut receiver = self.shutdown_signal.subscribe();
+        let _ = receiver.wait_for(|shutting_down| *shutting_down).await;
+    }
+
+    /// *shutdown* signals the shutdown, writes the final snapshots when `snapshot_on_shutdown`
+    /// is set, syncs the WAL of every database, saves the sessions when `persist_sessions` is
+    /// set, saves the last uses of tokens and flushes the logs
+    ///
+    /// Snapshots assume that no more writes come in, so servers should be drained first. The
+    /// instance stays usable, but writes made after it returns are only as durable as the
+    /// configured sync policy makes them.
+    pub async fn shutdown(&self) -> io::Result<()> {
+        self.begin_shutdown();
+
+        if self.config.snapshot_on_shutdown {
+            self.snapshot_all().await?;
+        }
+        self.persistence.sync_all().await?;
+        // saved last, so the sessions keep the expiry of their latest use
+        self.auth_manager.save_sessions().await?;
+        self.auth_manager
+            .tokens
+            .flush()
+            .await
+            .map_err(io::Error::other)?;
+        self.logger
+            .log_info("TinyCache shut down", LogLevel::System, self)
+            .await?;
+        self.logger.flush().await
+    }
+
+    /// *snapshot_all* writes a snapshot of every database and drops the WAL segments it covers
+    pub async fn snapshot_all(&self) -> io::Result<()> {
+        let databases: Vec<String> = self.databases.iter().map(|db| db.key().clone()).collect();
+        for database in databases {
+            self.persistence.snapshot(&database, self).await?;
+        }
+        Ok(())
+    }
+
     pub async fn recover_all(&self) -> io::Result<()> {
         self.logger
             .log_info(
@@ -117,6 +449,18 @@ impl TinyCache {
         *current = database.map(String::from);
     }
 
+    /// *publish_event* notifies subscribers about a change to a key
+    ///
+    /// Sending only fails when nobody is listening, which is not an error for the writer.
+    fn publish_event(&self, database: &str, key: Option<&str>, kind: KeyEventKind) {
+        let _ = self.events.send(KeyEvent {
+            database: database.to_string(),
+            key: key.map(String::from),
+            kind,
+            timestamp: compute_now_timestamp(),
+        });
+    }
+
     /// *ensure_db_exists* ensures a database exists in memory and initialised
     async fn ensure_db_exists(&self, database: &str) {
         self.databases
@@ -153,6 +497,7 @@ impl TinyCache {
         Some(DatabaseStats {
             entry_count,
             eviction_policy: cache_lock.eviction_policy.clone(),
+            rate_limited: self.rate_limiter.stats(database),
         })
     }
 
@@ -177,17 +522,31 @@ impl TinyCache {
                 DatabaseStats {
                     entry_count,
                     eviction_policy: cache_lock.eviction_policy.clone(),
+                    rate_limited: self.rate_limiter.stats(entry.key()),
                 },
             );
         }
         stats
     }
 
-    /// *drop_db* clears all database files
+    /// *drop_db* clears all database files and records the drop in the audit log
     pub async fn drop_db(&self, database: &str) -> io::Result<()> {
+        self.remove_db(database).await?;
+        let _ = self
+            .audit
+            .record(AuditEvent::new(AuditAction::DropDb, "").database(Some(database.to_string())))
+            .await;
+        Ok(())
+    }
+
+    /// *remove_db* clears all database files without auditing, for callers that audit the drop
+    /// themselves and for replaying drops that were audited when they happened
+    pub(crate) async fn remove_db(&self, database: &str) -> io::Result<()> {
         self.persistence
             .log_operation(database, WalOperation::DropDb)
             .await?;
+        self.schemas.remove_database(database);
+        self.vector_indexes.remove(database);
 
         let removed = self.databases.remove(database);
         if let Some((_, cache)) = removed {
@@ -202,6 +561,7 @@ impl TinyCache {
             }
         }
 
+        self.publish_event(database, None, KeyEventKind::DropDb);
         Ok(())
     }
 
@@ -213,6 +573,49 @@ impl TinyCache {
         self.databases.get(database).unwrap().clone()
     }
 
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    //////////////////////////////////////// SCHEMAS ///////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *set_schema* registers the JSON Schema that the JSON values of the keys starting with
+    /// `prefix` have to conform to, the empty prefix covering the whole database.
+    /// Fails with the reason when `schema` is not a valid schema.
+    pub async fn set_schema(
+        &self,
+        database: &str,
+        prefix: &str,
+        schema: JsonValue,
+    ) -> io::Result<Result<(), String>> {
+        let compiled = match Schema::compile(schema.clone()) {
+            Ok(compiled) => compiled,
+            Err(reason) => return Ok(Err(reason)),
+        };
+        self.persistence
+            .log_operation(
+                database,
+                WalOperation::SetSchema {
+                    prefix: prefix.to_string(),
+                    schema,
+                },
+            )
+            .await?;
+        self.schemas.register(database, prefix, compiled);
+        Ok(Ok(()))
+    }
+
+    /// *drop_schema* removes the schema of the prefix and returns whether there was one
+    pub async fn drop_schema(&self, database: &str, prefix: &str) -> io::Result<bool> {
+        self.persistence
+            .log_operation(
+                database,
+                WalOperation::DropSchema {
+                    prefix: prefix.to_string(),
+                },
+            )
+            .await?;
+        Ok(self.schemas.remove(database, prefix))
+    }
+
     ////////////////////////////////////////////////////////////////////////////////////////////
     /////////////////////////////////////// KEY_VALUE //////////////////////////////////////////
     ////////////////////////////////////////////////////////////////////////////////////////////
@@ -227,6 +630,8 @@ impl TinyCache {
         key: String,
         value: DataValue,
     ) -> io::Result<()> {
+        self.check_schema(database, &key, &value)?;
+
         // Log the operation to WAL first (for durability)
         self.persistence
             .log_operation(
@@ -254,8 +659,10 @@ impl TinyCache {
         cache
             .write()
             .await
-            .insert_key_value(database, key, value, expiry)
+            .insert_key_value(database, key.clone(), value, expiry)
             .await;
+
+        self.publish_event(database, Some(&key), KeyEventKind::Set);
         Ok(())
     }
 
@@ -266,6 +673,7 @@ impl TinyCache {
         value: DataValue,
         ttl: Duration,
     ) -> io::Result<()> {
+        self.check_schema(database, &key, &value)?;
         self.persistence
             .log_operation(
                 database,
@@ -281,14 +689,17 @@ impl TinyCache {
         let mut cache_lock = cache.write().await;
 
         cache_lock
-            .insert_key_value(database, key, value, Some(ttl))
+            .insert_key_value(database, key.clone(), value, Some(ttl))
             .await;
+        drop(cache_lock);
+
+        self.publish_event(database, Some(&key), KeyEventKind::Set);
         Ok(())
     }
 
     pub async fn get_key_value(&self, database: &str, key: &str) -> Option<DataValue> {
         let cache = self.get_cache(database).await;
-        let mut cache_lock = cache.write().await;
+        let cache_lock = cache.read().await;
         cache_lock.get_key_value(database, key).await
     }
 
@@ -310,7 +721,14 @@ impl TinyCache {
             entry_type: CacheEntryType::KeyValue,
         };
         let deleted = cache_lock.delete(&cache_key).await.is_some();
+        if let Some(mut index) = self.vector_indexes.get_mut(database) {
+            index.remove(key);
+        }
+        drop(cache_lock);
 
+        if deleted {
+            self.publish_event(database, Some(key), KeyEventKind::Delete);
+        }
         Ok(deleted)
     }
 
@@ -334,7 +752,11 @@ impl TinyCache {
         let mut cache_lock = cache.write().await;
 
         let result = cache_lock.incr_key_value(database, key, amount).await;
+        drop(cache_lock);
 
+        if result.is_some() {
+            self.publish_event(database, Some(key), KeyEventKind::Increment);
+        }
         Ok(result)
     }
 
@@ -363,6 +785,7 @@ impl TinyCache {
         value: DataValue,
         ttl: Option<Duration>,
     ) -> io::Result<Option<DataValue>> {
+        self.check_schema(database, key, &value)?;
         self.persistence
             .log_operation(
                 database,
@@ -379,39 +802,1480 @@ impl TinyCache {
             .await
             .update_key_value(database, key, value.clone(), ttl)
             .await;
+
+        if old_value.is_some() {
+            self.publish_event(database, Some(key), KeyEventKind::Update);
+        }
         Ok(old_value)
     }
 
-    // Returns a Json representation of all data in the database
-    // Includes key, value, and expiry information
-    pub async fn view_data(&self, database: &str) -> JsonValue {
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    ///////////////////////////////////////// LISTS ////////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *list_push* adds values to the head or tail of a list, creating it when missing, and
+    /// returns the new length
+    pub async fn list_push(
+        &self,
+        database: &str,
+        key: &str,
+        values: Vec<String>,
+        end: ListEnd,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = match end {
+            ListEnd::Head => WalOperation::LPush {
+                key: key.to_string(),
+                values: values.clone(),
+            },
+            ListEnd::Tail => WalOperation::RPush {
+                key: key.to_string(),
+                values: values.clone(),
+            },
+        };
+        self.write_collection(database, key, operation, |list| {
+            Ok(list::push(list, values, end))
+        })
+        .await
+    }
+
+    /// *list_pop* removes up to `count` values from the head or tail of a list
+    pub async fn list_pop(
+        &self,
+        database: &str,
+        key: &str,
+        count: usize,
+        end: ListEnd,
+    ) -> io::Result<Result<Vec<String>, ValueError>> {
+        let operation = match end {
+            ListEnd::Head => WalOperation::LPop {
+                key: key.to_string(),
+                count,
+            },
+            ListEnd::Tail => WalOperation::RPop {
+                key: key.to_string(),
+                count,
+            },
+        };
+        self.write_collection(database, key, operation, |list| {
+            Ok(list::pop(list, count, end))
+        })
+        .await
+    }
+
+    pub async fn list_set(
+        &self,
+        database: &str,
+        key: &str,
+        index: i64,
+        value: String,
+    ) -> io::Result<Result<(), ValueError>> {
+        let operation = WalOperation::LSet {
+            key: key.to_string(),
+            index,
+            value: value.clone(),
+        };
+        self.write_collection(database, key, operation, |list| {
+            list::set(list, index, value)
+        })
+        .await
+    }
+
+    /// *list_remove* drops up to `count` elements equal to `value`, see `list::remove`
+    pub async fn list_remove(
+        &self,
+        database: &str,
+        key: &str,
+        count: i64,
+        value: String,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::LRem {
+            key: key.to_string(),
+            count,
+            value: value.clone(),
+        };
+        self.write_collection(database, key, operation, |list| {
+            Ok(list::remove(list, count, &value))
+        })
+        .await
+    }
+
+    pub async fn list_trim(
+        &self,
+        database: &str,
+        key: &str,
+        start: i64,
+        stop: i64,
+    ) -> io::Result<Result<(), ValueError>> {
+        let operation = WalOperation::LTrim {
+            key: key.to_string(),
+            start,
+            stop,
+        };
+        self.write_collection(database, key, operation, |list| {
+            list::trim(list, start, stop);
+            Ok(())
+        })
+        .await
+    }
+
+    /// *list_insert* puts `value` next to the first `pivot`, returns the new length or `None`
+    /// when the pivot is not in the list
+    pub async fn list_insert(
+        &self,
+        database: &str,
+        key: &str,
+        before: bool,
+        pivot: String,
+        value: String,
+    ) -> io::Result<Result<Option<usize>, ValueError>> {
+        let operation = WalOperation::LInsert {
+            key: key.to_string(),
+            before,
+            pivot: pivot.clone(),
+            value: value.clone(),
+        };
+        self.write_collection(database, key, operation, |list| {
+            Ok(list::insert(list, before, &pivot, value))
+        })
+        .await
+    }
+
+    /// *read_list* runs `read` on a list, a missing key reads as an empty list
+    pub async fn read_list<T>(
+        &self,
+        database: &str,
+        key: &str,
+        read: impl FnOnce(&VecDeque<String>) -> T,
+    ) -> Result<T, ValueError> {
+        self.read_collection(database, key, read).await
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    ///////////////////////////////////////// SETS /////////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *set_add* adds members to a set, creating it when missing, and returns how many were new
+    pub async fn set_add(
+        &self,
+        database: &str,
+        key: &str,
+        members: Vec<String>,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::SAdd {
+            key: key.to_string(),
+            members: members.clone(),
+        };
+        self.write_collection(database, key, operation, |set| Ok(set::add(set, members)))
+            .await
+    }
+
+    /// *set_remove* removes members from a set and returns how many were in it
+    pub async fn set_remove(
+        &self,
+        database: &str,
+        key: &str,
+        members: Vec<String>,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::SRem {
+            key: key.to_string(),
+            members: members.clone(),
+        };
+        self.write_collection(database, key, operation, |set| {
+            Ok(set::remove(set, &members))
+        })
+        .await
+    }
+
+    /// *set_pop* removes up to `count` random members of a set. The members are picked before
+    /// the write is logged, and logged as the removal of exactly them.
+    pub async fn set_pop(
+        &self,
+        database: &str,
+        key: &str,
+        count: usize,
+    ) -> io::Result<Result<Vec<String>, ValueError>> {
         let cache = self.get_cache(database).await;
-        let cache_lock = cache.read().await;
-        let mut data_map = serde_json::Map::new();
-        for shard in cache_lock.shards.iter() {
-            let shard_lock = shard.read().await;
-            for (cache_key, item) in shard_lock.iter() {
-                if cache_key.database != database
-                    || cache_key.entry_type != CacheEntryType::KeyValue
-                {
-                    continue;
-                }
-                let CacheValue::KeyValue(value, expiry) = &item.value;
-                let value_data = match value {
-                    DataValue::String(s) => {
-                        json!({"type": "String", "value": s, "expiry": expiry, "created_at": item.created_at})
-                    }
-                    DataValue::List(l) => json!({"type": "List", "value": l, "expiry": expiry}),
-                    DataValue::Set(s) => {
-                        json!({"type": "Set", "value": s.keys().collect::<Vec<_>>(), "expiry": expiry})
-                    }
-                    DataValue::Json(j) => {
-                        json!({"type": "Json", "value": j, "expiry": expiry, "created_at": item.created_at})
-                    }
-                };
-                data_map.insert(cache_key.key.clone(), value_data);
-            }
+        let mut cache_lock = cache.write().await;
+        let picked = cache_lock
+            .modify_key_value(database, key, None, |slot| match slot {
+                Some(DataValue::Set(set)) => Ok(set::sample(set, count)),
+                Some(_) => Err(ValueError::WrongType),
+                None => Ok(Vec::new()),
+            })
+            .await;
+        let members = match picked {
+            Ok(members) if !members.is_empty() => members,
+            picked => return Ok(picked),
+        };
+
+        let operation = WalOperation::SRem {
+            key: key.to_string(),
+            members: members.clone(),
+        };
+        self.persistence.log_operation(database, operation).await?;
+        let (_, kind) = self
+            .apply_collection(&mut cache_lock, database, key, |set: &mut Members| {
+                Ok(set::remove(set, &members))
+            })
+            .await;
+        drop(cache_lock);
+
+        if let Some(kind) = kind {
+            self.publish_event(database, Some(key), kind);
+        }
+        Ok(Ok(members))
+    }
+
+    /// *read_set* runs `read` on a set, a missing key reads as an empty set
+    pub async fn read_set<T>(
+        &self,
+        database: &str,
+        key: &str,
+        read: impl FnOnce(&Members) -> T,
+    ) -> Result<T, ValueError> {
+        self.read_collection(database, key, read).await
+    }
+
+    /// *set_combine* computes the union, intersection or difference of the sets. The keys are
+    /// read under the database lock, so the result is consistent across shards.
+    pub async fn set_combine(
+        &self,
+        database: &str,
+        operation: SetOperation,
+        keys: &[&str],
+    ) -> Result<Members, ValueError> {
+        let cache = self.get_cache(database).await;
+        let cache_lock = cache.read().await;
+        Self::read_sets(&cache_lock, database, keys)
+            .await
+            .map(|sets| set::combine(operation, sets))
+    }
+
+    /// *set_store* stores the result of `set_combine` at `destination`, replacing whatever it
+    /// held, and returns its size. Reading the keys and writing the result is a single step.
+    pub async fn set_store(
+        &self,
+        database: &str,
+        operation: SetOperation,
+        destination: &str,
+        keys: &[&str],
+    ) -> io::Result<Result<usize, ValueError>> {
+        let cache = self.get_cache(database).await;
+        let mut cache_lock = cache.write().await;
+        let members = match Self::read_sets(&cache_lock, database, keys).await {
+            Ok(sets) => set::combine(operation, sets),
+            Err(e) => return Ok(Err(e)),
+        };
+
+        let len = members.len();
+        self.store_set(&mut cache_lock, database, destination, members)
+            .await?;
+        Ok(Ok(len))
+    }
+
+    /// *replace_set* replaces the value of the key by a set of the members, which is how the
+    /// `*STORE` commands are replayed
+    pub(crate) async fn replace_set(
+        &self,
+        database: &str,
+        key: &str,
+        members: Vec<String>,
+    ) -> io::Result<()> {
+        let cache = self.get_cache(database).await;
+        let mut cache_lock = cache.write().await;
+        let members = members.into_iter().map(|member| (member, ())).collect();
+        self.store_set(&mut cache_lock, database, key, members)
+            .await
+    }
+
+    async fn read_sets(
+        cache: &Cache,
+        database: &str,
+        keys: &[&str],
+    ) -> Result<Vec<Members>, ValueError> {
+        let mut sets = Vec::with_capacity(keys.len());
+        for key in keys {
+            match cache.get_key_value(database, key).await {
+                Some(DataValue::Set(set)) => sets.push(set),
+                Some(_) => return Err(ValueError::WrongType),
+                None => sets.push(Members::new()),
+            }
+        }
+        Ok(sets)
+    }
+
+    async fn store_set(
+        &self,
+        cache: &mut Cache,
+        database: &str,
+        key: &str,
+        members: Members,
+    ) -> io::Result<()> {
+        let operation = WalOperation::SStore {
+            key: key.to_string(),
+            members: members.keys().cloned().collect(),
+        };
+        self.persistence.log_operation(database, operation).await?;
+
+        let kind = cache
+            .modify_key_value(database, key, self.default_ttl(), |slot| {
+                let existed = slot.is_some();
+                *slot = (!members.is_empty()).then_some(DataValue::Set(members));
+                match (existed, slot.is_some()) {
+                    (_, true) => Some(KeyEventKind::Set),
+                    (true, false) => Some(KeyEventKind::Delete),
+                    (false, false) => None,
+                }
+            })
+            .await;
+        if let Some(kind) = kind {
+            self.publish_event(database, Some(key), kind);
+        }
+        Ok(())
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    //////////////////////////////////////// HASHES ////////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *hash_set* writes fields of a hash, creating it when missing, and returns how many fields
+    /// were new
+    pub async fn hash_set(
+        &self,
+        database: &str,
+        key: &str,
+        fields: Vec<(String, String)>,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::HSet {
+            key: key.to_string(),
+            fields: fields.clone(),
+        };
+        self.write_collection(database, key, operation, |hash| Ok(hash::set(hash, fields)))
+            .await
+    }
+
+    /// *hash_delete* removes fields of a hash and returns how many were in it
+    pub async fn hash_delete(
+        &self,
+        database: &str,
+        key: &str,
+        fields: Vec<String>,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::HDel {
+            key: key.to_string(),
+            fields: fields.clone(),
+        };
+        self.write_collection(database, key, operation, |hash| {
+            Ok(hash::delete(hash, &fields))
+        })
+        .await
+    }
+
+    /// *hash_incr_by_float* adds `amount` to a field of a hash, see `hash::incr_by_float`
+    pub async fn hash_incr_by_float(
+        &self,
+        database: &str,
+        key: &str,
+        field: &str,
+        amount: f64,
+    ) -> io::Result<Result<f64, ValueError>> {
+        let operation = WalOperation::HIncrByFloat {
+            key: key.to_string(),
+            field: field.to_string(),
+            amount,
+        };
+        self.write_collection(database, key, operation, |hash| {
+            hash::incr_by_float(hash, field, amount)
+        })
+        .await
+    }
+
+    /// *read_hash* runs `read` on a hash, a missing key reads as an empty hash
+    pub async fn read_hash<T>(
+        &self,
+        database: &str,
+        key: &str,
+        read: impl FnOnce(&Fields) -> T,
+    ) -> Result<T, ValueError> {
+        self.read_collection(database, key, read).await
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    ////////////////////////////////////// SORTED SETS /////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *sorted_set_add* adds members to a sorted set or updates their scores as the options
+    /// allow, and returns how many members were added
+    pub async fn sorted_set_add(
+        &self,
+        database: &str,
+        key: &str,
+        members: Vec<(f64, String)>,
+        options: AddOptions,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::ZAdd {
+            key: key.to_string(),
+            members: members.clone(),
+            options,
+        };
+        self.write_collection(database, key, operation, |set: &mut SortedSet| {
+            Ok(set.add(members, options))
+        })
+        .await
+    }
+
+    /// *sorted_set_incr_by* adds `amount` to the score of a member and returns the new score
+    pub async fn sorted_set_incr_by(
+        &self,
+        database: &str,
+        key: &str,
+        member: &str,
+        amount: f64,
+    ) -> io::Result<Result<f64, ValueError>> {
+        let operation = WalOperation::ZIncrBy {
+            key: key.to_string(),
+            member: member.to_string(),
+            amount,
+        };
+        self.write_collection(database, key, operation, |set: &mut SortedSet| {
+            set.incr_by(member, amount)
+        })
+        .await
+    }
+
+    /// *sorted_set_remove* removes members of a sorted set and returns how many were in it
+    pub async fn sorted_set_remove(
+        &self,
+        database: &str,
+        key: &str,
+        members: Vec<String>,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::ZRem {
+            key: key.to_string(),
+            members: members.clone(),
+        };
+        self.write_collection(database, key, operation, |set: &mut SortedSet| {
+            Ok(set.remove(&members))
+        })
+        .await
+    }
+
+    /// *sorted_set_pop* removes up to `count` members with the lowest scores, or the highest
+    /// with `max`. Ties are popped in member order, so replaying the pop removes the same ones.
+    pub async fn sorted_set_pop(
+        &self,
+        database: &str,
+        key: &str,
+        count: usize,
+        max: bool,
+    ) -> io::Result<Result<Vec<(String, f64)>, ValueError>> {
+        let operation = match max {
+            true => WalOperation::ZPopMax {
+                key: key.to_string(),
+                count,
+            },
+            false => WalOperation::ZPopMin {
+                key: key.to_string(),
+                count,
+            },
+        };
+        self.write_collection(database, key, operation, |set: &mut SortedSet| {
+            Ok(set.pop(count, max))
+        })
+        .await
+    }
+
+    /// *read_sorted_set* runs `read` on a sorted set, a missing key reads as an empty one
+    pub async fn read_sorted_set<T>(
+        &self,
+        database: &str,
+        key: &str,
+        read: impl FnOnce(&SortedSet) -> T,
+    ) -> Result<T, ValueError> {
+        self.read_collection(database, key, read).await
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    //////////////////////////////////////// STREAMS ///////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *stream_add* appends an entry to a stream at `now`, in milliseconds, under `id` or under
+    /// an ID generated from `now`, and returns its ID. The stream is trimmed to `max_len`
+    /// entries, and never holds more than `max_stream_size`.
+    pub async fn stream_add(
+        &self,
+        database: &str,
+        key: &str,
+        id: Option<StreamId>,
+        fields: Vec<(String, String)>,
+        max_len: Option<usize>,
+        now: u64,
+    ) -> io::Result<Result<StreamId, ValueError>> {
+        let max_len = match (max_len, self.config.max_stream_size) {
+            (max_len, 0) => max_len,
+            (Some(max_len), max_size) => Some(max_len.min(max_size)),
+            (None, max_size) => Some(max_size),
+        };
+        let operation = WalOperation::XAdd {
+            key: key.to_string(),
+            id,
+            fields: fields.clone(),
+            max_len,
+            time: now,
+        };
+        self.write_collection(database, key, operation, |stream: &mut Stream| {
+            stream.add(id, fields, max_len, now)
+        })
+        .await
+    }
+
+    /// *stream_create_group* adds a consumer group to a stream, delivering the entries after
+    /// `start` or, when `None`, the entries added from now on. A missing stream is only created
+    /// with `make_stream`.
+    pub async fn stream_create_group(
+        &self,
+        database: &str,
+        key: &str,
+        group: &str,
+        start: Option<StreamId>,
+        make_stream: bool,
+    ) -> io::Result<Result<(), ValueError>> {
+        let operation = WalOperation::XGroupCreate {
+            key: key.to_string(),
+            group: group.to_string(),
+            start,
+            make_stream,
+        };
+        self.write_collection(database, key, operation, |stream: &mut Stream| {
+            if stream.is_new() && !make_stream {
+                return Err(ValueError::NotFound);
+            }
+            stream.create_group(group, start)
+        })
+        .await
+    }
+
+    /// *stream_destroy_group* removes a consumer group and returns whether it existed
+    pub async fn stream_destroy_group(
+        &self,
+        database: &str,
+        key: &str,
+        group: &str,
+    ) -> io::Result<Result<bool, ValueError>> {
+        let operation = WalOperation::XGroupDestroy {
+            key: key.to_string(),
+            group: group.to_string(),
+        };
+        self.write_collection(database, key, operation, |stream: &mut Stream| {
+            Ok(stream.destroy_group(group))
+        })
+        .await
+    }
+
+    /// *stream_deliver* delivers up to `count` entries of a stream to a consumer of a group at
+    /// `now`, the entries the group never delivered when `after` is `None`, else the entries
+    /// pending for the consumer after that ID again
+    #[allow(clippy::too_many_arguments)]
+    pub async fn stream_deliver(
+        &self,
+        database: &str,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        now: u64,
+    ) -> io::Result<Result<Vec<StreamEntry>, ValueError>> {
+        // reads finding nothing new are not worth logging
+        if after.is_none() {
+            match self
+                .read_stream(database, key, |stream| stream.has_undelivered(group))
+                .await
+            {
+                Ok(Ok(true)) => {}
+                Ok(Ok(false)) => return Ok(Ok(Vec::new())),
+                Ok(Err(e)) | Err(e) => return Ok(Err(e)),
+            }
+        }
+        let operation = WalOperation::XDeliver {
+            key: key.to_string(),
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            after,
+            count,
+            time: now,
+        };
+        self.write_collection(database, key, operation, |stream: &mut Stream| {
+            stream.read_group(group, consumer, after, count, now)
+        })
+        .await
+    }
+
+    /// *stream_ack* acknowledges entries delivered by a group and returns how many were pending
+    pub async fn stream_ack(
+        &self,
+        database: &str,
+        key: &str,
+        group: &str,
+        ids: Vec<StreamId>,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::XAck {
+            key: key.to_string(),
+            group: group.to_string(),
+            ids: ids.clone(),
+        };
+        self.write_collection(database, key, operation, |stream: &mut Stream| {
+            stream.ack(group, &ids)
+        })
+        .await
+    }
+
+    /// *stream_claim* hands the pending entries of a group idle for at least `min_idle`
+    /// milliseconds at `now` over to the consumer, and returns them
+    #[allow(clippy::too_many_arguments)]
+    pub async fn stream_claim(
+        &self,
+        database: &str,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+        now: u64,
+    ) -> io::Result<Result<Vec<StreamEntry>, ValueError>> {
+        let operation = WalOperation::XClaim {
+            key: key.to_string(),
+            group: group.to_string(),
+            consumer: consumer.to_string(),
+            min_idle,
+            ids: ids.clone(),
+            time: now,
+        };
+        self.write_collection(database, key, operation, |stream: &mut Stream| {
+            stream.claim(group, consumer, min_idle, &ids, now)
+        })
+        .await
+    }
+
+    /// *stream_read* returns up to `count` entries of every stream added after the ID given for
+    /// it, `None` standing for the last ID of the stream when called. When no stream has such
+    /// entries and `block` is given, it waits until one has or `block` elapses, a zero `block`
+    /// waiting as long as it takes. Only the streams with entries are returned.
+    pub async fn stream_read(
+        &self,
+        database: &str,
+        streams: Vec<(String, Option<StreamId>)>,
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> Result<Vec<(String, Vec<StreamEntry>)>, ValueError> {
+        let mut after = Vec::with_capacity(streams.len());
+        for (key, id) in streams {
+            let id = match id {
+                Some(id) => id,
+                None => self.read_stream(database, &key, Stream::last_id).await?,
+            };
+            after.push((key, id));
+        }
+        let keys: Vec<String> = after.iter().map(|(key, _)| key.clone()).collect();
+        let deadline = block_deadline(block);
+
+        loop {
+            // subscribed before reading, so no write goes unnoticed
+            let mut events = self.events.subscribe();
+            let mut found = Vec::new();
+            for (key, id) in &after {
+                let entries = self
+                    .read_stream(database, key, |stream| stream.read_after(*id, count))
+                    .await?;
+                if !entries.is_empty() {
+                    found.push((key.clone(), entries));
+                }
+            }
+            if !found.is_empty()
+                || block.is_none()
+                || !self
+                    .wait_for_write(database, &keys, &mut events, deadline)
+                    .await
+            {
+                return Ok(found);
+            }
+        }
+    }
+
+    /// *stream_read_group* delivers up to `count` entries of every stream to a consumer of a
+    /// group, as `stream_deliver` does, and blocks like `stream_read` when `block` is given and
+    /// no stream has entries the group never delivered
+    pub async fn stream_read_group(
+        &self,
+        database: &str,
+        group: &str,
+        consumer: &str,
+        streams: Vec<(String, Option<StreamId>)>,
+        count: Option<usize>,
+        block: Option<Duration>,
+    ) -> io::Result<Result<Vec<(String, Vec<StreamEntry>)>, ValueError>> {
+        let keys: Vec<String> = streams.iter().map(|(key, _)| key.clone()).collect();
+        let deadline = block_deadline(block);
+
+        loop {
+            let mut events = self.events.subscribe();
+            let mut found = Vec::new();
+            for (key, after) in &streams {
+                let now = compute_now_millis();
+                match self
+                    .stream_deliver(database, key, group, consumer, *after, count, now)
+                    .await?
+                {
+                    Ok(entries) if entries.is_empty() => {}
+                    Ok(entries) => found.push((key.clone(), entries)),
+                    Err(e) => return Ok(Err(e)),
+                }
+            }
+            // the pending entries of the consumer are returned at once, even when there are none
+            let waits = block.is_some() && streams.iter().all(|(_, after)| after.is_none());
+            if !found.is_empty()
+                || !waits
+                || !self
+                    .wait_for_write(database, &keys, &mut events, deadline)
+                    .await
+            {
+                return Ok(Ok(found));
+            }
+        }
+    }
+
+    /// *read_stream* runs `read` on a stream, a missing key reads as an empty one
+    pub async fn read_stream<T>(
+        &self,
+        database: &str,
+        key: &str,
+        read: impl FnOnce(&Stream) -> T,
+    ) -> Result<T, ValueError> {
+        self.read_collection(database, key, read).await
+    }
+
+    /// *wait_for_write* waits until one of the keys is written, `deadline` passes or the
+    /// instance shuts down, and tells whether a key may have been written
+    async fn wait_for_write(
+        &self,
+        database: &str,
+        keys: &[String],
+        events: &mut broadcast::Receiver<KeyEvent>,
+        deadline: Option<Instant>,
+    ) -> bool {
+        let written = async {
+            loop {
+                match events.recv().await {
+                    Ok(event) if event.database == database => {
+                        if event.key.is_none_or(|key| keys.contains(&key)) {
+                            return true;
+                        }
+                    }
+                    Ok(_) => {}
+                    // the events missed may have been about the keys
+                    Err(broadcast::error::RecvError::Lagged(_)) => return true,
+                    Err(broadcast::error::RecvError::Closed) => return false,
+                }
+            }
+        };
+        let timeout = async {
+            match deadline {
+                Some(deadline) => sleep_until(deadline).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            written = written => written,
+            _ = timeout => false,
+            _ = self.shutdown_requested() => false,
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    //////////////////////////////////////// QUEUES ////////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *queue_push* adds a message to a queue at `now`, in milliseconds, visible `delay`
+    /// milliseconds later, and returns its ID. Fails once the queue holds `max_queue_size`
+    /// messages.
+    pub async fn queue_push(
+        &self,
+        database: &str,
+        key: &str,
+        message: String,
+        delay: u64,
+        now: u64,
+    ) -> io::Result<Result<u64, ValueError>> {
+        let operation = WalOperation::QPush {
+            key: key.to_string(),
+            message: message.clone(),
+            delay,
+            time: now,
+        };
+        let max_size = self.config.max_queue_size;
+        self.write_collection(database, key, operation, |queue: &mut Queue| {
+            queue.push(message, delay, now, max_size)
+        })
+        .await
+    }
+
+    /// *queue_pop* delivers the next visible message of a queue at `now`, hidden for `visibility`
+    /// milliseconds or `queue_visibility_timeout_secs`, and moves the messages delivered
+    /// `queue_max_deliveries` times to the dead letters on the way. Returns `None` when no
+    /// message is visible.
+    pub async fn queue_pop(
+        &self,
+        database: &str,
+        key: &str,
+        visibility: Option<u64>,
+        now: u64,
+    ) -> io::Result<Result<Option<Delivery>, ValueError>> {
+        let visibility = visibility.unwrap_or(self.config.queue_visibility_timeout_secs * 1000);
+        self.queue_deliver(
+            database,
+            key,
+            visibility,
+            self.config.queue_max_deliveries,
+            now,
+        )
+        .await
+    }
+
+    /// *queue_deliver* is `queue_pop` with the limits it was logged with, as replayed
+    pub(crate) async fn queue_deliver(
+        &self,
+        database: &str,
+        key: &str,
+        visibility: u64,
+        max_deliveries: u64,
+        now: u64,
+    ) -> io::Result<Result<Option<Delivery>, ValueError>> {
+        // polling an empty queue is not worth logging
+        match self
+            .read_queue(database, key, |queue| queue.has_ready(now))
+            .await
+        {
+            Ok(true) => {}
+            Ok(false) => return Ok(Ok(None)),
+            Err(e) => return Ok(Err(e)),
+        }
+        let operation = WalOperation::QPop {
+            key: key.to_string(),
+            visibility,
+            max_deliveries,
+            time: now,
+        };
+        self.write_collection(database, key, operation, |queue: &mut Queue| {
+            Ok(queue.pop(now, visibility, max_deliveries))
+        })
+        .await
+    }
+
+    /// *queue_ack* removes the message delivered with the receipt from the queue
+    pub async fn queue_ack(
+        &self,
+        database: &str,
+        key: &str,
+        receipt: &str,
+    ) -> io::Result<Result<(), ValueError>> {
+        let operation = WalOperation::QAck {
+            key: key.to_string(),
+            receipt: receipt.to_string(),
+        };
+        self.write_collection(database, key, operation, |queue: &mut Queue| {
+            queue.ack(receipt)
+        })
+        .await
+    }
+
+    /// *queue_nack* makes the message delivered with the receipt visible again `delay`
+    /// milliseconds after `now`
+    pub async fn queue_nack(
+        &self,
+        database: &str,
+        key: &str,
+        receipt: &str,
+        delay: u64,
+        now: u64,
+    ) -> io::Result<Result<(), ValueError>> {
+        let operation = WalOperation::QNack {
+            key: key.to_string(),
+            receipt: receipt.to_string(),
+            delay,
+            time: now,
+        };
+        self.write_collection(database, key, operation, |queue: &mut Queue| {
+            queue.nack(receipt, delay, now)
+        })
+        .await
+    }
+
+    /// *queue_redrive* pushes the dead letters of a queue back at `now` and returns how many
+    /// there were
+    pub async fn queue_redrive(
+        &self,
+        database: &str,
+        key: &str,
+        now: u64,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::QRedrive {
+            key: key.to_string(),
+            time: now,
+        };
+        self.write_collection(database, key, operation, |queue: &mut Queue| {
+            Ok(queue.redrive(now))
+        })
+        .await
+    }
+
+    /// *read_queue* runs `read` on a queue, a missing key reads as an empty one
+    pub async fn read_queue<T>(
+        &self,
+        database: &str,
+        key: &str,
+        read: impl FnOnce(&Queue) -> T,
+    ) -> Result<T, ValueError> {
+        self.read_collection(database, key, read).await
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    /////////////////////////////////////// DOCUMENTS //////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *json_set* writes a value at a path of a JSON document. Setting the root creates the key
+    /// when it is missing, every other path needs the document to exist.
+    pub async fn json_set(
+        &self,
+        database: &str,
+        key: &str,
+        pointer: &str,
+        value: JsonValue,
+    ) -> io::Result<Result<(), ValueError>> {
+        let operation = WalOperation::JsonSet {
+            key: key.to_string(),
+            path: pointer.to_string(),
+            value: value.clone(),
+        };
+        self.write_document(database, key, operation, |slot| match slot {
+            Some(document) => document::set(document, pointer, value),
+            None if pointer.is_empty() => {
+                *slot = Some(value);
+                Ok(())
+            }
+            None => Err(ValueError::NotFound),
+        })
+        .await
+    }
+
+    /// *json_delete* removes the value at a path of a JSON document, the root removes the key
+    pub async fn json_delete(
+        &self,
+        database: &str,
+        key: &str,
+        pointer: &str,
+    ) -> io::Result<Result<(), ValueError>> {
+        let operation = WalOperation::JsonDel {
+            key: key.to_string(),
+            path: pointer.to_string(),
+        };
+        self.write_document(database, key, operation, |slot| match slot {
+            Some(_) if pointer.is_empty() => {
+                *slot = None;
+                Ok(())
+            }
+            Some(document) => document::remove(document, pointer).map(|_| ()),
+            None => Err(ValueError::NotFound),
+        })
+        .await
+    }
+
+    /// *json_array_append* appends values to an array of a JSON document and returns its length
+    pub async fn json_array_append(
+        &self,
+        database: &str,
+        key: &str,
+        pointer: &str,
+        values: Vec<JsonValue>,
+    ) -> io::Result<Result<usize, ValueError>> {
+        let operation = WalOperation::JsonArrAppend {
+            key: key.to_string(),
+            path: pointer.to_string(),
+            values: values.clone(),
+        };
+        self.write_document(database, key, operation, |slot| {
+            let document = slot.as_mut().ok_or(ValueError::NotFound)?;
+            document::array_append(document, pointer, values)
+        })
+        .await
+    }
+
+    /// *json_number_incr_by* adds `amount` to a number of a JSON document and returns the result
+    pub async fn json_number_incr_by(
+        &self,
+        database: &str,
+        key: &str,
+        pointer: &str,
+        amount: f64,
+    ) -> io::Result<Result<JsonValue, ValueError>> {
+        let operation = WalOperation::JsonNumIncrBy {
+            key: key.to_string(),
+            path: pointer.to_string(),
+            amount,
+        };
+        self.write_document(database, key, operation, |slot| {
+            let document = slot.as_mut().ok_or(ValueError::NotFound)?;
+            document::number_incr_by(document, pointer, amount)
+        })
+        .await
+    }
+
+    /// *json_merge* applies an RFC 7386 merge patch to a JSON document
+    pub async fn json_merge(
+        &self,
+        database: &str,
+        key: &str,
+        patch: JsonValue,
+    ) -> io::Result<Result<(), ValueError>> {
+        let operation = WalOperation::JsonMerge {
+            key: key.to_string(),
+            patch: patch.clone(),
+        };
+        self.write_document(database, key, operation, |slot| {
+            let document = slot.as_mut().ok_or(ValueError::NotFound)?;
+            document::merge(document, &patch);
+            Ok(())
+        })
+        .await
+    }
+
+    /// *json_patch* applies an RFC 6902 patch to a JSON document, entirely or not at all
+    pub async fn json_patch(
+        &self,
+        database: &str,
+        key: &str,
+        operations: Vec<PatchOperation>,
+    ) -> io::Result<Result<(), ValueError>> {
+        let operation = WalOperation::JsonPatch {
+            key: key.to_string(),
+            operations: operations.clone(),
+        };
+        self.write_document(database, key, operation, |slot| {
+            let document = slot.as_mut().ok_or(ValueError::NotFound)?;
+            document::patch(document, &operations)
+        })
+        .await
+    }
+
+    /// *write_document* logs the operation and applies `apply` to the JSON document held by the
+    /// key while holding the cache lock, `apply` gets `None` for a missing key and leaves `None`
+    /// to delete it. A failing `apply`, or one leaving a document that breaks the schema of the
+    /// key, leaves the document as it was.
+    async fn write_document<T>(
+        &self,
+        database: &str,
+        key: &str,
+        operation: WalOperation,
+        apply: impl FnOnce(&mut Option<JsonValue>) -> Result<T, ValueError>,
+    ) -> io::Result<Result<T, ValueError>> {
+        let schema = self.schemas.schema_for(database, key);
+        let cache = self.get_cache(database).await;
+        let mut cache_lock = cache.write().await;
+        self.persistence.log_operation(database, operation).await?;
+
+        let (result, kind) = cache_lock
+            .modify_key_value(database, key, self.default_ttl(), |slot| {
+                let mut document = match slot.take() {
+                    Some(DataValue::Json(document)) => Some(document),
+                    Some(other) => {
+                        *slot = Some(other);
+                        return (Err(ValueError::WrongType), None);
+                    }
+                    None => None,
+                };
+                let existed = document.is_some();
+                let original = schema.as_ref().map(|_| document.clone());
+                let mut result = apply(&mut document);
+                if let (Ok(_), Some(schema), Some(updated)) = (&result, &schema, &document) {
+                    if let Err(violations) = schema.validate(updated) {
+                        result = Err(ValueError::SchemaViolation(violations));
+                        document = original.flatten();
+                    }
+                }
+
+                let kind = match (result.is_ok(), existed, document.is_some()) {
+                    (false, _, _) | (true, false, false) => None,
+                    (true, false, true) => Some(KeyEventKind::Set),
+                    (true, true, true) => Some(KeyEventKind::Update),
+                    (true, true, false) => Some(KeyEventKind::Delete),
+                };
+                *slot = document.map(DataValue::Json);
+                (result, kind)
+            })
+            .await;
+        drop(cache_lock);
+
+        if let Some(kind) = kind {
+            self.publish_event(database, Some(key), kind);
+        }
+        Ok(result)
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    //////////////////////////////////////// VECTORS ///////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *vector_index* sets the dimension and metric of the index of the database. An index holding
+    /// vectors keeps its dimension and is rebuilt with the new metric.
+    pub async fn vector_index(
+        &self,
+        database: &str,
+        config: IndexConfig,
+    ) -> io::Result<Result<(), ValueError>> {
+        let cache = self.get_cache(database).await;
+        // the index is only changed under the cache lock, like the vectors it indexes
+        let _cache_lock = cache.write().await;
+        if let Some(index) = self.vector_indexes.get(database) {
+            if !index.is_empty() && index.config.dimension != config.dimension {
+                return Ok(Err(ValueError::DimensionMismatch));
+            }
+        }
+        self.persistence
+            .log_operation(database, WalOperation::VIndex { config })
+            .await?;
+
+        match self.vector_indexes.get_mut(database) {
+            Some(mut index) => index.rebuild(config),
+            None => {
+                self.vector_indexes
+                    .insert(database.to_string(), VectorIndex::new(config));
+            }
+        }
+        Ok(Ok(()))
+    }
+
+    /// *vector_index_info* is the configuration of the index of the database and the number of
+    /// vectors it holds
+    pub fn vector_index_info(&self, database: &str) -> Option<(IndexConfig, usize)> {
+        self.vector_indexes
+            .get(database)
+            .map(|index| (index.config, index.len()))
+    }
+
+    /// *vector_add* stores the vector under the key and indexes it, replacing the vector the key
+    /// held. The first vector of a database without an index creates one of its dimension with
+    /// the cosine metric.
+    pub async fn vector_add(
+        &self,
+        database: &str,
+        key: &str,
+        vector: Vector,
+    ) -> io::Result<Result<(), ValueError>> {
+        let cache = self.get_cache(database).await;
+        let mut cache_lock = cache.write().await;
+        let dimension = self
+            .vector_indexes
+            .get(database)
+            .map(|index| index.config.dimension);
+        if dimension.is_some_and(|dimension| dimension != vector.values.len()) {
+            return Ok(Err(ValueError::DimensionMismatch));
+        }
+        self.persistence
+            .log_operation(
+                database,
+                WalOperation::VAdd {
+                    key: key.to_string(),
+                    vector: vector.clone(),
+                },
+            )
+            .await?;
+
+        let values = vector.values.clone();
+        let (result, kind) = cache_lock
+            .modify_key_value(database, key, self.default_ttl(), |slot| {
+                let kind = match slot {
+                    Some(DataValue::Vector(_)) => KeyEventKind::Update,
+                    Some(_) => return (Err(ValueError::WrongType), None),
+                    None => KeyEventKind::Set,
+                };
+                *slot = Some(DataValue::Vector(vector));
+                (Ok(()), Some(kind))
+            })
+            .await;
+        if result.is_ok() {
+            self.vector_indexes
+                .entry(database.to_string())
+                .or_insert_with(|| {
+                    VectorIndex::new(IndexConfig {
+                        dimension: values.len(),
+                        metric: Metric::Cosine,
+                    })
+                })
+                .insert(key, values);
+        }
+        drop(cache_lock);
+
+        if let Some(kind) = kind {
+            self.publish_event(database, Some(key), kind);
+        }
+        Ok(result)
+    }
+
+    /// *vector_delete* removes the vector of the key and returns whether there was one
+    pub async fn vector_delete(
+        &self,
+        database: &str,
+        key: &str,
+    ) -> io::Result<Result<bool, ValueError>> {
+        let cache = self.get_cache(database).await;
+        let mut cache_lock = cache.write().await;
+        self.persistence
+            .log_operation(
+                database,
+                WalOperation::VDel {
+                    key: key.to_string(),
+                },
+            )
+            .await?;
+
+        let result = cache_lock
+            .modify_key_value(database, key, self.default_ttl(), |slot| match slot {
+                Some(DataValue::Vector(_)) => {
+                    *slot = None;
+                    Ok(true)
+                }
+                Some(_) => Err(ValueError::WrongType),
+                None => Ok(false),
+            })
+            .await;
+        if let Some(mut index) = self.vector_indexes.get_mut(database) {
+            index.remove(key);
+        }
+        drop(cache_lock);
+
+        if result == Ok(true) {
+            self.publish_event(database, Some(key), KeyEventKind::Delete);
+        }
+        Ok(result)
+    }
+
+    /// *vector_search* finds the `count` vectors nearest to `query` whose metadata matches every
+    /// filter, nearest first. Vectors of keys the ACL hides, and vectors that expired or were
+    /// overwritten since they were indexed, are skipped.
+    pub async fn vector_search(
+        &self,
+        database: &str,
+        query: &[f32],
+        count: usize,
+        filters: &[FilterCondition],
+        acl: &KeyAcl,
+    ) -> Result<Vec<VectorMatch>, ValueError> {
+        let cache = self.get_cache(database).await;
+        let cache_lock = cache.read().await;
+
+        // the search is widened until enough vectors pass the filters or the index is exhausted
+        let mut wanted = count;
+        loop {
+            let (candidates, indexed) = match self.vector_indexes.get(database) {
+                Some(index) if index.config.dimension != query.len() => {
+                    return Err(ValueError::DimensionMismatch)
+                }
+                Some(index) => {
+                    let candidates: Vec<(String, Vec<f32>, f32)> = index
+                        .search(query, wanted)
+                        .into_iter()
+                        .map(|(key, values, distance)| (key, values.to_vec(), distance))
+                        .collect();
+                    (candidates, index.len())
+                }
+                None => return Ok(Vec::new()),
+            };
+
+            let mut matches = Vec::new();
+            for (key, values, distance) in candidates {
+                if matches.len() == count || !acl.allows(&key) {
+                    continue;
+                }
+                let Some(DataValue::Vector(vector)) =
+                    cache_lock.get_key_value(database, &key).await
+                else {
+                    continue;
+                };
+                if vector.values == values
+                    && filters
+                        .iter()
+                        .all(|filter| apply_filter(&vector.metadata, filter))
+                {
+                    matches.push(VectorMatch {
+                        key,
+                        distance,
+                        metadata: vector.metadata,
+                    });
+                }
+            }
+            if matches.len() == count || wanted >= indexed {
+                return Ok(matches);
+            }
+            wanted = wanted.saturating_mul(4);
+        }
+    }
+
+    ////////////////////////////////////////////////////////////////////////////////////////////
+    ////////////////////////////////////// COLLECTIONS /////////////////////////////////////////
+    ////////////////////////////////////////////////////////////////////////////////////////////
+
+    /// *read_collection* runs `read` on the collection held by the key without copying it, a
+    /// missing key reads as an empty one
+    async fn read_collection<C: Collection, T>(
+        &self,
+        database: &str,
+        key: &str,
+        read: impl FnOnce(&C) -> T,
+    ) -> Result<T, ValueError> {
+        let cache = self.get_cache(database).await;
+        let cache_lock = cache.read().await;
+        cache_lock
+            .read_key_value(database, key, |value| match value {
+                Some(value) => C::from_ref(value).map(read).ok_or(ValueError::WrongType),
+                None => Ok(read(&C::default())),
+            })
+            .await
+    }
+
+    /// *write_collection* logs the operation and applies `apply` to the collection while
+    /// holding the cache lock, so the WAL holds the writes in the order they were applied
+    async fn write_collection<C: Collection, T>(
+        &self,
+        database: &str,
+        key: &str,
+        operation: WalOperation,
+        apply: impl FnOnce(&mut C) -> Result<T, ValueError>,
+    ) -> io::Result<Result<T, ValueError>> {
+        let cache = self.get_cache(database).await;
+        let mut cache_lock = cache.write().await;
+        self.persistence.log_operation(database, operation).await?;
+        let (result, kind) = self
+            .apply_collection(&mut cache_lock, database, key, apply)
+            .await;
+        drop(cache_lock);
+
+        if let Some(kind) = kind {
+            self.publish_event(database, Some(key), kind);
+        }
+        Ok(result)
+    }
+
+    /// *apply_collection* applies `apply` to the collection held by the key and tells which
+    /// event the write makes. A missing key starts as an empty collection and a collection left
+    /// removable is removed. Durable collections are created without the default TTL.
+    async fn apply_collection<C: Collection, T>(
+        &self,
+        cache: &mut Cache,
+        database: &str,
+        key: &str,
+        apply: impl FnOnce(&mut C) -> Result<T, ValueError>,
+    ) -> (Result<T, ValueError>, Option<KeyEventKind>) {
+        let ttl = if C::durable() {
+            None
+        } else {
+            self.default_ttl()
+        };
+        cache
+            .modify_key_value(database, key, ttl, |slot| {
+                let mut collection = match slot.take().map(C::from_value) {
+                    Some(Ok(collection)) => collection,
+                    Some(Err(other)) => {
+                        *slot = Some(other);
+                        return (Err(ValueError::WrongType), None);
+                    }
+                    None => C::default(),
+                };
+                let existed = !collection.removable();
+                let result = apply(&mut collection);
+
+                let kind = match (result.is_ok(), existed, collection.removable()) {
+                    (false, _, _) | (_, false, true) => None,
+                    (true, true, true) => Some(KeyEventKind::Delete),
+                    (true, _, false) => Some(KeyEventKind::Update),
+                };
+                if !collection.removable() {
+                    *slot = Some(collection.into_value());
+                }
+                (result, kind)
+            })
+            .await
+    }
+
+    /// *check_schema* rejects a JSON value that does not conform to the schema of its key
+    fn check_schema(&self, database: &str, key: &str, value: &DataValue) -> io::Result<()> {
+        match value {
+            DataValue::Json(document) => self
+                .schemas
+                .validate(database, key, document)
+                .map_err(|violations| io::Error::new(io::ErrorKind::InvalidData, violations)),
+            _ => Ok(()),
+        }
+    }
+
+    /// *default_ttl* is the expiry of keys created without one
+    fn default_ttl(&self) -> Option<Duration> {
+        if self.config.default_ttl_secs > 0 {
+            Some(Duration::from_secs(self.config.default_ttl_secs))
+        } else {
+            compute_expiry()
+        }
+    }
+
+    /// *query* runs aggregation operations over the JSON values of a database, like the
+    /// `QUERY` command does
+    pub async fn query(&self, database: &str, operations: Vec<AggregationOperation>) -> JsonValue {
+        aggregate(database, operations, &KeyAcl::default(), self).await
+    }
+
+    // Returns a Json representation of all data in the database
+    // Includes key, value, and expiry information
+    pub async fn view_data(&self, database: &str) -> JsonValue {
+        let cache = self.get_cache(database).await;
+        let cache_lock = cache.read().await;
+        let mut data_map = serde_json::Map::new();
+        for shard in cache_lock.shards.iter() {
+            let shard_lock = shard.read().await;
+            for (cache_key, item) in shard_lock.iter() {
+                if cache_key.database != database
+                    || cache_key.entry_type != CacheEntryType::KeyValue
+                {
+                    continue;
+                }
+                let CacheValue::KeyValue(value, expiry) = &item.value;
+                let value_data = match value {
+                    DataValue::String(s) => {
+                        json!({"type": "String", "value": s, "expiry": expiry, "created_at": item.created_at})
+                    }
+                    DataValue::List(l) => json!({"type": "List", "value": l, "expiry": expiry}),
+                    DataValue::Set(s) => {
+                        json!({"type": "Set", "value": s.keys().collect::<Vec<_>>(), "expiry": expiry})
+                    }
+                    DataValue::Json(j) => {
+                        json!({"type": "Json", "value": j, "expiry": expiry, "created_at": item.created_at})
+                    }
+                    DataValue::Hash(h) => json!({"type": "Hash", "value": h, "expiry": expiry}),
+                    DataValue::SortedSet(z) => {
+                        json!({"type": "SortedSet", "value": z, "expiry": expiry})
+                    }
+                    DataValue::Vector(v) => json!({"type": "Vector", "value": v, "expiry": expiry}),
+                    DataValue::Stream(x) => json!({"type": "Stream", "value": x, "expiry": expiry}),
+                    DataValue::Queue(q) => json!({"type": "Queue", "value": q, "expiry": expiry}),
+                };
+                data_map.insert(cache_key.key.clone(), value_data);
+            }
         }
         json!(data_map)
     }
 }
+
+/// *block_deadline* is when a read blocking for `block` gives up, `None` for a zero `block`
+/// waiting as long as it takes
+fn block_deadline(block: Option<Duration>) -> Option<Instant> {
+    block
+        .filter(|block| !block.is_zero())
+        .and_then(|block| Instant::now().checked_add(block))
+}
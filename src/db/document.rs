@@ -0,0 +1,374 @@
+/// document.rs holds the partial updates of `DataValue::Json` documents.
+/// Paths are JSON Pointers (RFC 6901) like `/items/0/price`, or simple JSONPaths like
+/// `$.items[0].price`, which are turned into pointers before anything is logged.
+/// `merge` applies RFC 7386 merge patches and `patch` RFC 6902 patches. Every update either
+/// applies entirely or leaves the document as it was.
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value as JsonValue};
+
+use super::db::ValueError;
+
+/// A single operation of an RFC 6902 patch
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOperation {
+    Add { path: String, value: JsonValue },
+    Remove { path: String },
+    Replace { path: String, value: JsonValue },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: JsonValue },
+}
+
+impl PatchOperation {
+    /// *is_valid* tells if every path of the operation is a JSON Pointer
+    pub fn is_valid(&self) -> bool {
+        match self {
+            PatchOperation::Add { path, .. }
+            | PatchOperation::Remove { path }
+            | PatchOperation::Replace { path, .. }
+            | PatchOperation::Test { path, .. } => tokens(path).is_some(),
+            PatchOperation::Move { from, path } | PatchOperation::Copy { from, path } => {
+                tokens(from).is_some() && tokens(path).is_some()
+            }
+        }
+    }
+}
+
+/// *parse_path* turns a JSON Pointer or a JSONPath made of `.field`, `['field']` and `[index]`
+/// steps into a JSON Pointer, `None` when it is neither
+pub fn parse_path(path: &str) -> Option<String> {
+    let Some(mut rest) = path.strip_prefix('$') else {
+        return tokens(path).map(|_| path.to_string());
+    };
+
+    let mut pointer = String::new();
+    while !rest.is_empty() {
+        let (step, next) = if let Some(field) = rest.strip_prefix('.') {
+            let end = field.find(['.', '[']).unwrap_or(field.len());
+            (&field[..end], &field[end..])
+        } else if let Some(quoted) = rest.strip_prefix("['").or_else(|| rest.strip_prefix("[\"")) {
+            let end = quoted.find(['\'', '"'])?;
+            (&quoted[..end], quoted[end + 1..].strip_prefix(']')?)
+        } else {
+            let index = rest.strip_prefix('[')?;
+            let end = index.find(']')?;
+            index[..end].parse::<usize>().ok()?;
+            (&index[..end], &index[end + 1..])
+        };
+        if step.is_empty() {
+            return None;
+        }
+        pointer.push('/');
+        pointer.push_str(&step.replace('~', "~0").replace('/', "~1"));
+        rest = next;
+    }
+    Some(pointer)
+}
+
+/// *tokens* splits a JSON Pointer into its unescaped reference tokens
+fn tokens(pointer: &str) -> Option<Vec<String>> {
+    if pointer.is_empty() {
+        return Some(Vec::new());
+    }
+    pointer
+        .strip_prefix('/')?
+        .split('/')
+        .map(|token| {
+            let mut unescaped = String::with_capacity(token.len());
+            let mut chars = token.chars();
+            while let Some(c) = chars.next() {
+                if c != '~' {
+                    unescaped.push(c);
+                    continue;
+                }
+                match chars.next()? {
+                    '0' => unescaped.push('~'),
+                    '1' => unescaped.push('/'),
+                    _ => return None,
+                }
+            }
+            Some(unescaped)
+        })
+        .collect()
+}
+
+/// *parent_mut* resolves the parent of the value the pointer names and the last token
+fn parent_mut<'a>(
+    document: &'a mut JsonValue,
+    pointer: &str,
+) -> Result<(&'a mut JsonValue, String), ValueError> {
+    let mut tokens = tokens(pointer).ok_or(ValueError::PathNotFound)?;
+    let last = tokens.pop().ok_or(ValueError::PathNotFound)?;
+    let mut parent = document;
+    for token in tokens {
+        parent = match parent {
+            JsonValue::Object(map) => map.get_mut(&token),
+            JsonValue::Array(values) => token
+                .parse::<usize>()
+                .ok()
+                .and_then(|index| values.get_mut(index)),
+            _ => None,
+        }
+        .ok_or(ValueError::PathNotFound)?;
+    }
+    Ok((parent, last))
+}
+
+fn get_mut<'a>(
+    document: &'a mut JsonValue,
+    pointer: &str,
+) -> Result<&'a mut JsonValue, ValueError> {
+    if pointer.is_empty() {
+        return Ok(document);
+    }
+    match parent_mut(document, pointer)? {
+        (JsonValue::Object(map), last) => map.get_mut(&last),
+        (JsonValue::Array(values), last) => last
+            .parse::<usize>()
+            .ok()
+            .and_then(|index| values.get_mut(index)),
+        _ => None,
+    }
+    .ok_or(ValueError::PathNotFound)
+}
+
+/// *set* writes the value at the pointer, adding an object member or replacing an array element.
+/// `-` or the length of an array appends to it.
+pub fn set(document: &mut JsonValue, pointer: &str, value: JsonValue) -> Result<(), ValueError> {
+    if pointer.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+    match parent_mut(document, pointer)? {
+        (JsonValue::Object(map), last) => {
+            map.insert(last, value);
+            Ok(())
+        }
+        (JsonValue::Array(values), last) => match array_index(&last, values.len())? {
+            index if index == values.len() => {
+                values.push(value);
+                Ok(())
+            }
+            index => {
+                values[index] = value;
+                Ok(())
+            }
+        },
+        _ => Err(ValueError::WrongType),
+    }
+}
+
+/// *remove* removes the value at the pointer and returns it, the whole document cannot be removed
+pub fn remove(document: &mut JsonValue, pointer: &str) -> Result<JsonValue, ValueError> {
+    match parent_mut(document, pointer)? {
+        (JsonValue::Object(map), last) => map.remove(&last).ok_or(ValueError::PathNotFound),
+        (JsonValue::Array(values), last) => match last.parse::<usize>() {
+            Ok(index) if index < values.len() => Ok(values.remove(index)),
+            _ => Err(ValueError::PathNotFound),
+        },
+        _ => Err(ValueError::PathNotFound),
+    }
+}
+
+/// *array_append* appends the values to the array at the pointer and returns its new length
+pub fn array_append(
+    document: &mut JsonValue,
+    pointer: &str,
+    values: Vec<JsonValue>,
+) -> Result<usize, ValueError> {
+    match get_mut(document, pointer)? {
+        JsonValue::Array(array) => {
+            array.extend(values);
+            Ok(array.len())
+        }
+        _ => Err(ValueError::WrongType),
+    }
+}
+
+/// *number_incr_by* adds `amount` to the number at the pointer and returns the new number.
+/// Integers stay integers as long as the amount is one and the sum fits.
+pub fn number_incr_by(
+    document: &mut JsonValue,
+    pointer: &str,
+    amount: f64,
+) -> Result<JsonValue, ValueError> {
+    let target = get_mut(document, pointer)?;
+    let JsonValue::Number(number) = target else {
+        return Err(ValueError::NotANumber);
+    };
+
+    let integer = match (number.as_i64(), amount.fract() == 0.0) {
+        (Some(current), true) if amount.abs() < i64::MAX as f64 => {
+            current.checked_add(amount as i64)
+        }
+        _ => None,
+    };
+    let result = match integer {
+        Some(sum) => JsonValue::from(sum),
+        None => {
+            let sum = number.as_f64().unwrap_or_default() + amount;
+            if !sum.is_finite() {
+                return Err(ValueError::NotANumber);
+            }
+            JsonValue::from(sum)
+        }
+    };
+    *target = result.clone();
+    Ok(result)
+}
+
+/// *merge* applies an RFC 7386 merge patch, `null` members remove what they name
+pub fn merge(document: &mut JsonValue, patch: &JsonValue) {
+    let JsonValue::Object(patch) = patch else {
+        *document = patch.clone();
+        return;
+    };
+    if !document.is_object() {
+        *document = JsonValue::Object(Map::new());
+    }
+    if let JsonValue::Object(map) = document {
+        for (key, value) in patch {
+            if value.is_null() {
+                map.remove(key);
+            } else {
+                merge(map.entry(key.as_str()).or_insert(JsonValue::Null), value);
+            }
+        }
+    }
+}
+
+/// *patch* applies an RFC 6902 patch to a copy of the document, which replaces it only once
+/// every operation succeeded
+pub fn patch(document: &mut JsonValue, operations: &[PatchOperation]) -> Result<(), ValueError> {
+    let mut patched = document.clone();
+    for operation in operations {
+        match operation {
+            PatchOperation::Add { path, value } => add(&mut patched, path, value.clone())?,
+            PatchOperation::Remove { path } => {
+                remove(&mut patched, path)?;
+            }
+            PatchOperation::Replace { path, value } => {
+                *get_mut(&mut patched, path)? = value.clone();
+            }
+            PatchOperation::Move { from, path } => {
+                // a value cannot be moved into one of its own children
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(ValueError::PathNotFound);
+                }
+                if from != path {
+                    let value = remove(&mut patched, from)?;
+                    add(&mut patched, path, value)?;
+                }
+            }
+            PatchOperation::Copy { from, path } => {
+                let value = get_mut(&mut patched, from)?.clone();
+                add(&mut patched, path, value)?;
+            }
+            PatchOperation::Test { path, value } => {
+                if get_mut(&mut patched, path)? != value {
+                    return Err(ValueError::TestFailed);
+                }
+            }
+        }
+    }
+    *document = patched;
+    Ok(())
+}
+
+/// *add* is the RFC 6902 `add`, which inserts into arrays where `set` replaces
+fn add(document: &mut JsonValue, pointer: &str, value: JsonValue) -> Result<(), ValueError> {
+    if pointer.is_empty() {
+        *document = value;
+        return Ok(());
+    }
+    match parent_mut(document, pointer)? {
+        (JsonValue::Object(map), last) => {
+            map.insert(last, value);
+            Ok(())
+        }
+        (JsonValue::Array(values), last) => {
+            let index = array_index(&last, values.len())?;
+            values.insert(index, value);
+            Ok(())
+        }
+        _ => Err(ValueError::PathNotFound),
+    }
+}
+
+/// *array_index* resolves an array index that may point right after the last element
+fn array_index(token: &str, len: usize) -> Result<usize, ValueError> {
+    match token {
+        "-" => Ok(len),
+        _ => match token.parse::<usize>() {
+            Ok(index) if index <= len => Ok(index),
+            _ => Err(ValueError::OutOfRange),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_partial_updates() {
+        assert_eq!(
+            parse_path("$.items[1]['a/b']"),
+            Some("/items/1/a~1b".into())
+        );
+        assert_eq!(parse_path("$"), Some(String::new()));
+        assert_eq!(parse_path("/items/~2"), None);
+        assert_eq!(parse_path("items"), None);
+
+        let mut order = json!({"items": [{"price": 2}], "note": "gift"});
+        set(&mut order, "/items/0/price", json!(3)).unwrap();
+        set(&mut order, "/items/-", json!({"price": 1.5})).unwrap();
+        assert_eq!(
+            set(&mut order, "/missing/x", json!(1)),
+            Err(ValueError::PathNotFound)
+        );
+        assert_eq!(array_append(&mut order, "/items", vec![json!(null)]), Ok(3));
+        assert_eq!(remove(&mut order, "/items/2"), Ok(json!(null)));
+        assert_eq!(
+            number_incr_by(&mut order, "/items/0/price", 2.0),
+            Ok(json!(5))
+        );
+        assert_eq!(
+            number_incr_by(&mut order, "/items/1/price", 1.0),
+            Ok(json!(2.5))
+        );
+        assert_eq!(
+            number_incr_by(&mut order, "/note", 1.0),
+            Err(ValueError::NotANumber)
+        );
+
+        merge(&mut order, &json!({"note": null, "paid": {"by": "card"}}));
+        assert_eq!(
+            order,
+            json!({"items": [{"price": 5}, {"price": 2.5}], "paid": {"by": "card"}})
+        );
+
+        // a failing operation leaves the document untouched
+        let operations: Vec<PatchOperation> = serde_json::from_value(json!([
+            {"op": "move", "from": "/paid", "path": "/payment"},
+            {"op": "test", "path": "/payment/by", "value": "cash"}
+        ]))
+        .unwrap();
+        assert_eq!(patch(&mut order, &operations), Err(ValueError::TestFailed));
+        assert!(order.get("paid").is_some());
+
+        let operations: Vec<PatchOperation> = serde_json::from_value(json!([
+            {"op": "copy", "from": "/items/0", "path": "/items/0"},
+            {"op": "replace", "path": "/items/0/price", "value": 7},
+            {"op": "remove", "path": "/paid"}
+        ]))
+        .unwrap();
+        patch(&mut order, &operations).unwrap();
+        assert_eq!(
+            order,
+            json!({"items": [{"price": 7}, {"price": 5}, {"price": 2.5}]})
+        );
+    }
+}
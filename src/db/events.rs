@@ -0,0 +1,27 @@
+use serde::{Deserialize, Serialize};
+
+/// Number of key events buffered for slow subscribers before they start lagging
+pub const EVENT_CHANNEL_CAPACITY: usize = 1024;
+
+/// The kind of change a write operation made to a key
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyEventKind {
+    Set,
+    Update,
+    Delete,
+    Increment,
+    DropDb,
+}
+
+/// A change notification emitted after every successful write.
+///
+/// Events are broadcast on `TinyCache::events`; listeners such as the WebSocket endpoint
+/// subscribe to it and filter on the database they are interested in.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyEvent {
+    pub database: String,    // database context the change happened in
+    pub key: Option<String>, // affected key, `None` for database wide events
+    pub kind: KeyEventKind,
+    pub timestamp: u64,
+}
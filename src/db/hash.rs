@@ -0,0 +1,85 @@
+/// hash.rs holds the hash commands on `DataValue::Hash`, a flat map of fields to string values.
+/// Fields are written one by one, and logged to the WAL as the field writes themselves, so a
+/// small update of a large hash never rewrites the whole value.
+use std::collections::HashMap;
+
+use super::db::ValueError;
+
+/// The fields of a hash, as `DataValue::Hash` holds them
+pub type Fields = HashMap<String, String>;
+
+/// *set* writes the fields and returns how many were not in the hash yet
+pub fn set(hash: &mut Fields, fields: Vec<(String, String)>) -> usize {
+    fields
+        .into_iter()
+        .map(|(field, value)| hash.insert(field, value).is_none() as usize)
+        .sum()
+}
+
+/// *delete* removes the fields and returns how many were in the hash
+pub fn delete(hash: &mut Fields, fields: &[String]) -> usize {
+    fields
+        .iter()
+        .filter(|field| hash.remove(*field).is_some())
+        .count()
+}
+
+/// *incr_by_float* adds `amount` to the field, a missing field counting as `0`, and returns the
+/// new value. Fails with `NotANumber` when the field or the result is not a finite number.
+pub fn incr_by_float(hash: &mut Fields, field: &str, amount: f64) -> Result<f64, ValueError> {
+    let current = match hash.get(field) {
+        Some(value) => value.parse::<f64>().map_err(|_| ValueError::NotANumber)?,
+        None => 0.0,
+    };
+    let value = current + amount;
+    if !value.is_finite() {
+        return Err(ValueError::NotANumber);
+    }
+    hash.insert(field.to_string(), value.to_string());
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(pairs: &[(&str, &str)]) -> Fields {
+        pairs
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_set_and_delete() {
+        let mut profile = Fields::new();
+        let pairs = vec![
+            ("name".to_string(), "ada".to_string()),
+            ("visits".to_string(), "1".to_string()),
+        ];
+        assert_eq!(set(&mut profile, pairs), 2);
+        assert_eq!(set(&mut profile, vec![("name".into(), "grace".into())]), 0);
+        assert_eq!(profile["name"], "grace");
+        assert_eq!(delete(&mut profile, &["name".into(), "missing".into()]), 1);
+        assert_eq!(profile, fields(&[("visits", "1")]));
+    }
+
+    #[test]
+    fn test_incr_by_float() {
+        let mut profile = fields(&[("name", "ada"), ("visits", "1")]);
+        assert_eq!(incr_by_float(&mut profile, "visits", 1.5), Ok(2.5));
+        assert_eq!(incr_by_float(&mut profile, "score", -2.0), Ok(-2.0));
+        assert_eq!(profile["visits"], "2.5");
+
+        // fields that are not numbers, or would stop being finite, are left as they are
+        assert_eq!(
+            incr_by_float(&mut profile, "name", 1.0),
+            Err(ValueError::NotANumber)
+        );
+        assert_eq!(
+            incr_by_float(&mut profile, "score", f64::INFINITY),
+            Err(ValueError::NotANumber)
+        );
+        assert_eq!(profile["score"], "-2");
+    }
+}
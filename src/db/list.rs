@@ -0,0 +1,175 @@
+/// list.rs holds the list commands on `DataValue::List`.
+/// Lists are `VecDeque`s, so pushes and pops at either end are O(1). Indexes may be negative to
+/// count from the tail, `-1` being the last element, and ranges include both ends.
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use super::db::ValueError;
+
+/// The end of a list a push or pop works on
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ListEnd {
+    Head,
+    Tail,
+}
+
+/// *push* adds the values one after the other, so `LPUSH a b` leaves `b` first, and returns
+/// the new length
+pub fn push(list: &mut VecDeque<String>, values: Vec<String>, end: ListEnd) -> usize {
+    for value in values {
+        match end {
+            ListEnd::Head => list.push_front(value),
+            ListEnd::Tail => list.push_back(value),
+        }
+    }
+    list.len()
+}
+
+/// *pop* removes up to `count` values from the end, in the order they were removed
+pub fn pop(list: &mut VecDeque<String>, count: usize, end: ListEnd) -> Vec<String> {
+    let count = count.min(list.len());
+    match end {
+        ListEnd::Head => list.drain(..count).collect(),
+        ListEnd::Tail => (0..count).filter_map(|_| list.pop_back()).collect(),
+    }
+}
+
+pub fn range(list: &VecDeque<String>, start: i64, stop: i64) -> Vec<String> {
+    match bounds(start, stop, list.len()) {
+        Some((start, stop)) => list.range(start..=stop).cloned().collect(),
+        None => Vec::new(),
+    }
+}
+
+pub fn index(list: &VecDeque<String>, index: i64) -> Option<String> {
+    position(index, list.len()).map(|index| list[index].clone())
+}
+
+pub fn set(list: &mut VecDeque<String>, index: i64, value: String) -> Result<(), ValueError> {
+    if list.is_empty() {
+        return Err(ValueError::NotFound);
+    }
+    let index = position(index, list.len()).ok_or(ValueError::OutOfRange)?;
+    list[index] = value;
+    Ok(())
+}
+
+/// *remove* drops up to `count` elements equal to `value`, from the head for a positive count,
+/// from the tail for a negative one, all of them for `0`. Returns how many were dropped.
+pub fn remove(list: &mut VecDeque<String>, count: i64, value: &str) -> usize {
+    let limit = match count {
+        0 => usize::MAX,
+        count => count.unsigned_abs() as usize,
+    };
+
+    let mut removed = 0;
+    if count >= 0 {
+        list.retain(|element| {
+            let drop = removed < limit && element == value;
+            removed += drop as usize;
+            !drop
+        });
+    } else {
+        let mut index = list.len();
+        while index > 0 && removed < limit {
+            index -= 1;
+            if list[index] == value {
+                list.remove(index);
+                removed += 1;
+            }
+        }
+    }
+    removed
+}
+
+/// *trim* keeps only the elements between `start` and `stop`
+pub fn trim(list: &mut VecDeque<String>, start: i64, stop: i64) {
+    match bounds(start, stop, list.len()) {
+        Some((start, stop)) => {
+            list.truncate(stop + 1);
+            list.drain(..start);
+        }
+        None => list.clear(),
+    }
+}
+
+/// *insert* puts `value` before or after the first `pivot`, returns the new length or `None`
+/// when there is no such pivot
+pub fn insert(
+    list: &mut VecDeque<String>,
+    before: bool,
+    pivot: &str,
+    value: String,
+) -> Option<usize> {
+    let index = list.iter().position(|element| element == pivot)?;
+    list.insert(if before { index } else { index + 1 }, value);
+    Some(list.len())
+}
+
+/// *position* resolves a possibly negative index, `None` when it lies outside the list
+fn position(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+/// *bounds* resolves an inclusive range clamped to a sequence of `len` elements, `None` when it
+/// is empty
+pub(super) fn bounds(start: i64, stop: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let resolve = |index: i64| if index < 0 { len + index } else { index };
+    let start = resolve(start).max(0);
+    let stop = resolve(stop).min(len - 1);
+    (start <= stop).then_some((start as usize, stop as usize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn list(values: &[&str]) -> VecDeque<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_push_and_pop() {
+        let mut jobs = VecDeque::new();
+        assert_eq!(
+            push(&mut jobs, vec!["b".into(), "a".into()], ListEnd::Head),
+            2
+        );
+        assert_eq!(
+            push(&mut jobs, vec!["c".into(), "a".into()], ListEnd::Tail),
+            4
+        );
+        assert_eq!(jobs, list(&["a", "b", "c", "a"]));
+        assert_eq!(pop(&mut jobs, 1, ListEnd::Head), vec!["a"]);
+        assert_eq!(pop(&mut jobs, 5, ListEnd::Tail), vec!["a", "c", "b"]);
+        assert!(jobs.is_empty());
+    }
+
+    #[test]
+    fn test_negative_indexes_and_ranges() {
+        let jobs = list(&["a", "b", "c", "a"]);
+        assert_eq!(range(&jobs, 1, -1), vec!["b", "c", "a"]);
+        assert_eq!(range(&jobs, -100, 100).len(), 4);
+        assert!(range(&jobs, 3, 1).is_empty());
+        assert!(range(&jobs, i64::MAX, i64::MIN).is_empty());
+        assert_eq!(index(&jobs, -1), Some("a".to_string()));
+        assert_eq!(index(&jobs, 4), None);
+    }
+
+    #[test]
+    fn test_updates_in_place() {
+        let mut jobs = list(&["a", "b", "c", "a"]);
+        assert_eq!(set(&mut jobs, 9, "x".into()), Err(ValueError::OutOfRange));
+        assert_eq!(insert(&mut jobs, true, "c", "x".into()), Some(5));
+        assert_eq!(insert(&mut jobs, false, "missing", "x".into()), None);
+        assert_eq!(remove(&mut jobs, -1, "a"), 1);
+        assert_eq!(jobs, list(&["a", "b", "x", "c"]));
+
+        trim(&mut jobs, 1, -2);
+        assert_eq!(jobs, list(&["b", "x"]));
+        trim(&mut jobs, 2, 1);
+        assert_eq!(set(&mut jobs, 0, "x".into()), Err(ValueError::NotFound));
+    }
+}
@@ -1,2 +1,14 @@
+pub mod builder;
 pub mod cache;
 pub mod db;
+pub mod document;
+pub mod events;
+pub mod hash;
+pub mod list;
+pub mod pubsub;
+pub mod queue;
+pub mod schema;
+pub mod set;
+pub mod sorted_set;
+pub mod stream;
+pub mod vector;
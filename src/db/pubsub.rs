@@ -0,0 +1,303 @@
+/// pubsub.rs holds the pub/sub channels of the databases. A message published on a channel is
+/// pushed to every connection subscribed to the channel, or to a glob pattern matching it, and is
+/// never stored. Channels are scoped to their database.
+/// Every subscriber buffers up to `pubsub_buffer_size` messages. When a slow subscriber's buffer
+/// is full, `pubsub_slow_consumer` decides whether further messages are dropped for it or whether
+/// it is disconnected.
+use dashmap::DashMap;
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tokio::sync::{mpsc, Notify};
+
+use crate::{
+    constants::constants::{PUBSUB_DISCONNECT, PUBSUB_DROP},
+    security::acl::glob_match,
+};
+
+/// What happens to a subscriber whose buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    Drop,       // the messages that do not fit are dropped, the subscriber is told how many
+    Disconnect, // the subscriber is disconnected
+}
+
+impl SlowConsumerPolicy {
+    pub fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            PUBSUB_DROP => Some(SlowConsumerPolicy::Drop),
+            PUBSUB_DISCONNECT => Some(SlowConsumerPolicy::Disconnect),
+            _ => None,
+        }
+    }
+}
+
+/// A message pushed to a subscriber, with the pattern it matched for pattern subscriptions
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Message {
+    pub channel: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pattern: Option<String>,
+    pub message: String,
+}
+
+/// What a subscriber receives next
+#[derive(Debug, PartialEq)]
+pub enum Delivery {
+    Message(Message),
+    Dropped(u64), // messages were dropped since the last delivery, the buffer was full
+    Disconnected, // the subscriber fell behind under `SlowConsumerPolicy::Disconnect`
+}
+
+/// The channels and patterns of a subscriber, by database
+#[derive(Default)]
+struct Subscriptions {
+    channels: BTreeSet<(String, String)>,
+    patterns: BTreeSet<(String, String)>,
+}
+
+struct SubscriberEntry {
+    sender: mpsc::Sender<Message>,
+    subscriptions: Mutex<Subscriptions>,
+    dropped: Arc<AtomicU64>,
+    overflow: Arc<Notify>,
+}
+
+/// The subscribers of every database
+pub struct PubSub {
+    next_id: AtomicU64,
+    subscribers: Arc<DashMap<u64, SubscriberEntry>>,
+    buffer_size: usize,
+    policy: SlowConsumerPolicy,
+}
+
+impl PubSub {
+    pub fn new(buffer_size: usize, policy: SlowConsumerPolicy) -> Self {
+        PubSub {
+            next_id: AtomicU64::new(1),
+            subscribers: Arc::new(DashMap::new()),
+            buffer_size: buffer_size.max(1),
+            policy,
+        }
+    }
+
+    /// *subscriber* registers a subscriber without subscriptions, it is removed once dropped
+    pub fn subscriber(&self) -> Subscriber {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = mpsc::channel(self.buffer_size);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let overflow = Arc::new(Notify::new());
+        self.subscribers.insert(
+            id,
+            SubscriberEntry {
+                sender,
+                subscriptions: Mutex::new(Subscriptions::default()),
+                dropped: dropped.clone(),
+                overflow: overflow.clone(),
+            },
+        );
+        Subscriber {
+            id,
+            receiver,
+            subscribers: self.subscribers.clone(),
+            dropped,
+            overflow,
+        }
+    }
+
+    /// *publish* pushes the message to the subscribers of the channel and returns how many
+    /// received it, a subscriber matching the channel more than once receives it once per match
+    pub fn publish(&self, database: &str, channel: &str, message: &str) -> usize {
+        let mut received = 0;
+        for entry in self.subscribers.iter() {
+            let matches: Vec<Option<String>> = {
+                let subscriptions = entry.subscriptions.lock().unwrap();
+                let channel_match = subscriptions
+                    .channels
+                    .contains(&(database.to_string(), channel.to_string()))
+                    .then_some(None);
+                let pattern_matches = subscriptions
+                    .patterns
+                    .iter()
+                    .filter(|(db, pattern)| db == database && glob_match(pattern, channel))
+                    .map(|(_, pattern)| Some(pattern.clone()));
+                channel_match.into_iter().chain(pattern_matches).collect()
+            };
+
+            for pattern in matches {
+                let message = Message {
+                    channel: channel.to_string(),
+                    pattern,
+                    message: message.to_string(),
+                };
+                match entry.sender.try_send(message) {
+                    Ok(()) => received += 1,
+                    Err(mpsc::error::TrySendError::Full(_)) => match self.policy {
+                        SlowConsumerPolicy::Drop => {
+                            entry.dropped.fetch_add(1, Ordering::Relaxed);
+                        }
+                        SlowConsumerPolicy::Disconnect => entry.overflow.notify_one(),
+                    },
+                    Err(mpsc::error::TrySendError::Closed(_)) => {}
+                }
+            }
+        }
+        received
+    }
+
+    /// *channels* lists the channels of the database with at least one subscriber
+    pub fn channels(&self, database: &str) -> BTreeSet<String> {
+        self.subscribers
+            .iter()
+            .flat_map(|entry| {
+                let subscriptions = entry.subscriptions.lock().unwrap();
+                subscriptions
+                    .channels
+                    .iter()
+                    .filter(|(db, _)| db == database)
+                    .map(|(_, channel)| channel.clone())
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    }
+}
+
+/// The subscriptions of a connection and the messages pushed to it
+pub struct Subscriber {
+    id: u64,
+    receiver: mpsc::Receiver<Message>,
+    subscribers: Arc<DashMap<u64, SubscriberEntry>>,
+    dropped: Arc<AtomicU64>,
+    overflow: Arc<Notify>,
+}
+
+impl Subscriber {
+    /// *subscribe* adds channels, or glob patterns with `pattern`, of the database and returns
+    /// the number of subscriptions held afterwards
+    pub fn subscribe(&self, database: &str, names: &[&str], pattern: bool) -> usize {
+        self.update(|subscriptions| {
+            let set = match pattern {
+                true => &mut subscriptions.patterns,
+                false => &mut subscriptions.channels,
+            };
+            set.extend(
+                names
+                    .iter()
+                    .map(|name| (database.to_string(), name.to_string())),
+            );
+        })
+    }
+
+    /// *unsubscribe* removes channels, or glob patterns with `pattern`, of the database, all of
+    /// them when `names` is empty, and returns the number of subscriptions held afterwards
+    pub fn unsubscribe(&self, database: &str, names: &[&str], pattern: bool) -> usize {
+        self.update(|subscriptions| {
+            let set = match pattern {
+                true => &mut subscriptions.patterns,
+                false => &mut subscriptions.channels,
+            };
+            if names.is_empty() {
+                set.retain(|(db, _)| db != database);
+            } else {
+                for name in names {
+                    set.remove(&(database.to_string(), name.to_string()));
+                }
+            }
+        })
+    }
+
+    /// *count* is the number of channels and patterns subscribed to
+    pub fn count(&self) -> usize {
+        self.update(|_| ())
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Subscriptions)) -> usize {
+        let Some(entry) = self.subscribers.get(&self.id) else {
+            return 0;
+        };
+        let mut subscriptions = entry.subscriptions.lock().unwrap();
+        change(&mut subscriptions);
+        subscriptions.channels.len() + subscriptions.patterns.len()
+    }
+
+    /// *next* waits for the next delivery, dropped messages are reported before the next message
+    pub async fn next(&mut self) -> Delivery {
+        let dropped = self.dropped.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            return Delivery::Dropped(dropped);
+        }
+        tokio::select! {
+            biased;
+            _ = self.overflow.notified() => Delivery::Disconnected,
+            message = self.receiver.recv() => match message {
+                Some(message) => Delivery::Message(message),
+                None => Delivery::Disconnected,
+            },
+        }
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.subscribers.remove(&self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(channel: &str, pattern: Option<&str>, message: &str) -> Delivery {
+        Delivery::Message(Message {
+            channel: channel.to_string(),
+            pattern: pattern.map(String::from),
+            message: message.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_pubsub() {
+        let pubsub = PubSub::new(2, SlowConsumerPolicy::Drop);
+        let mut subscriber = pubsub.subscriber();
+        assert_eq!(subscriber.subscribe("shop", &["orders", "carts"], false), 2);
+        assert_eq!(subscriber.subscribe("shop", &["invalidate:*"], true), 3);
+        assert_eq!(
+            pubsub.channels("shop").into_iter().collect::<Vec<_>>(),
+            vec!["carts", "orders"]
+        );
+
+        assert_eq!(pubsub.publish("shop", "orders", "o1"), 1);
+        assert_eq!(pubsub.publish("other", "orders", "o2"), 0);
+        assert_eq!(pubsub.publish("shop", "invalidate:user:1", "u1"), 1);
+        assert_eq!(subscriber.next().await, message("orders", None, "o1"));
+        assert_eq!(
+            subscriber.next().await,
+            message("invalidate:user:1", Some("invalidate:*"), "u1")
+        );
+
+        // a full buffer drops the messages that do not fit and reports them
+        for i in 0..5 {
+            pubsub.publish("shop", "carts", &i.to_string());
+        }
+        assert_eq!(subscriber.next().await, Delivery::Dropped(3));
+        assert_eq!(subscriber.next().await, message("carts", None, "0"));
+        assert_eq!(subscriber.next().await, message("carts", None, "1"));
+
+        assert_eq!(subscriber.unsubscribe("shop", &[], false), 1);
+        assert_eq!(pubsub.publish("shop", "orders", "o3"), 0);
+        drop(subscriber);
+        assert_eq!(pubsub.publish("shop", "invalidate:x", "x"), 0);
+
+        let pubsub = PubSub::new(1, SlowConsumerPolicy::Disconnect);
+        let mut subscriber = pubsub.subscriber();
+        subscriber.subscribe("shop", &["orders"], false);
+        pubsub.publish("shop", "orders", "o1");
+        pubsub.publish("shop", "orders", "o2");
+        assert_eq!(subscriber.next().await, Delivery::Disconnected);
+    }
+}
@@ -0,0 +1,321 @@
+/// queue.rs holds `DataValue::Queue`, a queue of messages delivered at least once. A popped
+/// message stays in the queue, invisible for a visibility timeout, until it is acknowledged with
+/// the receipt handle of its delivery. Once the timeout passes it is delivered again, and a
+/// message delivered `max_deliveries` times without being acknowledged moves to the dead letters
+/// of the queue on its next delivery.
+/// Messages are ordered by the time they become visible, then by ID, so a queue is FIFO for
+/// messages pushed without delay. Writes take the time they happen at, so replaying them from the
+/// WAL makes the same deliveries and receipts.
+/// A queue is only removed with its key, so its IDs, and with them its receipts, are never reused.
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use super::db::ValueError;
+
+/// A message of a queue
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QueueMessage {
+    pub id: u64,
+    pub message: String,
+    pub deliveries: u64,
+    visible_at: u64,         // milliseconds since the epoch it can be delivered from
+    receipt: Option<String>, // the receipt of the last delivery, until it is acknowledged or nacked
+}
+
+/// A message as `QPOP` delivers it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub receipt: String,
+    pub message: String,
+    pub deliveries: u64,
+}
+
+/// What `QSTATS` reports about a queue
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct QueueStats {
+    pub ready: usize,     // messages that can be delivered
+    pub delayed: usize,   // messages pushed or nacked with a delay that has not passed
+    pub in_flight: usize, // messages delivered and not acknowledged within their visibility timeout
+    pub dead: usize,      // messages delivered too often
+}
+
+/// A queue as snapshots hold it
+#[derive(Serialize, Deserialize)]
+struct QueueState {
+    messages: Vec<QueueMessage>,
+    dead: VecDeque<QueueMessage>,
+    next_id: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "QueueState", into = "QueueState")]
+pub struct Queue {
+    messages: BTreeMap<u64, QueueMessage>,
+    schedule: BTreeSet<(u64, u64)>, // when every message becomes visible, with its ID
+    dead: VecDeque<QueueMessage>,
+    next_id: u64,
+}
+
+impl From<QueueState> for Queue {
+    fn from(state: QueueState) -> Self {
+        let mut queue = Queue {
+            dead: state.dead,
+            next_id: state.next_id,
+            ..Self::default()
+        };
+        for message in state.messages {
+            queue.schedule.insert((message.visible_at, message.id));
+            queue.messages.insert(message.id, message);
+        }
+        queue
+    }
+}
+
+impl From<Queue> for QueueState {
+    fn from(queue: Queue) -> Self {
+        QueueState {
+            messages: queue.messages.into_values().collect(),
+            dead: queue.dead,
+            next_id: queue.next_id,
+        }
+    }
+}
+
+impl Queue {
+    /// *len* is the number of messages left to acknowledge, dead letters aside
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    /// *is_new* tells whether nothing was ever pushed, a queue that was is kept even without
+    /// messages so its IDs keep growing
+    pub fn is_new(&self) -> bool {
+        self.next_id == 0
+    }
+
+    /// *push* adds a message that becomes visible `delay` milliseconds after `now` and returns
+    /// its ID, failing when the queue already holds `max_size` messages, `0` for no limit
+    pub fn push(
+        &mut self,
+        message: String,
+        delay: u64,
+        now: u64,
+        max_size: usize,
+    ) -> Result<u64, ValueError> {
+        if max_size > 0 && self.messages.len() >= max_size {
+            return Err(ValueError::QueueFull);
+        }
+        self.next_id += 1;
+        let message = QueueMessage {
+            id: self.next_id,
+            message,
+            deliveries: 0,
+            visible_at: now.saturating_add(delay),
+            receipt: None,
+        };
+        self.schedule.insert((message.visible_at, message.id));
+        self.messages.insert(message.id, message);
+        Ok(self.next_id)
+    }
+
+    /// *has_ready* tells whether a message can be delivered at `now`
+    pub fn has_ready(&self, now: u64) -> bool {
+        self.schedule
+            .first()
+            .is_some_and(|(visible_at, _)| *visible_at <= now)
+    }
+
+    /// *pop* delivers the next visible message at `now`, hiding it for `visibility` milliseconds.
+    /// Messages already delivered `max_deliveries` times, `0` for no limit, are moved to the dead
+    /// letters instead.
+    pub fn pop(&mut self, now: u64, visibility: u64, max_deliveries: u64) -> Option<Delivery> {
+        loop {
+            let (visible_at, id) = *self.schedule.first()?;
+            if visible_at > now {
+                return None;
+            }
+            self.schedule.pop_first();
+            let mut message = self.messages.remove(&id)?;
+            if max_deliveries > 0 && message.deliveries >= max_deliveries {
+                message.receipt = None;
+                self.dead.push_back(message);
+                continue;
+            }
+
+            message.deliveries += 1;
+            message.visible_at = now.saturating_add(visibility);
+            let receipt = format!("{}-{}", message.id, message.deliveries);
+            message.receipt = Some(receipt.clone());
+            let delivery = Delivery {
+                id,
+                receipt,
+                message: message.message.clone(),
+                deliveries: message.deliveries,
+            };
+            self.schedule.insert((message.visible_at, id));
+            self.messages.insert(id, message);
+            return Some(delivery);
+        }
+    }
+
+    /// *ack* removes the message delivered with the receipt. A receipt stops being valid once
+    /// its message is delivered again or nacked.
+    pub fn ack(&mut self, receipt: &str) -> Result<(), ValueError> {
+        let message = self.delivered(receipt)?;
+        let (id, visible_at) = (message.id, message.visible_at);
+        self.schedule.remove(&(visible_at, id));
+        self.messages.remove(&id);
+        Ok(())
+    }
+
+    /// *nack* makes the message delivered with the receipt visible again `delay` milliseconds
+    /// after `now`
+    pub fn nack(&mut self, receipt: &str, delay: u64, now: u64) -> Result<(), ValueError> {
+        let message = self.delivered(receipt)?;
+        let (id, visible_at) = (message.id, message.visible_at);
+        self.schedule.remove(&(visible_at, id));
+        let message = self
+            .messages
+            .get_mut(&id)
+            .ok_or(ValueError::InvalidReceipt)?;
+        message.receipt = None;
+        message.visible_at = now.saturating_add(delay);
+        self.schedule.insert((message.visible_at, id));
+        Ok(())
+    }
+
+    /// *delivered* is the message whose last delivery has the receipt
+    fn delivered(&self, receipt: &str) -> Result<&QueueMessage, ValueError> {
+        let (id, _) = receipt.split_once('-').ok_or(ValueError::InvalidReceipt)?;
+        let id = id.parse::<u64>().map_err(|_| ValueError::InvalidReceipt)?;
+        self.messages
+            .get(&id)
+            .filter(|message| message.receipt.as_deref() == Some(receipt))
+            .ok_or(ValueError::InvalidReceipt)
+    }
+
+    /// *stats* counts the messages by state at `now`
+    pub fn stats(&self, now: u64) -> QueueStats {
+        let mut stats = QueueStats {
+            dead: self.dead.len(),
+            ..QueueStats::default()
+        };
+        for message in self.messages.values() {
+            match (message.visible_at <= now, message.receipt.is_some()) {
+                (true, _) => stats.ready += 1,
+                (false, true) => stats.in_flight += 1,
+                (false, false) => stats.delayed += 1,
+            }
+        }
+        stats
+    }
+
+    /// *dead_letters* returns up to `count` dead letters, oldest first
+    pub fn dead_letters(&self, count: usize) -> Vec<QueueMessage> {
+        self.dead.iter().take(count).cloned().collect()
+    }
+
+    /// *redrive* pushes the dead letters back at `now` as new messages, with new IDs so that the
+    /// receipts of their past deliveries stay invalid, and returns how many there were
+    pub fn redrive(&mut self, now: u64) -> usize {
+        let redriven = self.dead.len();
+        for mut message in self.dead.drain(..) {
+            self.next_id += 1;
+            message.id = self.next_id;
+            message.deliveries = 0;
+            message.visible_at = now;
+            self.schedule.insert((message.visible_at, message.id));
+            self.messages.insert(message.id, message);
+        }
+        redriven
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_limits_and_delays() {
+        let mut queue = Queue::default();
+        assert!(queue.is_new());
+        assert_eq!(queue.push("a".into(), 0, 1000, 2), Ok(1));
+        assert_eq!(queue.push("b".into(), 500, 1000, 2), Ok(2));
+        assert_eq!(
+            queue.push("c".into(), 0, 1000, 2),
+            Err(ValueError::QueueFull)
+        );
+
+        // delayed messages wait, messages become visible in order
+        assert_eq!(queue.pop(1000, 1000, 0).unwrap().message, "a");
+        assert_eq!(queue.pop(1000, 1000, 0), None);
+        let stats = queue.stats(1000);
+        assert_eq!((stats.in_flight, stats.delayed, stats.ready), (1, 1, 0));
+        assert_eq!(queue.pop(1500, 1000, 0).unwrap().message, "b");
+    }
+
+    #[test]
+    fn test_visibility_and_receipts() {
+        let mut queue = Queue::default();
+        queue.push("a".into(), 0, 1000, 0).unwrap();
+        let first = queue.pop(1000, 100, 0).unwrap();
+        assert_eq!(
+            (first.message.as_str(), first.receipt.as_str()),
+            ("a", "1-1")
+        );
+
+        // a message not acknowledged in time is delivered again, with a new receipt
+        let again = queue.pop(1100, 100, 0).unwrap();
+        assert_eq!((again.id, again.deliveries), (1, 2));
+        assert_eq!(queue.ack(&first.receipt), Err(ValueError::InvalidReceipt));
+
+        // a nacked message waits for its delay, and its receipt stops being valid
+        assert_eq!(queue.nack(&again.receipt, 1000, 1100), Ok(()));
+        assert_eq!(queue.ack(&again.receipt), Err(ValueError::InvalidReceipt));
+        assert_eq!(queue.pop(2099, 100, 0), None);
+        let last = queue.pop(2100, 100, 0).unwrap();
+        assert_eq!(queue.ack(&last.receipt), Ok(()));
+        assert!(queue.is_empty() && !queue.is_new());
+        assert_eq!(queue.push("b".into(), 0, 3000, 0), Ok(2));
+    }
+
+    #[test]
+    fn test_dead_letters() {
+        let mut queue = Queue::default();
+        queue.push("a".into(), 0, 1000, 0).unwrap();
+        queue.pop(1000, 100, 2).unwrap();
+        let second = queue.pop(1100, 100, 2).unwrap();
+
+        // delivered too often, the message is dead lettered instead
+        assert_eq!(queue.pop(1200, 100, 2), None);
+        assert_eq!(queue.stats(1200).dead, 1);
+        assert_eq!(queue.dead_letters(10)[0].message, "a");
+        assert_eq!(queue.ack(&second.receipt), Err(ValueError::InvalidReceipt));
+
+        // redriven messages get new IDs, so old receipts never match them
+        assert_eq!(queue.redrive(3000), 1);
+        let redriven = queue.pop(3000, 100, 2).unwrap();
+        assert_eq!((redriven.id, redriven.deliveries), (2, 1));
+        assert_eq!(queue.ack(&second.receipt), Err(ValueError::InvalidReceipt));
+        assert_eq!(queue.ack(&redriven.receipt), Ok(()));
+    }
+
+    #[test]
+    fn test_snapshot_format() {
+        let mut queue = Queue::default();
+        queue.push("a".into(), 0, 1000, 0).unwrap();
+        queue.push("b".into(), 500, 1000, 0).unwrap();
+        queue.pop(1000, 100, 0).unwrap();
+
+        // snapshots rebuild the schedule
+        let serialized = serde_json::to_string(&queue).unwrap();
+        let restored = serde_json::from_str::<Queue>(&serialized).unwrap();
+        assert_eq!(restored, queue);
+        assert_eq!(restored.stats(1000), queue.stats(1000));
+    }
+}
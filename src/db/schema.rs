@@ -0,0 +1,181 @@
+/// schema.rs holds the JSON Schemas that the `DataValue::Json` values of a database conform to.
+/// A schema is registered for the whole database, the empty prefix, or for the keys starting
+/// with a prefix, and a key is validated against the schema of the longest prefix it starts with.
+/// Schemas are compiled once when registered, values stored before a schema was registered are
+/// not validated again.
+use dashmap::DashMap;
+use jsonschema::Validator;
+use serde_json::Value as JsonValue;
+use std::{collections::BTreeMap, fmt, sync::Arc};
+
+/// At most this many violations are reported for a rejected value
+const MAX_REPORTED_ERRORS: usize = 5;
+
+/// A registered schema, as it was written and compiled
+#[derive(Clone)]
+pub struct Schema {
+    pub source: JsonValue,
+    validator: Arc<Validator>,
+}
+
+impl fmt::Debug for Schema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Schema")
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Schema {
+    /// *compile* fails with the reason when `source` is not a valid schema
+    pub fn compile(source: JsonValue) -> Result<Self, String> {
+        let validator =
+            jsonschema::validator_for(&source).map_err(|e| format!("INVALID_SCHEMA: {}", e))?;
+        Ok(Schema {
+            source,
+            validator: Arc::new(validator),
+        })
+    }
+
+    /// *validate* fails with every violation of `value`, up to `MAX_REPORTED_ERRORS`, each
+    /// prefixed with the JSON Pointer of the offending part
+    pub fn validate(&self, value: &JsonValue) -> Result<(), String> {
+        let violations: Vec<String> = self
+            .validator
+            .iter_errors(value)
+            .take(MAX_REPORTED_ERRORS)
+            .map(|error| {
+                let path = error.instance_path.to_string();
+                let path = if path.is_empty() { "/" } else { path.as_str() };
+                format!("{}: {}", path, error)
+            })
+            .collect();
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(format!("SCHEMA_VIOLATION: {}", violations.join("; ")))
+        }
+    }
+}
+
+/// The schemas of every database, by database and key prefix
+#[derive(Default)]
+pub struct SchemaRegistry {
+    schemas: DashMap<String, BTreeMap<String, Schema>>,
+}
+
+impl SchemaRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// *register* sets the schema of the prefix, replacing the one it had
+    pub fn register(&self, database: &str, prefix: &str, schema: Schema) {
+        self.schemas
+            .entry(database.to_string())
+            .or_default()
+            .insert(prefix.to_string(), schema);
+    }
+
+    /// *remove* drops the schema of the prefix and returns whether there was one
+    pub fn remove(&self, database: &str, prefix: &str) -> bool {
+        let Some(mut schemas) = self.schemas.get_mut(database) else {
+            return false;
+        };
+        let removed = schemas.remove(prefix).is_some();
+        let empty = schemas.is_empty();
+        drop(schemas);
+        if empty {
+            self.schemas.remove(database);
+        }
+        removed
+    }
+
+    /// *remove_database* drops every schema of the database
+    pub fn remove_database(&self, database: &str) {
+        self.schemas.remove(database);
+    }
+
+    /// *list* returns the schemas of the database as they were written, by prefix
+    pub fn list(&self, database: &str) -> BTreeMap<String, JsonValue> {
+        self.schemas
+            .get(database)
+            .map(|schemas| {
+                schemas
+                    .iter()
+                    .map(|(prefix, schema)| (prefix.clone(), schema.source.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// *schema_for* returns the schema of the longest prefix the key starts with
+    pub fn schema_for(&self, database: &str, key: &str) -> Option<Schema> {
+        let schemas = self.schemas.get(database)?;
+        schemas
+            .iter()
+            .filter(|(prefix, _)| key.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, schema)| schema.clone())
+    }
+
+    /// *validate* checks the value against the schema that applies to the key, if any
+    pub fn validate(&self, database: &str, key: &str, value: &JsonValue) -> Result<(), String> {
+        match self.schema_for(database, key) {
+            Some(schema) => schema.validate(value),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_schema_registry() {
+        assert!(Schema::compile(json!({"type": "no-such-type"}))
+            .unwrap_err()
+            .starts_with("INVALID_SCHEMA"));
+
+        let registry = SchemaRegistry::new();
+        let object = Schema::compile(json!({"type": "object"})).unwrap();
+        let user = Schema::compile(json!({
+            "type": "object",
+            "properties": {"age": {"type": "integer", "minimum": 0}},
+            "required": ["name"]
+        }))
+        .unwrap();
+        registry.register("shop", "", object);
+        registry.register("shop", "user:", user);
+
+        assert!(registry
+            .validate("shop", "cart:1", &json!({"items": []}))
+            .is_ok());
+        assert!(registry.validate("shop", "cart:1", &json!([1, 2])).is_err());
+        assert!(registry.validate("other", "cart:1", &json!([1, 2])).is_ok());
+        assert!(registry
+            .validate("shop", "user:1", &json!({"name": "ada", "age": 36}))
+            .is_ok());
+
+        let error = registry
+            .validate("shop", "user:1", &json!({"age": -1}))
+            .unwrap_err();
+        assert!(error.starts_with("SCHEMA_VIOLATION: "));
+        assert!(error.contains("/age: "));
+        assert!(error.contains("/: "));
+
+        assert_eq!(
+            registry.list("shop").keys().collect::<Vec<_>>(),
+            vec!["", "user:"]
+        );
+        assert!(registry.remove("shop", "user:"));
+        assert!(!registry.remove("shop", "user:"));
+        assert!(registry
+            .validate("shop", "user:1", &json!({"age": -1}))
+            .is_ok());
+        registry.remove_database("shop");
+        assert!(registry.list("shop").is_empty());
+    }
+}
@@ -0,0 +1,109 @@
+/// set.rs holds the set commands on `DataValue::Set`.
+/// `SPOP` and `SRANDMEMBER` pick members at random, `SPOP` is logged as the removal of the
+/// members it picked so that replaying it removes the same ones.
+use rand::seq::{IteratorRandom, SliceRandom};
+use std::collections::HashMap;
+
+/// The members of a set, as `DataValue::Set` holds them
+pub type Members = HashMap<String, ()>;
+
+/// How `SUNION`, `SINTER` and `SDIFF` combine their sets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetOperation {
+    Union,
+    Inter,
+    Diff,
+}
+
+/// *add* adds the members and returns how many were not in the set yet
+pub fn add(set: &mut Members, members: Vec<String>) -> usize {
+    members
+        .into_iter()
+        .map(|member| set.insert(member, ()).is_none() as usize)
+        .sum()
+}
+
+/// *remove* removes the members and returns how many were in the set
+pub fn remove(set: &mut Members, members: &[String]) -> usize {
+    members
+        .iter()
+        .filter(|member| set.remove(*member).is_some())
+        .count()
+}
+
+/// *sample* picks up to `count` distinct members at random
+pub fn sample(set: &Members, count: usize) -> Vec<String> {
+    set.keys()
+        .choose_multiple(&mut rand::thread_rng(), count)
+        .into_iter()
+        .cloned()
+        .collect()
+}
+
+/// *sample_repeated* picks `count` members at random, the same member may come up more than once
+pub fn sample_repeated(set: &Members, count: usize) -> Vec<String> {
+    let members: Vec<&String> = set.keys().collect();
+    let mut rng = rand::thread_rng();
+    (0..count)
+        .filter_map(|_| members.choose(&mut rng).map(|member| (*member).clone()))
+        .collect()
+}
+
+/// *combine* folds the sets from left to right, `Diff` keeps the members of the first set that
+/// are in none of the others
+pub fn combine(operation: SetOperation, sets: Vec<Members>) -> Members {
+    let mut sets = sets.into_iter();
+    let mut result = sets.next().unwrap_or_default();
+    for set in sets {
+        match operation {
+            SetOperation::Union => result.extend(set),
+            SetOperation::Inter => result.retain(|member, _| set.contains_key(member)),
+            SetOperation::Diff => result.retain(|member, _| !set.contains_key(member)),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(members: &[&str]) -> Members {
+        members
+            .iter()
+            .map(|member| (member.to_string(), ()))
+            .collect()
+    }
+
+    #[test]
+    fn test_add_and_remove() {
+        let mut tags = Members::new();
+        assert_eq!(add(&mut tags, vec!["a".into(), "b".into(), "a".into()]), 2);
+        assert_eq!(add(&mut tags, vec!["b".into(), "c".into()]), 1);
+        assert_eq!(remove(&mut tags, &["c".into(), "missing".into()]), 1);
+        assert_eq!(tags, set(&["a", "b"]));
+    }
+
+    #[test]
+    fn test_random_members() {
+        let tags = set(&["a", "b"]);
+        assert_eq!(sample(&tags, 5).len(), 2);
+        assert!(sample(&tags, 1)
+            .iter()
+            .all(|member| tags.contains_key(member)));
+        assert_eq!(sample_repeated(&tags, 5).len(), 5);
+        assert!(sample_repeated(&Members::new(), 5).is_empty());
+    }
+
+    #[test]
+    fn test_combine() {
+        let sets = || vec![set(&["a", "b", "c"]), set(&["b", "c", "d"]), set(&["c"])];
+        assert_eq!(
+            combine(SetOperation::Union, sets()),
+            set(&["a", "b", "c", "d"])
+        );
+        assert_eq!(combine(SetOperation::Inter, sets()), set(&["c"]));
+        assert_eq!(combine(SetOperation::Diff, sets()), set(&["a"]));
+        assert!(combine(SetOperation::Inter, vec![set(&["a"]), Members::new()]).is_empty());
+    }
+}
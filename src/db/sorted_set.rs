@@ -0,0 +1,485 @@
+/// sorted_set.rs holds `DataValue::SortedSet`, members ordered by score, then by member for
+/// equal scores. Scores are finite, a write that would make one infinite or NaN fails.
+/// A `BTreeSet` keeps the order and a `HashMap` the score of every member, so updates and pops
+/// are O(log n). Score ranges seek their first member and cost O(log n + k) for k members, ranks
+/// are counted by walking the order and cost O(n).
+/// A sorted set serializes as its `(member, score)` pairs in order, which keeps snapshots small.
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+    ops::Bound,
+};
+
+use super::{db::ValueError, list::bounds};
+
+/// A score ordered by `f64::total_cmp`
+#[derive(Debug, Clone, Copy)]
+struct Score(f64);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// The order of the members, by score then by member
+type Key = (Score, String);
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "Vec<(String, f64)>", into = "Vec<(String, f64)>")]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    order: BTreeSet<Key>,
+}
+
+impl From<Vec<(String, f64)>> for SortedSet {
+    fn from(members: Vec<(String, f64)>) -> Self {
+        let mut set = Self::default();
+        for (member, score) in members {
+            set.insert(member, score);
+        }
+        set
+    }
+}
+
+impl From<SortedSet> for Vec<(String, f64)> {
+    fn from(set: SortedSet) -> Self {
+        set.order
+            .into_iter()
+            .map(|(score, member)| (member, score.0))
+            .collect()
+    }
+}
+
+/// The conditions of `ZADD`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddOptions {
+    pub only_new: bool,      // NX, never update existing members
+    pub only_existing: bool, // XX, never add new members
+    pub greater: bool,       // GT, only raise the scores of existing members
+    pub less: bool,          // LT, only lower the scores of existing members
+}
+
+impl AddOptions {
+    /// *parse* reads the `NX`, `XX`, `GT` and `LT` flags, `None` for an unknown flag or flags
+    /// that contradict each other
+    pub fn parse(flags: &[&str]) -> Option<Self> {
+        let mut options = Self::default();
+        for flag in flags {
+            match *flag {
+                "NX" => options.only_new = true,
+                "XX" => options.only_existing = true,
+                "GT" => options.greater = true,
+                "LT" => options.less = true,
+                _ => return None,
+            }
+        }
+        let contradicting = (options.only_new && options.only_existing)
+            || (options.greater && options.less)
+            || (options.only_new && (options.greater || options.less));
+        (!contradicting).then_some(options)
+    }
+}
+
+/// A bound of a lexicographical range, `Min` and `Max` lie below and above every member
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LexBound {
+    Min,
+    Max,
+    Included(String),
+    Excluded(String),
+}
+
+impl LexBound {
+    /// *parse* reads `-`, `+`, `[member` or `(member`
+    pub fn parse(bound: &str) -> Option<Self> {
+        match bound {
+            "-" => Some(LexBound::Min),
+            "+" => Some(LexBound::Max),
+            _ => match bound.split_at_checked(1)? {
+                ("[", member) => Some(LexBound::Included(member.to_string())),
+                ("(", member) => Some(LexBound::Excluded(member.to_string())),
+                _ => None,
+            },
+        }
+    }
+
+    fn at_least(&self, member: &str) -> bool {
+        match self {
+            LexBound::Min => true,
+            LexBound::Max => false,
+            LexBound::Included(bound) => member >= bound.as_str(),
+            LexBound::Excluded(bound) => member > bound.as_str(),
+        }
+    }
+
+    fn at_most(&self, member: &str) -> bool {
+        match self {
+            LexBound::Min => false,
+            LexBound::Max => true,
+            LexBound::Included(bound) => member <= bound.as_str(),
+            LexBound::Excluded(bound) => member < bound.as_str(),
+        }
+    }
+}
+
+/// *parse_score_bound* reads a score bound, `(` makes it exclusive and `-inf` and `+inf` are
+/// accepted
+pub fn parse_score_bound(bound: &str) -> Option<Bound<f64>> {
+    let (value, exclusive) = match bound.strip_prefix('(') {
+        Some(value) => (value, true),
+        None => (bound, false),
+    };
+    let value = value.parse::<f64>().ok().filter(|value| !value.is_nan())?;
+    Some(match exclusive {
+        true => Bound::Excluded(value),
+        false => Bound::Included(value),
+    })
+}
+
+/// What a range of `ZRANGE` runs over
+#[derive(Debug, Clone, PartialEq)]
+pub enum RangeBy {
+    Rank(i64, i64), // inclusive, negative ranks count from the end
+    Score(Bound<f64>, Bound<f64>),
+    Lex(LexBound, LexBound), // only meaningful when every member has the same score
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RangeQuery {
+    pub by: RangeBy,
+    pub reverse: bool,                 // from the highest score down
+    pub limit: Option<(usize, usize)>, // offset and count, only for score and lex ranges
+}
+
+impl SortedSet {
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// *add* adds members or updates their scores as the options allow, and returns how many
+    /// members were added
+    pub fn add(&mut self, members: Vec<(f64, String)>, options: AddOptions) -> usize {
+        let mut added = 0;
+        for (score, member) in members {
+            match self.score(&member) {
+                Some(_) if options.only_new => {}
+                Some(current)
+                    if (options.greater && score <= current)
+                        || (options.less && score >= current) => {}
+                Some(_) => self.insert(member, score),
+                None if options.only_existing => {}
+                None => {
+                    self.insert(member, score);
+                    added += 1;
+                }
+            }
+        }
+        added
+    }
+
+    /// *incr_by* adds `amount` to the score of the member, a missing member counting as `0`,
+    /// and returns the new score
+    pub fn incr_by(&mut self, member: &str, amount: f64) -> Result<f64, ValueError> {
+        let score = self.score(member).unwrap_or(0.0) + amount;
+        if !score.is_finite() {
+            return Err(ValueError::NotANumber);
+        }
+        self.insert(member.to_string(), score);
+        Ok(score)
+    }
+
+    /// *remove* removes the members and returns how many were in the sorted set
+    pub fn remove(&mut self, members: &[String]) -> usize {
+        members
+            .iter()
+            .filter(|member| match self.scores.remove(*member) {
+                Some(score) => self.order.remove(&(Score(score), member.to_string())),
+                None => false,
+            })
+            .count()
+    }
+
+    /// *pop* removes up to `count` members with the lowest scores, or the highest with `max`
+    pub fn pop(&mut self, count: usize, max: bool) -> Vec<(String, f64)> {
+        let mut popped = Vec::new();
+        while popped.len() < count {
+            let first = match max {
+                true => self.order.pop_last(),
+                false => self.order.pop_first(),
+            };
+            let Some((score, member)) = first else {
+                break;
+            };
+            self.scores.remove(&member);
+            popped.push((member, score.0));
+        }
+        popped
+    }
+
+    /// *rank* is the position of the member from the lowest score, or from the highest with
+    /// `reverse`
+    pub fn rank(&self, member: &str, reverse: bool) -> Option<usize> {
+        let score = self.score(member)?;
+        let below = self
+            .order
+            .range(..(Score(score), member.to_string()))
+            .count();
+        Some(match reverse {
+            true => self.len() - 1 - below,
+            false => below,
+        })
+    }
+
+    /// *range* returns the members of the range with their scores, in the order asked for
+    pub fn range(&self, query: &RangeQuery) -> Vec<(String, f64)> {
+        let (offset, count) = query.limit.unwrap_or((0, usize::MAX));
+        self.entries(&query.by, query.reverse)
+            .skip(offset)
+            .take(count)
+            .map(|(member, score)| (member.clone(), score))
+            .collect()
+    }
+
+    /// *count* is the number of members scored between `min` and `max`
+    pub fn count(&self, min: Bound<f64>, max: Bound<f64>) -> usize {
+        self.entries(&RangeBy::Score(min, max), false).count()
+    }
+
+    fn insert(&mut self, member: String, score: f64) {
+        if let Some(previous) = self.scores.insert(member.clone(), score) {
+            self.order.remove(&(Score(previous), member.clone()));
+        }
+        self.order.insert((Score(score), member));
+    }
+
+    fn entries<'a>(
+        &'a self,
+        by: &'a RangeBy,
+        reverse: bool,
+    ) -> Box<dyn Iterator<Item = (&'a String, f64)> + 'a> {
+        // score ranges start from their first member, the others walk the whole order
+        let range = match by {
+            RangeBy::Score(min, max) => match score_keys(min, max) {
+                Some(keys) => self.order.range(keys),
+                None => return Box::new(std::iter::empty()),
+            },
+            _ => self.order.range::<Key, _>(..),
+        };
+        let ordered = range.map(|(score, member)| (member, score.0));
+        let ordered: Box<dyn Iterator<Item = (&String, f64)>> = match reverse {
+            true => Box::new(ordered.rev()),
+            false => Box::new(ordered),
+        };
+
+        match by {
+            RangeBy::Rank(start, stop) => match bounds(*start, *stop, self.len()) {
+                Some((start, stop)) => Box::new(ordered.skip(start).take(stop - start + 1)),
+                None => Box::new(std::iter::empty()),
+            },
+            // the keys cover the members scored at an excluded bound, which are left out here
+            RangeBy::Score(min, max) => {
+                Box::new(ordered.filter(move |(_, score)| {
+                    within(min, *score, true) && within(max, *score, false)
+                }))
+            }
+            RangeBy::Lex(min, max) => Box::new(
+                ordered.filter(move |(member, _)| min.at_least(member) && max.at_most(member)),
+            ),
+        }
+    }
+}
+
+/// *score_keys* is the range of the order covering every member scored between two bounds,
+/// `None` when the bounds do not make a range
+fn score_keys(min: &Bound<f64>, max: &Bound<f64>) -> Option<(Bound<Key>, Bound<Key>)> {
+    // the empty string comes before every member of a score, and `-0` before the members at `0`
+    let start = match min {
+        Bound::Included(min) | Bound::Excluded(min) if *min == 0.0 => Some(Score(-0.0)),
+        Bound::Included(min) | Bound::Excluded(min) => Some(Score(*min)),
+        Bound::Unbounded => None,
+    };
+    let end = match max {
+        Bound::Included(max) | Bound::Excluded(max) => Some(Score(max.next_up())),
+        Bound::Unbounded => None,
+    };
+    if let (Some(start), Some(end)) = (start, end) {
+        if start > end {
+            return None;
+        }
+    }
+    Some((
+        start.map_or(Bound::Unbounded, |start| {
+            Bound::Included((start, String::new()))
+        }),
+        end.map_or(Bound::Unbounded, |end| {
+            Bound::Excluded((end, String::new()))
+        }),
+    ))
+}
+
+/// *within* tells if the score is on the inner side of the bound, the bound being a lower one
+/// with `lower`
+fn within(bound: &Bound<f64>, score: f64, lower: bool) -> bool {
+    match (bound, lower) {
+        (Bound::Unbounded, _) => true,
+        (Bound::Included(bound), true) => score >= *bound,
+        (Bound::Excluded(bound), true) => score > *bound,
+        (Bound::Included(bound), false) => score <= *bound,
+        (Bound::Excluded(bound), false) => score < *bound,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn members(entries: &[(String, f64)]) -> Vec<&str> {
+        entries.iter().map(|(member, _)| member.as_str()).collect()
+    }
+
+    /// bob 25, carol 30, alice 40
+    fn board() -> SortedSet {
+        let scores = vec![
+            (30.0, "carol".into()),
+            (40.0, "alice".into()),
+            (25.0, "bob".into()),
+        ];
+        let mut board = SortedSet::default();
+        board.add(scores, AddOptions::default());
+        board
+    }
+
+    fn query(by: RangeBy, reverse: bool, limit: Option<(usize, usize)>) -> RangeQuery {
+        RangeQuery { by, reverse, limit }
+    }
+
+    #[test]
+    fn test_add_options() {
+        let mut board = board();
+        let gt = AddOptions::parse(&["GT"]).unwrap();
+        assert_eq!(
+            board.add(vec![(5.0, "alice".into()), (35.0, "bob".into())], gt),
+            0
+        );
+        assert_eq!(board.score("alice"), Some(40.0));
+        assert_eq!(board.score("bob"), Some(35.0));
+        let xx = AddOptions::parse(&["XX"]).unwrap();
+        assert_eq!(board.add(vec![(1.0, "dave".into())], xx), 0);
+        assert_eq!(board.score("dave"), None);
+        assert_eq!(AddOptions::parse(&["NX", "GT"]), None);
+    }
+
+    #[test]
+    fn test_incr_by_and_rank() {
+        let mut board = board();
+        assert_eq!(board.incr_by("bob", 20.0), Ok(45.0));
+        assert_eq!(
+            board.incr_by("bob", f64::INFINITY),
+            Err(ValueError::NotANumber)
+        );
+        assert_eq!(board.rank("bob", false), Some(2));
+        assert_eq!(board.rank("bob", true), Some(0));
+        assert_eq!(board.rank("nobody", false), None);
+    }
+
+    #[test]
+    fn test_rank_ranges() {
+        let board = board();
+        let all = board.range(&query(RangeBy::Rank(0, -1), false, None));
+        assert_eq!(members(&all), vec!["bob", "carol", "alice"]);
+        let last = board.range(&query(RangeBy::Rank(-1, -1), true, None));
+        assert_eq!(members(&last), vec!["bob"]);
+        let inverted = RangeBy::Rank(i64::MAX, i64::MIN);
+        assert!(board.range(&query(inverted, false, None)).is_empty());
+    }
+
+    #[test]
+    fn test_score_ranges() {
+        let mut board = board();
+        let by_score = RangeBy::Score(Bound::Excluded(25.0), Bound::Included(f64::INFINITY));
+        let top = board.range(&query(by_score.clone(), true, Some((0, 1))));
+        assert_eq!(top, vec![("alice".to_string(), 40.0)]);
+        assert_eq!(
+            members(&board.range(&query(by_score, false, None))),
+            vec!["carol", "alice"]
+        );
+        assert_eq!(
+            board.count(
+                parse_score_bound("-inf").unwrap(),
+                parse_score_bound("(30").unwrap()
+            ),
+            1
+        );
+        assert_eq!(board.count(Bound::Included(30.0), Bound::Included(25.0)), 0);
+        assert_eq!(board.count(Bound::Excluded(25.0), Bound::Excluded(40.0)), 1);
+
+        // `-0` sorts before `0` but lies within a range starting at `0`
+        board.add(vec![(-0.0, "zero".into())], AddOptions::default());
+        assert_eq!(board.count(Bound::Included(0.0), Bound::Included(0.0)), 1);
+    }
+
+    #[test]
+    fn test_lex_ranges() {
+        let board = board();
+        let by_lex = RangeBy::Lex(
+            LexBound::parse("[b").unwrap(),
+            LexBound::parse("(c").unwrap(),
+        );
+        assert_eq!(
+            members(&board.range(&query(by_lex, false, None))),
+            vec!["bob"]
+        );
+        assert_eq!(LexBound::parse("b"), None);
+    }
+
+    #[test]
+    fn test_snapshot_format() {
+        let board = board();
+        let serialized = serde_json::to_string(&board).unwrap();
+        assert_eq!(
+            serialized,
+            r#"[["bob",25.0],["carol",30.0],["alice",40.0]]"#
+        );
+        assert_eq!(
+            serde_json::from_str::<SortedSet>(&serialized).unwrap(),
+            board
+        );
+    }
+
+    #[test]
+    fn test_pop_and_remove() {
+        let mut board = board();
+        assert_eq!(board.pop(1, true), vec![("alice".to_string(), 40.0)]);
+        assert_eq!(
+            board.pop(5, false),
+            vec![("bob".to_string(), 25.0), ("carol".to_string(), 30.0)]
+        );
+        let mut board = SortedSet::from(vec![("alice".to_string(), 1.0)]);
+        assert_eq!(board.remove(&["alice".into(), "nobody".into()]), 1);
+        assert!(board.is_empty());
+    }
+}
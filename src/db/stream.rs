@@ -0,0 +1,564 @@
+/// stream.rs holds `DataValue::Stream`, an append-only log of entries. Every entry is a list of
+/// field value pairs under an ID of the form `<milliseconds>-<sequence>`, and IDs only ever grow:
+/// an entry added with `*` is given the current time, or the ID following the last one when the
+/// clock is behind it.
+/// Consumer groups read a stream together. Every entry is delivered to one consumer of a group and
+/// stays pending until it is acknowledged, so another consumer can claim it when the first fails.
+/// Writes take the time they happen at, so replaying them from the WAL generates the same IDs and
+/// delivery times. A stream is only removed with its key, trimming away every entry keeps its last
+/// ID and its groups.
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fmt, ops::Bound};
+
+use super::db::ValueError;
+
+/// The ID of a stream entry, ordered by time then by sequence
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(into = "String", try_from = "String")]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    /// *parse* reads `<ms>-<seq>`, or `<ms>` whose sequence is `missing_seq`
+    pub fn parse(id: &str, missing_seq: u64) -> Option<Self> {
+        let (ms, seq) = match id.split_once('-') {
+            Some((ms, seq)) => (ms.parse().ok()?, seq.parse().ok()?),
+            None => (id.parse().ok()?, missing_seq),
+        };
+        Some(StreamId { ms, seq })
+    }
+
+    /// *following* is the ID generated after this one within the same millisecond
+    fn following(self) -> Self {
+        match self.seq.checked_add(1) {
+            Some(seq) => StreamId { ms: self.ms, seq },
+            None => StreamId {
+                ms: self.ms.saturating_add(1),
+                seq: 0,
+            },
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+impl From<StreamId> for String {
+    fn from(id: StreamId) -> Self {
+        id.to_string()
+    }
+}
+
+impl TryFrom<String> for StreamId {
+    type Error = String;
+
+    fn try_from(id: String) -> Result<Self, Self::Error> {
+        StreamId::parse(&id, 0).ok_or_else(|| format!("invalid stream ID `{}`", id))
+    }
+}
+
+/// *parse_range_bound* reads a bound of `XRANGE`, `-` and `+` for no bound, an ID, or an ID
+/// prefixed with `(` to leave it out. An ID without its sequence covers the whole millisecond.
+pub fn parse_range_bound(bound: &str, end: bool) -> Option<Bound<StreamId>> {
+    let missing_seq = if end { u64::MAX } else { 0 };
+    match bound {
+        "-" | "+" => Some(Bound::Unbounded),
+        _ => match bound.strip_prefix('(') {
+            Some(id) => StreamId::parse(id, missing_seq).map(Bound::Excluded),
+            None => StreamId::parse(bound, missing_seq).map(Bound::Included),
+        },
+    }
+}
+
+/// An entry as read from a stream
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamEntry {
+    pub id: StreamId,
+    pub fields: Vec<(String, String)>,
+}
+
+/// An entry delivered to a consumer of a group and not acknowledged yet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PendingEntry {
+    pub consumer: String,
+    pub delivered_at: u64, // milliseconds since the epoch of the last delivery
+    pub deliveries: u64,
+}
+
+/// What `XPENDING` reports about a group
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PendingSummary {
+    pub count: usize,
+    pub min: Option<StreamId>,
+    pub max: Option<StreamId>,
+    pub consumers: BTreeMap<String, usize>,
+}
+
+/// What `XPENDING` reports about a pending entry
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PendingInfo {
+    pub id: StreamId,
+    pub consumer: String,
+    pub idle: u64, // milliseconds since the last delivery
+    pub deliveries: u64,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct ConsumerGroup {
+    last_delivered: StreamId,
+    pending: BTreeMap<StreamId, PendingEntry>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Stream {
+    entries: BTreeMap<StreamId, Vec<(String, String)>>,
+    last_id: StreamId,
+    groups: BTreeMap<String, ConsumerGroup>,
+}
+
+impl Stream {
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// *is_new* tells whether the stream was never written to, a stream that has been is kept
+    /// even without entries so its IDs keep growing
+    pub fn is_new(&self) -> bool {
+        self.entries.is_empty() && self.groups.is_empty() && self.last_id == StreamId::default()
+    }
+
+    pub fn last_id(&self) -> StreamId {
+        self.last_id
+    }
+
+    /// *add* appends an entry under `id`, which has to be greater than the last ID, or under an
+    /// ID generated from `now` in milliseconds. With `max_len` the oldest entries are trimmed
+    /// until at most that many are left. Returns the ID of the entry.
+    pub fn add(
+        &mut self,
+        id: Option<StreamId>,
+        fields: Vec<(String, String)>,
+        max_len: Option<usize>,
+        now: u64,
+    ) -> Result<StreamId, ValueError> {
+        let id = match id {
+            Some(id) if id > self.last_id => id,
+            Some(_) => return Err(ValueError::IdTooSmall),
+            None if now > self.last_id.ms => StreamId { ms: now, seq: 0 },
+            None => self.last_id.following(),
+        };
+        self.entries.insert(id, fields);
+        self.last_id = id;
+        if let Some(max_len) = max_len {
+            self.trim(max_len);
+        }
+        Ok(id)
+    }
+
+    /// *trim* removes the oldest entries until at most `max_len` are left and returns how many
+    /// were removed
+    pub fn trim(&mut self, max_len: usize) -> usize {
+        let removed = self.entries.len().saturating_sub(max_len);
+        for _ in 0..removed {
+            self.entries.pop_first();
+        }
+        removed
+    }
+
+    /// *range* returns up to `count` entries between two bounds, oldest first
+    pub fn range(
+        &self,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: Option<usize>,
+    ) -> Vec<StreamEntry> {
+        if !valid_range(start, end) {
+            return Vec::new();
+        }
+        self.entries
+            .range((start, end))
+            .take(count.unwrap_or(usize::MAX))
+            .map(|(id, fields)| StreamEntry {
+                id: *id,
+                fields: fields.clone(),
+            })
+            .collect()
+    }
+
+    /// *read_after* returns up to `count` entries added after `id`, oldest first
+    pub fn read_after(&self, id: StreamId, count: Option<usize>) -> Vec<StreamEntry> {
+        self.range(Bound::Excluded(id), Bound::Unbounded, count)
+    }
+
+    /// *create_group* adds a group that delivers the entries after `start`, or only the ones
+    /// added from now on when `start` is `None`
+    pub fn create_group(&mut self, group: &str, start: Option<StreamId>) -> Result<(), ValueError> {
+        if self.groups.contains_key(group) {
+            return Err(ValueError::GroupExists);
+        }
+        let group_state = ConsumerGroup {
+            last_delivered: start.unwrap_or(self.last_id),
+            pending: BTreeMap::new(),
+        };
+        self.groups.insert(group.to_string(), group_state);
+        Ok(())
+    }
+
+    /// *destroy_group* removes a group with its pending entries and returns whether it existed
+    pub fn destroy_group(&mut self, group: &str) -> bool {
+        self.groups.remove(group).is_some()
+    }
+
+    /// *has_undelivered* tells whether the group has entries it never delivered
+    pub fn has_undelivered(&self, group: &str) -> Result<bool, ValueError> {
+        let group = self.groups.get(group).ok_or(ValueError::NoSuchGroup)?;
+        Ok(self
+            .entries
+            .last_key_value()
+            .is_some_and(|(id, _)| *id > group.last_delivered))
+    }
+
+    /// *read_group* delivers up to `count` entries the group never delivered to the consumer when
+    /// `after` is `None`. Otherwise it delivers the entries pending for the consumer after that
+    /// ID again, leaving out the ones trimmed from the stream since.
+    pub fn read_group(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        now: u64,
+    ) -> Result<Vec<StreamEntry>, ValueError> {
+        let state = self.groups.get_mut(group).ok_or(ValueError::NoSuchGroup)?;
+        let count = count.unwrap_or(usize::MAX);
+        let Some(after) = after else {
+            let entries: Vec<StreamEntry> = self
+                .entries
+                .range((Bound::Excluded(state.last_delivered), Bound::Unbounded))
+                .take(count)
+                .map(|(id, fields)| StreamEntry {
+                    id: *id,
+                    fields: fields.clone(),
+                })
+                .collect();
+            for entry in &entries {
+                let pending = PendingEntry {
+                    consumer: consumer.to_string(),
+                    delivered_at: now,
+                    deliveries: 1,
+                };
+                state.pending.insert(entry.id, pending);
+                state.last_delivered = entry.id;
+            }
+            return Ok(entries);
+        };
+
+        let mut entries = Vec::new();
+        for (id, pending) in state
+            .pending
+            .range_mut((Bound::Excluded(after), Bound::Unbounded))
+            .filter(|(_, pending)| pending.consumer == consumer)
+        {
+            if entries.len() == count {
+                break;
+            }
+            if let Some(fields) = self.entries.get(id) {
+                pending.delivered_at = now;
+                pending.deliveries += 1;
+                entries.push(StreamEntry {
+                    id: *id,
+                    fields: fields.clone(),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// *ack* removes entries from the pending entries of the group and returns how many were
+    /// pending
+    pub fn ack(&mut self, group: &str, ids: &[StreamId]) -> Result<usize, ValueError> {
+        let state = self.groups.get_mut(group).ok_or(ValueError::NoSuchGroup)?;
+        Ok(ids
+            .iter()
+            .filter(|id| state.pending.remove(id).is_some())
+            .count())
+    }
+
+    /// *pending_summary* counts the pending entries of the group, in total and by consumer
+    pub fn pending_summary(&self, group: &str) -> Result<PendingSummary, ValueError> {
+        let state = self.groups.get(group).ok_or(ValueError::NoSuchGroup)?;
+        let mut consumers = BTreeMap::new();
+        for pending in state.pending.values() {
+            *consumers.entry(pending.consumer.clone()).or_insert(0) += 1;
+        }
+        Ok(PendingSummary {
+            count: state.pending.len(),
+            min: state.pending.first_key_value().map(|(id, _)| *id),
+            max: state.pending.last_key_value().map(|(id, _)| *id),
+            consumers,
+        })
+    }
+
+    /// *pending* lists up to `count` pending entries of the group between two bounds, only the
+    /// ones of `consumer` if given
+    pub fn pending(
+        &self,
+        group: &str,
+        start: Bound<StreamId>,
+        end: Bound<StreamId>,
+        count: usize,
+        consumer: Option<&str>,
+        now: u64,
+    ) -> Result<Vec<PendingInfo>, ValueError> {
+        let state = self.groups.get(group).ok_or(ValueError::NoSuchGroup)?;
+        if !valid_range(start, end) {
+            return Ok(Vec::new());
+        }
+        Ok(state
+            .pending
+            .range((start, end))
+            .filter(|(_, pending)| consumer.is_none_or(|consumer| pending.consumer == consumer))
+            .take(count)
+            .map(|(id, pending)| PendingInfo {
+                id: *id,
+                consumer: pending.consumer.clone(),
+                idle: now.saturating_sub(pending.delivered_at),
+                deliveries: pending.deliveries,
+            })
+            .collect())
+    }
+
+    /// *claim* hands the pending entries idle for at least `min_idle` milliseconds over to the
+    /// consumer and returns them. Pending entries trimmed from the stream are dropped instead.
+    pub fn claim(
+        &mut self,
+        group: &str,
+        consumer: &str,
+        min_idle: u64,
+        ids: &[StreamId],
+        now: u64,
+    ) -> Result<Vec<StreamEntry>, ValueError> {
+        let state = self.groups.get_mut(group).ok_or(ValueError::NoSuchGroup)?;
+        let mut claimed = Vec::new();
+        for id in ids {
+            let Some(pending) = state.pending.get_mut(id) else {
+                continue;
+            };
+            if now.saturating_sub(pending.delivered_at) < min_idle {
+                continue;
+            }
+            match self.entries.get(id) {
+                Some(fields) => {
+                    pending.consumer = consumer.to_string();
+                    pending.delivered_at = now;
+                    pending.deliveries += 1;
+                    claimed.push(StreamEntry {
+                        id: *id,
+                        fields: fields.clone(),
+                    });
+                }
+                None => {
+                    state.pending.remove(id);
+                }
+            }
+        }
+        Ok(claimed)
+    }
+}
+
+/// *valid_range* tells whether a range can hold IDs, `BTreeMap::range` panics on the others
+fn valid_range(start: Bound<StreamId>, end: Bound<StreamId>) -> bool {
+    match (start, end) {
+        (Bound::Included(start), Bound::Included(end)) => start <= end,
+        (Bound::Included(start) | Bound::Excluded(start), Bound::Excluded(end))
+        | (Bound::Excluded(start), Bound::Included(end)) => start < end,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(value: &str) -> Vec<(String, String)> {
+        vec![("value".to_string(), value.to_string())]
+    }
+
+    fn ids(entries: &[StreamEntry]) -> Vec<String> {
+        entries.iter().map(|entry| entry.id.to_string()).collect()
+    }
+
+    /// entries 1000-0, 1000-1 and 2000-5
+    fn stream() -> Stream {
+        let mut stream = Stream::default();
+        stream.add(None, fields("a"), None, 1000).unwrap();
+        stream.add(None, fields("b"), None, 1000).unwrap();
+        let explicit = StreamId::parse("2000-5", 0).unwrap();
+        stream.add(Some(explicit), fields("c"), None, 0).unwrap();
+        stream
+    }
+
+    #[test]
+    fn test_ids_only_grow() {
+        let mut stream = Stream::default();
+        assert!(stream.is_new());
+        assert_eq!(
+            stream.add(None, fields("a"), None, 1000),
+            Ok(StreamId { ms: 1000, seq: 0 })
+        );
+        // the clock going back never makes IDs go back
+        assert_eq!(
+            stream
+                .add(None, fields("b"), None, 999)
+                .unwrap()
+                .to_string(),
+            "1000-1"
+        );
+        let explicit = StreamId::parse("2000-5", 0).unwrap();
+        assert_eq!(
+            stream.add(Some(explicit), fields("c"), None, 0),
+            Ok(explicit)
+        );
+        assert_eq!(
+            stream.add(Some(explicit), fields("d"), None, 0),
+            Err(ValueError::IdTooSmall)
+        );
+
+        // trimming every entry keeps the last ID
+        assert_eq!(stream.trim(0), 3);
+        assert!(stream.is_empty() && !stream.is_new());
+        assert_eq!(
+            stream
+                .add(None, fields("e"), Some(1), 0)
+                .unwrap()
+                .to_string(),
+            "2000-6"
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let stream = stream();
+        let all = stream.range(Bound::Unbounded, Bound::Unbounded, None);
+        assert_eq!(ids(&all), vec!["1000-0", "1000-1", "2000-5"]);
+        let millisecond = stream.range(
+            parse_range_bound("1000", false).unwrap(),
+            parse_range_bound("1000", true).unwrap(),
+            None,
+        );
+        assert_eq!(millisecond.len(), 2);
+        let reversed = stream.range(
+            parse_range_bound("2000", false).unwrap(),
+            parse_range_bound("(1000", true).unwrap(),
+            None,
+        );
+        assert!(reversed.is_empty());
+        assert_eq!(
+            ids(&stream.read_after(StreamId { ms: 1000, seq: 0 }, Some(1))),
+            vec!["1000-1"]
+        );
+    }
+
+    #[test]
+    fn test_consumer_groups() {
+        let mut stream = stream();
+        stream
+            .create_group("workers", Some(StreamId::default()))
+            .unwrap();
+        assert_eq!(
+            stream.create_group("workers", None),
+            Err(ValueError::GroupExists)
+        );
+        assert_eq!(
+            stream.read_group("nobody", "w1", None, None, 0),
+            Err(ValueError::NoSuchGroup)
+        );
+
+        // every entry is delivered once per group, and stays pending until acknowledged
+        let first = stream
+            .read_group("workers", "w1", None, Some(2), 5000)
+            .unwrap();
+        assert_eq!(ids(&first), vec!["1000-0", "1000-1"]);
+        let second = stream
+            .read_group("workers", "w2", None, None, 5000)
+            .unwrap();
+        assert_eq!(ids(&second), vec!["2000-5"]);
+        assert!(!stream.has_undelivered("workers").unwrap());
+        assert_eq!(stream.ack("workers", &[first[0].id, first[0].id]), Ok(1));
+
+        let summary = stream.pending_summary("workers").unwrap();
+        assert_eq!((summary.count, summary.min), (2, Some(first[1].id)));
+        assert_eq!(summary.consumers.get("w1"), Some(&1));
+        let history = stream
+            .read_group("workers", "w1", Some(StreamId::default()), None, 6000)
+            .unwrap();
+        assert_eq!(history, vec![first[1].clone()]);
+        assert!(stream.destroy_group("workers"));
+        assert!(!stream.destroy_group("workers"));
+    }
+
+    #[test]
+    fn test_claims() {
+        let mut stream = stream();
+        stream
+            .create_group("workers", Some(StreamId::default()))
+            .unwrap();
+        let delivered = stream
+            .read_group("workers", "w1", None, None, 5000)
+            .unwrap();
+        let id = delivered[2].id;
+
+        // claims only take entries idle long enough
+        assert!(stream
+            .claim("workers", "w2", 5000, &[id], 7000)
+            .unwrap()
+            .is_empty());
+        let claimed = stream.claim("workers", "w2", 1000, &[id], 7000).unwrap();
+        assert_eq!(claimed, vec![delivered[2].clone()]);
+        let pending = stream
+            .pending(
+                "workers",
+                Bound::Unbounded,
+                Bound::Unbounded,
+                10,
+                Some("w2"),
+                7500,
+            )
+            .unwrap();
+        assert_eq!((pending[0].idle, pending[0].deliveries), (500, 2));
+
+        // trimmed entries are dropped from the pending entries once claimed
+        stream.trim(0);
+        assert!(stream
+            .claim("workers", "w2", 0, &[id], 8000)
+            .unwrap()
+            .is_empty());
+        assert_eq!(stream.pending_summary("workers").unwrap().count, 2);
+    }
+
+    #[test]
+    fn test_snapshot_format() {
+        let mut stream = stream();
+        stream
+            .create_group("workers", Some(StreamId::default()))
+            .unwrap();
+        stream
+            .read_group("workers", "w1", None, Some(1), 5000)
+            .unwrap();
+
+        // snapshots keep the entries, the last ID and the pending entries
+        let serialized = serde_json::to_string(&stream).unwrap();
+        assert_eq!(serde_json::from_str::<Stream>(&serialized).unwrap(), stream);
+    }
+}
@@ -0,0 +1,266 @@
+/// vector.rs holds `DataValue::Vector`, an embedding with optional JSON metadata, and the HNSW
+/// index every database keeps over its vectors for similarity searches.
+/// The index has a fixed dimension and metric, set with `VINDEX` or taken from the first vector
+/// added with the cosine metric. HNSW graphs cannot remove nodes, so removed and replaced vectors
+/// are only marked as such and the graph is rebuilt once they make up half of it.
+/// Only the dimension and metric of the index are persisted, the graph is rebuilt from the
+/// vectors on recovery.
+use hnsw::{Hnsw, Searcher};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use space::Neighbor;
+use std::collections::HashMap;
+
+/// Candidates considered by a search, more find the nearest vectors more reliably
+const EF_SEARCH: usize = 64;
+
+/// Removed nodes are only rebuilt away once the graph holds at least this many
+const MIN_REBUILD_NODES: usize = 64;
+
+/// How the distance between two vectors is measured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    Cosine, // `1 - cosine similarity`, between 0 and 2
+    L2,     // euclidean distance
+    Dot,    // negated dot product, so that the most similar vectors come first
+}
+
+impl Metric {
+    pub fn parse(metric: &str) -> Option<Self> {
+        match metric.to_ascii_uppercase().as_str() {
+            "COSINE" => Some(Metric::Cosine),
+            "L2" => Some(Metric::L2),
+            "DOT" => Some(Metric::Dot),
+            _ => None,
+        }
+    }
+
+    pub fn distance(&self, a: &[f32], b: &[f32]) -> f32 {
+        let dot = || a.iter().zip(b).map(|(x, y)| x * y).sum::<f32>();
+        match self {
+            Metric::Cosine => {
+                let norms = norm(a) * norm(b);
+                if norms == 0.0 {
+                    1.0
+                } else {
+                    1.0 - dot() / norms
+                }
+            }
+            Metric::L2 => a
+                .iter()
+                .zip(b)
+                .map(|(x, y)| (x - y) * (x - y))
+                .sum::<f32>()
+                .sqrt(),
+            Metric::Dot => -dot(),
+        }
+    }
+}
+
+fn norm(vector: &[f32]) -> f32 {
+    vector.iter().map(|x| x * x).sum::<f32>().sqrt()
+}
+
+/// A vector as `DataValue::Vector` holds it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vector {
+    pub values: Vec<f32>,
+    #[serde(default)]
+    pub metadata: JsonValue,
+}
+
+/// A vector found by `VSEARCH`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct VectorMatch {
+    pub key: String,
+    pub distance: f32,
+    pub metadata: JsonValue,
+}
+
+/// The dimension and metric of the index of a database
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct IndexConfig {
+    pub dimension: usize,
+    pub metric: Metric,
+}
+
+/// The metric as the HNSW graph sees it, distances are ordered as unsigned integers
+struct Distance(Metric);
+
+impl space::Metric<Vec<f32>> for Distance {
+    type Unit = u32;
+
+    fn distance(&self, a: &Vec<f32>, b: &Vec<f32>) -> u32 {
+        // maps the floats onto integers in the same order, negative ones included
+        let bits = self.0.distance(a, b).to_bits();
+        if bits >> 31 == 0 {
+            bits | 1 << 31
+        } else {
+            !bits
+        }
+    }
+}
+
+/// The HNSW index of the vectors of a database
+pub struct VectorIndex {
+    pub config: IndexConfig,
+    graph: Hnsw<Distance, Vec<f32>, StdRng, 12, 24>,
+    keys: Vec<Option<String>>, // the key of every node of the graph, `None` once removed
+    nodes: HashMap<String, usize>,
+    removed: usize,
+}
+
+impl VectorIndex {
+    pub fn new(config: IndexConfig) -> Self {
+        VectorIndex {
+            config,
+            graph: Hnsw::new(Distance(config.metric)),
+            keys: Vec::new(),
+            nodes: HashMap::new(),
+            removed: 0,
+        }
+    }
+
+    /// *len* is the number of vectors in the index
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// *insert* indexes the vector of the key, replacing the one it had
+    pub fn insert(&mut self, key: &str, values: Vec<f32>) {
+        self.remove(key);
+        let node = self.graph.insert(values, &mut Searcher::default());
+        self.keys.push(Some(key.to_string()));
+        self.nodes.insert(key.to_string(), node);
+    }
+
+    /// *remove* drops the vector of the key from the index and returns whether it had one
+    pub fn remove(&mut self, key: &str) -> bool {
+        let Some(node) = self.nodes.remove(key) else {
+            return false;
+        };
+        self.keys[node] = None;
+        self.removed += 1;
+        if self.keys.len() >= MIN_REBUILD_NODES && self.removed * 2 >= self.keys.len() {
+            self.rebuild(self.config);
+        }
+        true
+    }
+
+    /// *rebuild* builds the graph again from the indexed vectors, measured with the metric of
+    /// `config`, whose dimension has to be the one of the vectors
+    pub fn rebuild(&mut self, config: IndexConfig) {
+        let vectors: Vec<(String, Vec<f32>)> = self
+            .nodes
+            .iter()
+            .map(|(key, node)| (key.clone(), self.graph.feature(*node).clone()))
+            .collect();
+        *self = VectorIndex::new(config);
+        for (key, values) in vectors {
+            self.insert(&key, values);
+        }
+    }
+
+    /// *search* returns up to `count` indexed keys nearest to `query`, with their vector and
+    /// distance, nearest first
+    pub fn search(&self, query: &[f32], count: usize) -> Vec<(String, &[f32], f32)> {
+        let nodes = count.saturating_add(self.removed).min(self.keys.len());
+        if nodes == 0 {
+            return Vec::new();
+        }
+        let query = query.to_vec();
+        // the graph does not always reach every node, and a search for more nodes than it reaches
+        // fails, so small indexes and searches for a large part of one scan all of it instead
+        let found: Vec<usize> = if self.keys.len() <= EF_SEARCH || nodes * 2 > self.keys.len() {
+            (0..self.keys.len()).collect()
+        } else {
+            let mut neighbors = vec![
+                Neighbor {
+                    index: !0,
+                    distance: !0,
+                };
+                nodes
+            ];
+            self.graph
+                .nearest(
+                    &query,
+                    nodes.max(EF_SEARCH),
+                    &mut Searcher::default(),
+                    &mut neighbors,
+                )
+                .iter()
+                .map(|neighbor| neighbor.index)
+                .collect()
+        };
+        let mut nearest: Vec<(String, &[f32], f32)> = found
+            .into_iter()
+            .filter_map(|index| {
+                let key = self.keys.get(index)?.as_ref()?;
+                let values = self.graph.feature(index).as_slice();
+                Some((
+                    key.clone(),
+                    values,
+                    self.config.metric.distance(&query, values),
+                ))
+            })
+            .collect();
+        nearest.sort_by(|a, b| a.2.total_cmp(&b.2));
+        nearest.truncate(count);
+        nearest
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vector_index() {
+        assert_eq!(Metric::Cosine.distance(&[1.0, 0.0], &[0.0, 2.0]), 1.0);
+        assert_eq!(Metric::L2.distance(&[0.0, 0.0], &[3.0, 4.0]), 5.0);
+        assert_eq!(Metric::Dot.distance(&[1.0, 2.0], &[3.0, 4.0]), -11.0);
+
+        let config = IndexConfig {
+            dimension: 2,
+            metric: Metric::L2,
+        };
+        let mut index = VectorIndex::new(config);
+        for i in 0..100 {
+            index.insert(&format!("v{}", i), vec![i as f32, 0.0]);
+        }
+        let nearest: Vec<String> = index
+            .search(&[41.9, 0.0], 3)
+            .into_iter()
+            .map(|(key, _, _)| key)
+            .collect();
+        assert_eq!(nearest, vec!["v42", "v41", "v43"]);
+
+        // replaced and removed vectors are never found again, and are rebuilt away
+        index.insert("v42", vec![-10.0, 0.0]);
+        assert!(index.remove("v41"));
+        assert!(!index.remove("v41"));
+        let (key, values, distance) = index.search(&[41.9, 0.0], 1).remove(0);
+        assert_eq!((key.as_str(), values), ("v43", &[43.0, 0.0][..]));
+        assert!((distance - 1.1).abs() < 1e-4);
+        assert_eq!(index.search(&[0.0, 0.0], usize::MAX).len(), 99);
+
+        for i in 0..60 {
+            index.remove(&format!("v{}", i));
+        }
+        assert_eq!(index.len(), 40);
+        assert!(index.keys.len() < 100);
+        assert_eq!(index.search(&[0.0, 0.0], 1)[0].0, "v60");
+
+        index.rebuild(IndexConfig {
+            dimension: 2,
+            metric: Metric::Dot,
+        });
+        assert_eq!(index.search(&[1.0, 0.0], 1)[0].0, "v99");
+    }
+}
@@ -0,0 +1,43 @@
+//! # TinyCache
+//!
+//! The TinyCache engine as a library. The `tinycache` binary is a thin wrapper around it that
+//! adds the setup wizard and the network servers, services can instead embed the engine and
+//! use it in-process:
+//!
+//! ```no_run
+//! use tinycache::{AggregationOperation, DataValue, TinyCache};
+//!
+//! # async fn example() -> std::io::Result<()> {
+//! let db = TinyCache::builder("/var/lib/myservice/cache")
+//!     .in_memory(true)
+//!     .build()
+//!     .await?;
+//!
+//! db.create_key_value("users", "user:1".to_string(), DataValue::Json(serde_json::json!({"age": 36})))
+//!     .await?;
+//! let count = db.query("users", vec![AggregationOperation::Count]).await;
+//!
+//! db.shutdown().await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The types re-exported at the crate root form the stable API, the modules are public so that
+//! the binary can reach the server internals.
+pub mod cli;
+pub mod constants;
+pub mod db;
+pub mod persistance;
+pub mod query;
+pub mod requests;
+pub mod security;
+pub mod utils;
+
+pub use db::{
+    builder::TinyCacheBuilder,
+    db::{DataValue, DatabaseStats, DatabaseType, TinyCache},
+    events::{KeyEvent, KeyEventKind},
+};
+pub use persistance::persistance::PersistenceConfig;
+pub use security::config::DBConfig;
+pub use tinycache_protocol::{AggregationOperation, FilterCondition, Response, ResponseData};
@@ -1,25 +1,12 @@
-mod cli;
-mod constants;
-mod db;
-mod persistance;
-mod query;
-mod requests;
-mod security;
-mod utils;
-
 use clap::{Arg, Command};
-use cli::cli::CLI;
 use colored::*;
-use db::db::TinyCache;
 use dotenv::dotenv;
-use persistance::persistance::PersistenceConfig;
-use requests::client::handle_client;
-use security::config::DBConfig;
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use utils::{
-    logs::LogLevel,
-    utils::{display_startup_info, print_art, return_data_dir},
+use tinycache::{
+    cli::cli::CLI,
+    requests::listener::serve,
+    utils::utils::{display_startup_info, print_art, return_data_dir},
+    DBConfig, PersistenceConfig, TinyCache,
 };
 
 #[tokio::main]
@@ -61,42 +48,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Initialize TinyCache with the data directory and the database configurations
-    let db = Arc::new(TinyCache::new(data_dir, config.clone(), persist_config).await?);
+    let db = Arc::new(
+        TinyCache::builder(data_dir)
+            .config(config)
+            .persistence(persist_config)
+            .build()
+            .await?,
+    );
 
     display_startup_info(&db).await;
 
-    // Starting the TCP server using the db, port and host from the configuration
-    run_tcp_server(db, &config.host.to_string(), &config.port).await?;
+    // Serving TCP, and the HTTP API and Unix domain socket when configured
+    serve(db).await?;
 
     Ok(())
 }
-
-async fn run_tcp_server(db: Arc<TinyCache>, host: &str, port: &str) -> std::io::Result<()> {
-    let address = format!("{}:{}", host, port);
-    let listener = TcpListener::bind(&address).await?;
-
-    db.logger
-        .log_info(
-            &format!("Database listening on {}", address),
-            LogLevel::System,
-            &db,
-        )
-        .await?;
-
-    loop {
-        let (socket, addr) = listener.accept().await?;
-        let db = Arc::clone(&db);
-
-        db.logger
-            .log_info(
-                &format!("New connection from: {}", addr),
-                LogLevel::System,
-                &db,
-            )
-            .await?;
-
-        tokio::spawn(async move {
-            handle_client(socket, db).await;
-        });
-    }
-}
@@ -13,6 +13,7 @@
 ///      - `wal_segment_size`: Max size of a WAL file before rotation (e.g., 16MB).
 ///      - `wal_sync_policy`: Sync behavior ("always", "everysec", "no").
 ///      - `wal_max_segments`: Maximum number of WAL segments to retain per database.
+///      - `wal_enabled`: Turned off for in-memory only instances, nothing is logged or recovered.
 ///    - In `main.rs`, initialize with `PersistenceConfig::default(&data_dir)`.
 /// 2. **Integration**:
 ///    - `TinyCache::new_with_persistence` replaces `TinyCache::new`, setting up the `PersistenceManager`.
@@ -33,7 +34,10 @@
 ///    - For each database:
 ///      - **Replay WAL**: Scans all "wal-<db_name>-*.log" files, sorted by timestamp, and replays
 ///        operations (create, update, delete, etc.) to rebuild the complete in-memory state.
+///        Replayed operations are not logged again, they already are in the segments replayed.
 ///      - Skips corrupted or empty WAL lines, logging errors for transparency.
+///    - Databases written under the legacy `<user>:<password>@<database>` names are merged into
+///      `<database>` afterwards, and their files are removed.
 /// 2. **Data Consistency**:
 ///    - WAL uses a binary-compatible format (JSON with newline delimiters) to preserve `DataValue`
 ///      types (String, Json, etc.).
@@ -43,6 +47,15 @@
 ///    - Recovery is automatic on startup via `recover_all`.
 ///    - To inspect: Check files in `persist_dir` (e.g., "data/persist/") for WAL logs.
 ///
+/// ## Snapshots
+/// - With `snapshot_on_shutdown`, `TinyCache::shutdown` writes "snapshot-<db_name>.json" for every
+///   database once the servers are drained. The snapshot is written to a temporary file, synced
+///   and renamed, then the WAL segments it covers are deleted.
+/// - Snapshots also hold the JSON Schemas registered for the database, by key prefix, and the
+///   configuration of its vector index, whose graph is rebuilt from the vectors.
+/// - Recovery loads the snapshot (skipping expired entries) before replaying the WAL segments
+///   written after it.
+///
 /// ## Notes
 /// - **Performance**: WAL appends are fast (no seeks); segmented approach prevents single large files.
 /// - **Safety**: "everysec" sync balances speed and durability, losing at most 1s of data.
@@ -51,14 +64,28 @@
 /// - **Testing**: Simulate crashes, verify recovery with `view_data` to ensure data matches.
 ///
 use crate::{
-    db::db::{DataValue, TinyCache},
+    db::{
+        cache::{CacheEntryType, CacheValue},
+        db::{DataValue, TinyCache},
+        document::PatchOperation,
+        list::ListEnd,
+        schema::Schema,
+        sorted_set::AddOptions,
+        stream::StreamId,
+        vector::{IndexConfig, Metric, Vector, VectorIndex},
+    },
     utils::utils::compute_now_timestamp,
 };
 use dashmap::DashMap;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use std::{io, time::Duration};
 use tokio::{
     fs::{self, File, OpenOptions},
@@ -77,6 +104,13 @@ pub struct PersistenceConfig {
     pub wal_sync_policy: String,
     /// Maximum number of WAL segments to retain per database (0 = unlimited).
     pub wal_max_segments: u32,
+    /// Whether writes are logged at all, disabled for in-memory only instances.
+    #[serde(default = "default_wal_enabled")]
+    pub wal_enabled: bool,
+}
+
+fn default_wal_enabled() -> bool {
+    true
 }
 
 impl PersistenceConfig {
@@ -90,6 +124,7 @@ impl PersistenceConfig {
             wal_segment_size: 16 * 1024 * 1024,      // 16MB
             wal_sync_policy: "everysec".to_string(), // sync every second
             wal_max_segments: 10,                    // Keep last 10 segments per DB
+            wal_enabled: true,
         }
     }
 }
@@ -120,6 +155,199 @@ pub enum WalOperation {
         amount: f64,
     },
     DropDb,
+    LPush {
+        key: String,
+        values: Vec<String>,
+    },
+    RPush {
+        key: String,
+        values: Vec<String>,
+    },
+    LPop {
+        key: String,
+        count: usize,
+    },
+    RPop {
+        key: String,
+        count: usize,
+    },
+    LSet {
+        key: String,
+        index: i64,
+        value: String,
+    },
+    LRem {
+        key: String,
+        count: i64,
+        value: String,
+    },
+    LTrim {
+        key: String,
+        start: i64,
+        stop: i64,
+    },
+    LInsert {
+        key: String,
+        before: bool,
+        pivot: String,
+        value: String,
+    },
+    SAdd {
+        key: String,
+        members: Vec<String>,
+    },
+    SRem {
+        key: String,
+        members: Vec<String>,
+    },
+    SStore {
+        key: String,
+        members: Vec<String>,
+    }, // the result of a `*STORE` command
+    HSet {
+        key: String,
+        fields: Vec<(String, String)>,
+    },
+    HDel {
+        key: String,
+        fields: Vec<String>,
+    },
+    HIncrByFloat {
+        key: String,
+        field: String,
+        amount: f64,
+    },
+    ZAdd {
+        key: String,
+        members: Vec<(f64, String)>,
+        options: AddOptions,
+    },
+    ZIncrBy {
+        key: String,
+        member: String,
+        amount: f64,
+    },
+    ZRem {
+        key: String,
+        members: Vec<String>,
+    },
+    ZPopMin {
+        key: String,
+        count: usize,
+    },
+    ZPopMax {
+        key: String,
+        count: usize,
+    },
+    // the JSON document updates, paths are JSON Pointers
+    JsonSet {
+        key: String,
+        path: String,
+        value: JsonValue,
+    },
+    JsonDel {
+        key: String,
+        path: String,
+    },
+    JsonArrAppend {
+        key: String,
+        path: String,
+        values: Vec<JsonValue>,
+    },
+    JsonNumIncrBy {
+        key: String,
+        path: String,
+        amount: f64,
+    },
+    JsonMerge {
+        key: String,
+        patch: JsonValue,
+    },
+    JsonPatch {
+        key: String,
+        operations: Vec<PatchOperation>,
+    },
+    SetSchema {
+        prefix: String,
+        schema: JsonValue,
+    },
+    DropSchema {
+        prefix: String,
+    },
+    VIndex {
+        config: IndexConfig,
+    },
+    VAdd {
+        key: String,
+        vector: Vector,
+    },
+    VDel {
+        key: String,
+    },
+    XAdd {
+        key: String,
+        id: Option<StreamId>, // `None` for an ID generated from `time`
+        fields: Vec<(String, String)>,
+        max_len: Option<usize>,
+        time: u64,
+    },
+    XGroupCreate {
+        key: String,
+        group: String,
+        start: Option<StreamId>,
+        make_stream: bool,
+    },
+    XGroupDestroy {
+        key: String,
+        group: String,
+    },
+    XDeliver {
+        key: String,
+        group: String,
+        consumer: String,
+        after: Option<StreamId>,
+        count: Option<usize>,
+        time: u64,
+    },
+    XAck {
+        key: String,
+        group: String,
+        ids: Vec<StreamId>,
+    },
+    XClaim {
+        key: String,
+        group: String,
+        consumer: String,
+        min_idle: u64,
+        ids: Vec<StreamId>,
+        time: u64,
+    },
+    QPush {
+        key: String,
+        message: String,
+        delay: u64,
+        time: u64,
+    },
+    QPop {
+        key: String,
+        visibility: u64,
+        max_deliveries: u64,
+        time: u64,
+    },
+    QAck {
+        key: String,
+        receipt: String,
+    },
+    QNack {
+        key: String,
+        receipt: String,
+        delay: u64,
+        time: u64,
+    },
+    QRedrive {
+        key: String,
+        time: u64,
+    },
 }
 
 /// A single entry in the WAL, tied to a database and timestamped.
@@ -130,6 +358,26 @@ pub struct WalEntry {
     pub timestamp: u64,
 }
 
+/// The state of a database at the time it was snapshotted.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Snapshot {
+    pub database: String,
+    pub timestamp: u64,
+    pub entries: Vec<SnapshotEntry>,
+    #[serde(default)]
+    pub schemas: BTreeMap<String, JsonValue>, // the JSON Schemas of the database, by key prefix
+    #[serde(default)]
+    pub vector_index: Option<IndexConfig>, // the dimension and metric of the vector index
+}
+
+/// A single key of a snapshot, `expiry` is the absolute expiry time in seconds.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SnapshotEntry {
+    pub key: String,
+    pub value: DataValue,
+    pub expiry: Option<u64>,
+}
+
 /// Manages the Write-Ahead Log for a single database.
 pub struct WalManager {
     pub current_segment: File,
@@ -300,10 +548,28 @@ impl WalManager {
     }
 }
 
+/// Keeps operations from being logged while it lives, ends replaying however recovery ends.
+struct ReplayGuard<'a>(&'a AtomicBool);
+
+impl<'a> ReplayGuard<'a> {
+    fn new(replaying: &'a AtomicBool) -> Self {
+        replaying.store(true, Ordering::SeqCst);
+        ReplayGuard(replaying)
+    }
+}
+
+impl Drop for ReplayGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
 /// Manages WAL persistence for all databases in TinyCache.
 pub struct PersistenceManager {
     pub config: PersistenceConfig,
     pub wal_managers: Arc<DashMap<String, RwLock<WalManager>>>,
+    /// Set while recovery replays logged operations, which must not be logged a second time
+    replaying: AtomicBool,
 }
 
 impl PersistenceManager {
@@ -311,13 +577,16 @@ impl PersistenceManager {
     pub async fn new(config: PersistenceConfig) -> io::Result<Self> {
         info!("Initializing persistence manager with config: {:?}", config);
 
-        debug!("Creating persist directory: {:?}", config.persist_dir);
-        fs::create_dir_all(&config.persist_dir).await?;
+        if config.wal_enabled {
+            debug!("Creating persist directory: {:?}", config.persist_dir);
+            fs::create_dir_all(&config.persist_dir).await?;
+        }
 
         info!("Persistence manager successfully initialized");
         Ok(PersistenceManager {
             config,
             wal_managers: Arc::new(DashMap::new()),
+            replaying: AtomicBool::new(false),
         })
     }
 
@@ -353,6 +622,10 @@ impl PersistenceManager {
             db_name, operation
         );
 
+        if !self.config.wal_enabled || self.replaying.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
         self.ensure_wal(db_name).await?;
 
         let entry = WalEntry {
@@ -383,30 +656,236 @@ impl PersistenceManager {
         Ok(())
     }
 
-    /// Recovers a database from WAL segments.
-    pub async fn recover(&self, db_name: &str, tinycache: &TinyCache) -> io::Result<()> {
-        info!("Starting WAL recovery for database '{}'", db_name);
+    /// Path of the snapshot file of a database.
+    fn snapshot_path(&self, db_name: &str) -> PathBuf {
+        self.config
+            .persist_dir
+            .join(format!("snapshot-{}.json", db_name))
+    }
 
-        // Find all WAL files for this database
-        let wal_dir = &self.config.persist_dir;
-        let mut read_dir = fs::read_dir(wal_dir).await?;
+    /// Lists the WAL segments of a database, oldest first.
+    async fn wal_segments(&self, db_name: &str) -> io::Result<Vec<PathBuf>> {
+        let mut read_dir = fs::read_dir(&self.config.persist_dir).await?;
         let mut wal_files = Vec::new();
 
-        debug!("Scanning for WAL files in directory: {:?}", wal_dir);
         while let Some(entry) = read_dir.next_entry().await? {
             let path = entry.path();
-            if let Some(file_name) = path.file_name() {
-                if let Some(name_str) = file_name.to_str() {
-                    if name_str.starts_with(&format!("wal-{}-", db_name)) {
-                        debug!("Found WAL file for database '{}': {:?}", db_name, path);
-                        wal_files.push(path);
-                    }
+            if let Some(name_str) = path.file_name().and_then(|name| name.to_str()) {
+                if name_str.starts_with(&format!("wal-{}-", db_name)) {
+                    wal_files.push(path);
                 }
             }
         }
 
-        // Sort WAL files by timestamp (embedded in filename)
         wal_files.sort();
+        Ok(wal_files)
+    }
+
+    /// Writes a snapshot of a database and deletes the WAL segments it makes redundant.
+    ///
+    /// Writes made to the database while the snapshot is taken may be lost, so callers have to
+    /// stop the writers first (`TinyCache::shutdown` runs after the servers are drained).
+    pub async fn snapshot(&self, db_name: &str, tinycache: &TinyCache) -> io::Result<()> {
+        if !self.config.wal_enabled {
+            return Ok(());
+        }
+
+        info!("Writing snapshot for database '{}'", db_name);
+        let snapshot = Snapshot {
+            database: db_name.to_string(),
+            timestamp: compute_now_timestamp(),
+            entries: Self::live_entries(db_name, tinycache).await,
+            schemas: tinycache.schemas.list(db_name),
+            vector_index: tinycache
+                .vector_index_info(db_name)
+                .map(|(config, _)| config),
+        };
+        let serialized = serde_json::to_vec(&snapshot)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        // write-then-rename, so a crash never leaves a half written snapshot behind
+        let path = self.snapshot_path(db_name);
+        let tmp_path = path.with_extension("json.tmp");
+        let mut file = File::create(&tmp_path).await?;
+        file.write_all(&serialized).await?;
+        file.sync_all().await?;
+        fs::rename(&tmp_path, &path).await?;
+
+        // every logged operation is part of the snapshot now
+        self.remove_segments(db_name).await?;
+
+        info!(
+            "Snapshot of database '{}' written with {} entries",
+            db_name,
+            snapshot.entries.len()
+        );
+        Ok(())
+    }
+
+    /// Collects the unexpired key-value entries of a database.
+    async fn live_entries(db_name: &str, tinycache: &TinyCache) -> Vec<SnapshotEntry> {
+        let now = compute_now_timestamp();
+        let mut entries = Vec::new();
+
+        let cache = tinycache.get_cache(db_name).await;
+        let cache_lock = cache.read().await;
+        for shard in cache_lock.shards.iter() {
+            let shard_lock = shard.read().await;
+            for (cache_key, item) in shard_lock.iter() {
+                if cache_key.database != db_name
+                    || cache_key.entry_type != CacheEntryType::KeyValue
+                    || item.expiry.is_some_and(|expiry| now > expiry)
+                {
+                    continue;
+                }
+                let CacheValue::KeyValue(value, _) = &item.value;
+                entries.push(SnapshotEntry {
+                    key: cache_key.key.clone(),
+                    value: value.clone(),
+                    expiry: item.expiry,
+                });
+            }
+        }
+        entries
+    }
+
+    /// Closes the WAL of a database and removes its segments.
+    async fn remove_segments(&self, db_name: &str) -> io::Result<()> {
+        self.wal_managers.remove(db_name);
+        for segment in self.wal_segments(db_name).await? {
+            fs::remove_file(&segment).await?;
+        }
+        Ok(())
+    }
+
+    /// Moves a database stored under its legacy `<user>:<password>@<database>` name into
+    /// `<database>`. Keys that already exist there are kept, the legacy files are removed.
+    async fn migrate_legacy_database(
+        &self,
+        legacy_name: &str,
+        tinycache: &TinyCache,
+    ) -> io::Result<()> {
+        let Some((_, db_name)) = legacy_name.rsplit_once('@') else {
+            return Ok(());
+        };
+        self.recover(legacy_name, tinycache).await?;
+
+        let now = compute_now_timestamp();
+        let mut moved = 0;
+        for entry in Self::live_entries(legacy_name, tinycache).await {
+            if tinycache.get_key_value(db_name, &entry.key).await.is_some() {
+                continue;
+            }
+            match entry.expiry {
+                Some(expiry) => {
+                    tinycache
+                        .create_key_value_with_ttl(
+                            db_name,
+                            entry.key,
+                            entry.value,
+                            Duration::from_secs(expiry.saturating_sub(now)),
+                        )
+                        .await?
+                }
+                None => {
+                    tinycache
+                        .create_key_value(db_name, entry.key, entry.value)
+                        .await?
+                }
+            }
+            moved += 1;
+        }
+
+        tinycache.databases.remove(legacy_name);
+        self.remove_segments(legacy_name).await?;
+        match fs::remove_file(self.snapshot_path(legacy_name)).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        info!(
+            "Migrated {} entries of a legacy database into '{}'",
+            moved, db_name
+        );
+        Ok(())
+    }
+
+    /// Loads the snapshot of a database into the cache, returns whether one existed.
+    ///
+    /// Entries are inserted directly, they are already durable and must not be logged again.
+    async fn load_snapshot(&self, db_name: &str, tinycache: &TinyCache) -> io::Result<bool> {
+        let path = self.snapshot_path(db_name);
+        let contents = match fs::read(&path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        let snapshot: Snapshot = serde_json::from_slice(&contents)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let now = compute_now_timestamp();
+        let cache = tinycache.get_cache(db_name).await;
+        let mut cache_lock = cache.write().await;
+        let mut loaded = 0;
+        let mut vectors = Vec::new();
+        for entry in snapshot.entries {
+            let ttl = match entry.expiry {
+                Some(expiry) if expiry <= now => continue,
+                Some(expiry) => Some(Duration::from_secs(expiry - now)),
+                None => None,
+            };
+            if let DataValue::Vector(vector) = &entry.value {
+                vectors.push((entry.key.clone(), vector.values.clone()));
+            }
+            cache_lock
+                .insert_key_value(db_name, entry.key, entry.value, ttl)
+                .await;
+            loaded += 1;
+        }
+        drop(cache_lock);
+
+        // the vector index is rebuilt from the vectors, the first one sizes an unconfigured index
+        let config = snapshot.vector_index.or_else(|| {
+            vectors.first().map(|(_, values)| IndexConfig {
+                dimension: values.len(),
+                metric: Metric::Cosine,
+            })
+        });
+        if let Some(config) = config {
+            let mut index = VectorIndex::new(config);
+            for (key, values) in vectors {
+                if values.len() == config.dimension {
+                    index.insert(&key, values);
+                }
+            }
+            tinycache.vector_indexes.insert(db_name.to_string(), index);
+        }
+
+        for (prefix, schema) in snapshot.schemas {
+            match Schema::compile(schema) {
+                Ok(schema) => tinycache.schemas.register(db_name, &prefix, schema),
+                Err(e) => warn!(
+                    "Skipping the schema of prefix '{}' in database '{}': {}",
+                    prefix, db_name, e
+                ),
+            }
+        }
+
+        info!(
+            "Loaded snapshot of database '{}' taken at {} with {} entries",
+            db_name, snapshot.timestamp, loaded
+        );
+        Ok(true)
+    }
+
+    /// Recovers a database from its snapshot and WAL segments.
+    pub async fn recover(&self, db_name: &str, tinycache: &TinyCache) -> io::Result<()> {
+        info!("Starting WAL recovery for database '{}'", db_name);
+
+        self.load_snapshot(db_name, tinycache).await?;
+
+        // Find all WAL files for this database, sorted by the timestamp in their name
+        let wal_files = self.wal_segments(db_name).await?;
         info!(
             "Found {} WAL segments for database '{}'",
             wal_files.len(),
@@ -415,6 +894,9 @@ impl PersistenceManager {
 
         let mut total_operations = 0;
         let mut skipped_operations = 0;
+        // operations are replayed through the public writers, which would log them again and
+        // apply them twice on the next start
+        let _replaying = ReplayGuard::new(&self.replaying);
 
         // Replay all WAL segments in order
         for (index, path) in wal_files.iter().enumerate() {
@@ -540,7 +1022,235 @@ impl PersistenceManager {
                 tinycache.decrement_key_value(db_name, key, *amount).await?;
             }
             WalOperation::DropDb => {
-                tinycache.drop_db(db_name).await?;
+                tinycache.remove_db(db_name).await?;
+            }
+            // list writes that failed when logged fail the same way on replay, their error is
+            // part of the original outcome and not a replay error
+            WalOperation::LPush { key, values } => {
+                let _ = tinycache
+                    .list_push(db_name, key, values.clone(), ListEnd::Head)
+                    .await?;
+            }
+            WalOperation::RPush { key, values } => {
+                let _ = tinycache
+                    .list_push(db_name, key, values.clone(), ListEnd::Tail)
+                    .await?;
+            }
+            WalOperation::LPop { key, count } => {
+                let _ = tinycache
+                    .list_pop(db_name, key, *count, ListEnd::Head)
+                    .await?;
+            }
+            WalOperation::RPop { key, count } => {
+                let _ = tinycache
+                    .list_pop(db_name, key, *count, ListEnd::Tail)
+                    .await?;
+            }
+            WalOperation::LSet { key, index, value } => {
+                let _ = tinycache
+                    .list_set(db_name, key, *index, value.clone())
+                    .await?;
+            }
+            WalOperation::LRem { key, count, value } => {
+                let _ = tinycache
+                    .list_remove(db_name, key, *count, value.clone())
+                    .await?;
+            }
+            WalOperation::LTrim { key, start, stop } => {
+                let _ = tinycache.list_trim(db_name, key, *start, *stop).await?;
+            }
+            WalOperation::LInsert {
+                key,
+                before,
+                pivot,
+                value,
+            } => {
+                let _ = tinycache
+                    .list_insert(db_name, key, *before, pivot.clone(), value.clone())
+                    .await?;
+            }
+            WalOperation::SAdd { key, members } => {
+                let _ = tinycache.set_add(db_name, key, members.clone()).await?;
+            }
+            WalOperation::SRem { key, members } => {
+                let _ = tinycache.set_remove(db_name, key, members.clone()).await?;
+            }
+            WalOperation::SStore { key, members } => {
+                tinycache.replace_set(db_name, key, members.clone()).await?;
+            }
+            WalOperation::HSet { key, fields } => {
+                let _ = tinycache.hash_set(db_name, key, fields.clone()).await?;
+            }
+            WalOperation::HDel { key, fields } => {
+                let _ = tinycache.hash_delete(db_name, key, fields.clone()).await?;
+            }
+            WalOperation::HIncrByFloat { key, field, amount } => {
+                let _ = tinycache
+                    .hash_incr_by_float(db_name, key, field, *amount)
+                    .await?;
+            }
+            WalOperation::ZAdd {
+                key,
+                members,
+                options,
+            } => {
+                let _ = tinycache
+                    .sorted_set_add(db_name, key, members.clone(), *options)
+                    .await?;
+            }
+            WalOperation::ZIncrBy {
+                key,
+                member,
+                amount,
+            } => {
+                let _ = tinycache
+                    .sorted_set_incr_by(db_name, key, member, *amount)
+                    .await?;
+            }
+            WalOperation::ZRem { key, members } => {
+                let _ = tinycache
+                    .sorted_set_remove(db_name, key, members.clone())
+                    .await?;
+            }
+            WalOperation::ZPopMin { key, count } => {
+                let _ = tinycache
+                    .sorted_set_pop(db_name, key, *count, false)
+                    .await?;
+            }
+            WalOperation::ZPopMax { key, count } => {
+                let _ = tinycache.sorted_set_pop(db_name, key, *count, true).await?;
+            }
+            WalOperation::JsonSet { key, path, value } => {
+                let _ = tinycache
+                    .json_set(db_name, key, path, value.clone())
+                    .await?;
+            }
+            WalOperation::JsonDel { key, path } => {
+                let _ = tinycache.json_delete(db_name, key, path).await?;
+            }
+            WalOperation::JsonArrAppend { key, path, values } => {
+                let _ = tinycache
+                    .json_array_append(db_name, key, path, values.clone())
+                    .await?;
+            }
+            WalOperation::JsonNumIncrBy { key, path, amount } => {
+                let _ = tinycache
+                    .json_number_incr_by(db_name, key, path, *amount)
+                    .await?;
+            }
+            WalOperation::JsonMerge { key, patch } => {
+                let _ = tinycache.json_merge(db_name, key, patch.clone()).await?;
+            }
+            WalOperation::JsonPatch { key, operations } => {
+                let _ = tinycache
+                    .json_patch(db_name, key, operations.clone())
+                    .await?;
+            }
+            WalOperation::SetSchema { prefix, schema } => {
+                let _ = tinycache
+                    .set_schema(db_name, prefix, schema.clone())
+                    .await?;
+            }
+            WalOperation::DropSchema { prefix } => {
+                tinycache.drop_schema(db_name, prefix).await?;
+            }
+            WalOperation::VIndex { config } => {
+                let _ = tinycache.vector_index(db_name, *config).await?;
+            }
+            WalOperation::VAdd { key, vector } => {
+                let _ = tinycache.vector_add(db_name, key, vector.clone()).await?;
+            }
+            WalOperation::VDel { key } => {
+                let _ = tinycache.vector_delete(db_name, key).await?;
+            }
+            WalOperation::XAdd {
+                key,
+                id,
+                fields,
+                max_len,
+                time,
+            } => {
+                let _ = tinycache
+                    .stream_add(db_name, key, *id, fields.clone(), *max_len, *time)
+                    .await?;
+            }
+            WalOperation::XGroupCreate {
+                key,
+                group,
+                start,
+                make_stream,
+            } => {
+                let _ = tinycache
+                    .stream_create_group(db_name, key, group, *start, *make_stream)
+                    .await?;
+            }
+            WalOperation::XGroupDestroy { key, group } => {
+                let _ = tinycache.stream_destroy_group(db_name, key, group).await?;
+            }
+            WalOperation::XDeliver {
+                key,
+                group,
+                consumer,
+                after,
+                count,
+                time,
+            } => {
+                let _ = tinycache
+                    .stream_deliver(db_name, key, group, consumer, *after, *count, *time)
+                    .await?;
+            }
+            WalOperation::XAck { key, group, ids } => {
+                let _ = tinycache
+                    .stream_ack(db_name, key, group, ids.clone())
+                    .await?;
+            }
+            WalOperation::XClaim {
+                key,
+                group,
+                consumer,
+                min_idle,
+                ids,
+                time,
+            } => {
+                let _ = tinycache
+                    .stream_claim(db_name, key, group, consumer, *min_idle, ids.clone(), *time)
+                    .await?;
+            }
+            WalOperation::QPush {
+                key,
+                message,
+                delay,
+                time,
+            } => {
+                let _ = tinycache
+                    .queue_push(db_name, key, message.clone(), *delay, *time)
+                    .await?;
+            }
+            WalOperation::QPop {
+                key,
+                visibility,
+                max_deliveries,
+                time,
+            } => {
+                let _ = tinycache
+                    .queue_deliver(db_name, key, *visibility, *max_deliveries, *time)
+                    .await?;
+            }
+            WalOperation::QAck { key, receipt } => {
+                let _ = tinycache.queue_ack(db_name, key, receipt).await?;
+            }
+            WalOperation::QNack {
+                key,
+                receipt,
+                delay,
+                time,
+            } => {
+                let _ = tinycache
+                    .queue_nack(db_name, key, receipt, *delay, *time)
+                    .await?;
+            }
+            WalOperation::QRedrive { key, time } => {
+                let _ = tinycache.queue_redrive(db_name, key, *time).await?;
             }
         }
         Ok(())
@@ -615,8 +1325,28 @@ impl PersistenceManager {
         Ok(())
     }
 
+    /// Syncs the open WAL segment of every database to disk.
+    pub async fn sync_all(&self) -> io::Result<()> {
+        for wal in self.wal_managers.iter() {
+            let mut wal_lock = wal.write().await;
+            wal_lock.current_segment.flush().await?;
+            wal_lock.current_segment.sync_all().await?;
+            wal_lock.last_sync = compute_now_timestamp() / 1000;
+        }
+        info!(
+            "Synced WAL segments of {} databases",
+            self.wal_managers.len()
+        );
+        Ok(())
+    }
+
     /// Recovers all databases found in the persist directory.
     pub async fn recover_all(&self, tinycache: &TinyCache) -> io::Result<()> {
+        if !self.config.wal_enabled {
+            info!("WAL disabled, skipping recovery");
+            return Ok(());
+        }
+
         info!("Starting recovery for all databases");
 
         let wal_dir = &self.config.persist_dir;
@@ -628,7 +1358,12 @@ impl PersistenceManager {
             let path = entry.path();
             if let Some(file_name) = path.file_name() {
                 if let Some(name_str) = file_name.to_str() {
-                    if name_str.starts_with("wal-") && name_str.ends_with(".log") {
+                    if let Some(db_name) = name_str
+                        .strip_prefix("snapshot-")
+                        .and_then(|name| name.strip_suffix(".json"))
+                    {
+                        databases.insert(db_name.to_string());
+                    } else if name_str.starts_with("wal-") && name_str.ends_with(".log") {
                         // Extract database name from "wal-<db_name>-<timestamp>.log"
                         if let Some(db_part) = name_str.strip_prefix("wal-") {
                             if let Some(last_dash) = db_part.rfind('-') {
@@ -647,7 +1382,10 @@ impl PersistenceManager {
             databases
         );
 
-        // Recover each database
+        // Recover each database, legacy ones are merged into theirs once those are recovered
+        let (legacy, databases): (Vec<_>, Vec<_>) = databases
+            .into_iter()
+            .partition(|db_name| db_name.contains('@'));
         for db_name in databases {
             info!("Recovering database: '{}'", db_name);
             if let Err(e) = self.recover(&db_name, tinycache).await {
@@ -656,8 +1394,328 @@ impl PersistenceManager {
                 info!("Successfully recovered database: '{}'", db_name);
             }
         }
+        for legacy_name in legacy {
+            if let Err(e) = self.migrate_legacy_database(&legacy_name, tinycache).await {
+                error!("Failed to migrate a legacy database: {}", e);
+            }
+        }
 
         info!("Recovery completed for all databases");
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        db::{
+            db::{DataValue, TinyCache, ValueError},
+            list::ListEnd,
+            queue::QueueStats,
+            sorted_set::AddOptions,
+        },
+        security::config::DBConfig,
+        utils::utils::compute_now_millis,
+    };
+    use serde_json::json;
+    use std::{ops::Bound, time::Duration};
+
+    #[tokio::test]
+    async fn test_snapshot_replaces_wal_segments() {
+        let data_dir = std::env::temp_dir().join(format!("tinycache-{}", uuid::Uuid::new_v4()));
+        let config = DBConfig {
+            snapshot_on_shutdown: true,
+            ..DBConfig::default()
+        };
+
+        let db = TinyCache::builder(&data_dir)
+            .config(config.clone())
+            .build()
+            .await
+            .unwrap();
+        db.create_key_value("shop", "a".to_string(), DataValue::Json(json!(1)))
+            .await
+            .unwrap();
+        db.create_key_value_with_ttl(
+            "shop",
+            "b".to_string(),
+            DataValue::Json(json!({"x": true})),
+            Duration::from_secs(60),
+        )
+        .await
+        .unwrap();
+        db.delete_key_value("shop", "a").await.unwrap();
+        db.shutdown().await.unwrap();
+
+        let persist_dir = data_dir.join("data");
+        assert!(persist_dir.join("snapshot-shop.json").exists());
+        assert!(db
+            .persistence
+            .wal_segments("shop")
+            .await
+            .unwrap()
+            .is_empty());
+
+        let db = TinyCache::builder(&data_dir)
+            .config(config)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(db.get_key_value("shop", "a").await, None);
+        assert_eq!(
+            db.get_key_value("shop", "b").await,
+            Some(DataValue::Json(json!({"x": true})))
+        );
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test]
+    async fn test_replayed_list_writes_are_not_logged_again() {
+        let data_dir = std::env::temp_dir().join(format!("tinycache-{}", uuid::Uuid::new_v4()));
+        let mut db = TinyCache::builder(&data_dir).build().await.unwrap();
+        db.list_push("shop", "cart", vec!["a".to_string()], ListEnd::Tail)
+            .await
+            .unwrap()
+            .unwrap();
+        db.list_insert("shop", "cart", true, "a".to_string(), "b".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+
+        // every start replays the WAL, which must not grow with the replayed operations
+        for _ in 0..2 {
+            drop(db);
+            db = TinyCache::builder(&data_dir).build().await.unwrap();
+            let items = db
+                .read_list("shop", "cart", |list| {
+                    list.iter().cloned().collect::<Vec<_>>()
+                })
+                .await;
+            assert_eq!(items, Ok(vec!["b".to_string(), "a".to_string()]));
+        }
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test]
+    async fn test_hash_increments_survive_restarts() {
+        let data_dir = std::env::temp_dir().join(format!("tinycache-{}", uuid::Uuid::new_v4()));
+        let mut db = TinyCache::builder(&data_dir).build().await.unwrap();
+        db.hash_set(
+            "shop",
+            "stock",
+            vec![("apples".to_string(), "1".to_string())],
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        db.hash_incr_by_float("shop", "stock", "apples", 1.5)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // increments are not idempotent, each start has to apply them exactly once
+        for _ in 0..2 {
+            drop(db);
+            db = TinyCache::builder(&data_dir).build().await.unwrap();
+            let apples = db
+                .read_hash("shop", "stock", |fields| fields.get("apples").cloned())
+                .await;
+            assert_eq!(apples, Ok(Some("2.5".to_string())));
+        }
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test]
+    async fn test_sorted_set_increments_survive_restarts() {
+        let data_dir = std::env::temp_dir().join(format!("tinycache-{}", uuid::Uuid::new_v4()));
+        let mut db = TinyCache::builder(&data_dir).build().await.unwrap();
+        db.sorted_set_add(
+            "shop",
+            "ranking",
+            vec![(1.0, "alice".to_string())],
+            AddOptions::default(),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        db.sorted_set_incr_by("shop", "ranking", "alice", 2.0)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // increments are not idempotent, each start has to apply them exactly once
+        for _ in 0..2 {
+            drop(db);
+            db = TinyCache::builder(&data_dir).build().await.unwrap();
+            let score = db
+                .read_sorted_set("shop", "ranking", |set| (set.len(), set.score("alice")))
+                .await;
+            assert_eq!(score, Ok((1, Some(3.0))));
+        }
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test]
+    async fn test_json_updates_survive_restarts() {
+        let data_dir = std::env::temp_dir().join(format!("tinycache-{}", uuid::Uuid::new_v4()));
+        let mut db = TinyCache::builder(&data_dir).build().await.unwrap();
+        db.create_key_value(
+            "shop",
+            "order".to_string(),
+            DataValue::Json(json!({"total": 10, "items": []})),
+        )
+        .await
+        .unwrap();
+        db.json_array_append("shop", "order", "/items", vec![json!("pen")])
+            .await
+            .unwrap()
+            .unwrap();
+        db.json_number_incr_by("shop", "order", "/total", 5.0)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // appends and increments are not idempotent, each start has to apply them exactly once
+        for _ in 0..2 {
+            drop(db);
+            db = TinyCache::builder(&data_dir).build().await.unwrap();
+            assert_eq!(
+                db.get_key_value("shop", "order").await,
+                Some(DataValue::Json(json!({"total": 15, "items": ["pen"]})))
+            );
+        }
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test]
+    async fn test_stream_pending_entries_survive_restart() {
+        let data_dir = std::env::temp_dir().join(format!("tinycache-{}", uuid::Uuid::new_v4()));
+        let db = TinyCache::builder(&data_dir).build().await.unwrap();
+        db.stream_create_group("shop", "orders", "workers", None, true)
+            .await
+            .unwrap()
+            .unwrap();
+        let fields = vec![("event".to_string(), "paid".to_string())];
+        let id = db
+            .stream_add("shop", "orders", None, fields, None, compute_now_millis())
+            .await
+            .unwrap()
+            .unwrap();
+        let streams = vec![("orders".to_string(), None)];
+        let delivered = db
+            .stream_read_group("shop", "workers", "w1", streams, None, None)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(delivered[0].1[0].id, id);
+
+        // no snapshot, the instance is rebuilt from the WAL alone
+        drop(db);
+        let db = TinyCache::builder(&data_dir).build().await.unwrap();
+        let pending = db
+            .read_stream("shop", "orders", |stream| {
+                let (start, end) = (Bound::Unbounded, Bound::Unbounded);
+                stream.pending("workers", start, end, 10, None, compute_now_millis())
+            })
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].id, pending[0].consumer.as_str()), (id, "w1"));
+        let claimed = db
+            .stream_claim(
+                "shop",
+                "orders",
+                "workers",
+                "w2",
+                0,
+                vec![id],
+                compute_now_millis(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(claimed, delivered[0].1);
+
+        // a second start replays the same entry and claim and nothing else
+        drop(db);
+        let db = TinyCache::builder(&data_dir).build().await.unwrap();
+        let (len, pending) = db
+            .read_stream("shop", "orders", |stream| {
+                let (start, end) = (Bound::Unbounded, Bound::Unbounded);
+                let pending = stream.pending("workers", start, end, 10, None, compute_now_millis());
+                (stream.len(), pending)
+            })
+            .await
+            .unwrap();
+        let pending = pending.unwrap();
+        assert_eq!(len, 1);
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].id, pending[0].consumer.as_str()), (id, "w2"));
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+
+    #[tokio::test]
+    async fn test_queue_deliveries_survive_restart() {
+        let data_dir = std::env::temp_dir().join(format!("tinycache-{}", uuid::Uuid::new_v4()));
+        let db = TinyCache::builder(&data_dir).build().await.unwrap();
+        db.queue_push("shop", "emails", "welcome".to_string(), 0, 1000)
+            .await
+            .unwrap()
+            .unwrap();
+        let delivery = db
+            .queue_pop("shop", "emails", Some(30_000), 1000)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+
+        // no snapshot, the instance is rebuilt from the WAL alone
+        drop(db);
+        let db = TinyCache::builder(&data_dir).build().await.unwrap();
+        assert_eq!(
+            db.queue_pop("shop", "emails", None, 30_999).await.unwrap(),
+            Ok(None)
+        );
+        let again = db
+            .queue_pop("shop", "emails", None, 31_000)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        assert_eq!((again.id, again.deliveries), (delivery.id, 2));
+        assert_eq!(again.message, "welcome");
+        assert_eq!(
+            db.queue_ack("shop", "emails", &delivery.receipt)
+                .await
+                .unwrap(),
+            Err(ValueError::InvalidReceipt)
+        );
+
+        // a second start replays the same deliveries and nothing else
+        drop(db);
+        let db = TinyCache::builder(&data_dir).build().await.unwrap();
+        let stats = db
+            .read_queue("shop", "emails", |queue| (queue.len(), queue.stats(31_000)))
+            .await
+            .unwrap();
+        assert_eq!(
+            stats,
+            (
+                1,
+                QueueStats {
+                    in_flight: 1,
+                    ..QueueStats::default()
+                }
+            )
+        );
+
+        let _ = std::fs::remove_dir_all(&data_dir);
+    }
+}
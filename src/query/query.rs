@@ -84,53 +84,20 @@
 //!
 use std::collections::{HashMap, HashSet};
 
-use serde::{Deserialize, Serialize};
 use serde_json::{json, Value as JsonValue};
 
-use crate::db::{
-    cache::CacheValue,
-    db::{DataValue, TinyCache},
-};
-
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub enum AggregationOperation {
-    Count,
-    Sum(String),
-    Average(String),
-    GroupBy(String),
-    Filter(FilterCondition),
-    Min(String),
-    Max(String),
-    Distinct(String),
-    TopN {
-        field: String,
-        n: usize,
-    },
-    BottomN {
-        field: String,
-        n: usize,
-    },
-    Median(String),
-    StdDev(String),
-    Sort {
-        field: String,
-        descending: bool,
+use crate::{
+    db::{
+        cache::CacheValue,
+        db::{DataValue, TinyCache},
     },
-    Join {
-        source_key: String,
-        source_field: String,
-        target_field: String,
-    },
-}
+    security::acl::KeyAcl,
+};
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
-pub struct FilterCondition {
-    pub field: String,    // field path in value
-    pub operator: String, // "eq", "gt", "lt", "gte", "lte", "contains"
-    pub value: JsonValue,
-}
+// The operation types are part of the wire protocol so that clients can build queries
+pub use tinycache_protocol::query::{AggregationOperation, FilterCondition};
 
-fn apply_filter(doc: &JsonValue, condition: &FilterCondition) -> bool {
+pub(crate) fn apply_filter(doc: &JsonValue, condition: &FilterCondition) -> bool {
     if let Some(field_value) = doc.pointer(&condition.field) {
         match condition.operator.as_str() {
             "eq" => field_value == &condition.value,
@@ -224,9 +191,11 @@ fn apply_filter(doc: &JsonValue, condition: &FilterCondition) -> bool {
 //     }
 // }
 
+/// *aggregate* runs the operations over the JSON values of the keys the ACL allows
 pub async fn aggregate(
     database: &str,
     operations: Vec<AggregationOperation>,
+    acl: &KeyAcl,
     db: &TinyCache,
 ) -> JsonValue {
     let cache = db.get_cache(database).await;
@@ -239,7 +208,7 @@ pub async fn aggregate(
         filtered_data.extend(
             shard_guard
                 .iter()
-                .filter(|(k, _)| k.database == database)
+                .filter(|(k, _)| k.database == database && acl.allows(&k.key))
                 .filter_map(|(_, item)| match &item.value {
                     CacheValue::KeyValue(entry, _) => {
                         if let DataValue::Json(json) = &entry {
@@ -452,7 +421,11 @@ pub async fn aggregate(
                         data.extend(
                             shard_guard
                                 .iter()
-                                .filter(|(k, _)| k.database == database && k.key == source_key)
+                                .filter(|(k, _)| {
+                                    k.database == database
+                                        && k.key == source_key
+                                        && acl.allows(&k.key)
+                                })
                                 .filter_map(|(_, item)| match &item.value {
                                     CacheValue::KeyValue(entry, _) => {
                                         if let DataValue::Json(json) = entry {
@@ -1,78 +1,154 @@
-use crate::{db::db::TinyCache, security::auth::Session, utils::logs::LogLevel};
-use std::sync::Arc;
+use crate::{
+    db::{
+        db::TinyCache,
+        pubsub::{Delivery, Message, Subscriber},
+    },
+    security::auth::{session_fingerprint, Session},
+    utils::{
+        logs::LogLevel,
+        response::{Response, ResponseData},
+    },
+};
+use serde::Serialize;
+use serde_json::json;
+use std::{future, io, sync::Arc, time::Duration};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
-    net::TcpStream,
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    time::sleep,
+};
+
+use super::{
+    connections::ConnectionGuard,
+    listener::{ClientStream, Peer},
+    requests::{process_requests, process_subscription_request},
 };
 
-use super::requests::process_requests;
+/// Upper bound for a single request line, protects the server from unbounded buffering
+const MAX_REQUEST_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Line sent to clients that are waiting for their next request when the server shuts down
+const SHUTDOWN_MESSAGE: &[u8] = b"Server is shutting down.\n";
+
+/// Line sent before closing a connection that sent nothing for `idle_timeout_secs`
+const IDLE_TIMEOUT_MESSAGE: &[u8] = b"Idle timeout. Please reconnect.\n";
+
+/// Line sent before closing a connection whose session expired or that reached
+/// `max_session_duration_secs`
+const SESSION_EXPIRED_MESSAGE: &[u8] = b"Session expired. Please reconnect.\n";
+
+/// Line sent before closing a connection on `CLIENT KILL`
+const KILLED_MESSAGE: &[u8] = b"Connection killed by an administrator.\n";
+
+/// Line sent before closing a subscribed connection that fell behind, with
+/// `pubsub_slow_consumer = "disconnect"`
+const SLOW_CONSUMER_MESSAGE: &[u8] = b"Slow consumer. Please reconnect.\n";
+
+/// Lines pushed to subscribed connections, tagged like the WebSocket pushes so that clients can
+/// tell them apart from command responses
+#[derive(Serialize)]
+#[serde(tag = "push", content = "data", rename_all = "snake_case")]
+enum PushMessage {
+    Message(Message),
+    Dropped { messages: u64 },
+}
 
 /// *handle_client* handles a single client connection and continuously reads requests from the client
 ///
 /// Each requests is processed using the process_request function and
 /// Sends responses back to the client
 /// Closes the connection when the client disconnects or an error occurs
-pub async fn handle_client(mut socket: TcpStream, db: Arc<TinyCache>) {
+pub async fn handle_client(mut socket: Box<dyn ClientStream>, peer: Peer, db: Arc<TinyCache>) {
     // handle client function will operate different based on the deployment mode.
     // if deployment mode is standalone, then we will have to check if the connected client has
     // the neccessary credentials/access and authenticate them
-    let mut connections = db.active_connections.write().await;
+    // The guard holds the connection slot and gives it back however this function returns
+    let connection = match db
+        .connections
+        .register(peer.to_string(), db.config.max_connections)
+    {
+        Some(connection) => connection,
+        None => {
+            let _ = socket.write_all(b"ERROR: Max connections reached\n").await;
+            let _ = db
+                .logger
+                .log_error(
+                    "Max connections reached, rejecting new client",
+                    LogLevel::System,
+                    &db,
+                )
+                .await;
+            return;
+        }
+    };
 
-    if *connections >= db.config.max_connections {
-        let _ = socket.write_all(b"ERROR: Max connections reached\n").await;
+    // now validting worker threads availability
+    if let Err(e) = db.config.validate() {
+        let _ = socket.write_all(format!("ERROR: {}\n", e).as_bytes()).await;
         let _ = db
             .logger
             .log_error(
-                "Max connections reached, rejecting new client",
+                &format!("Worker thread validation failed: {}", e),
                 LogLevel::System,
                 &db,
             )
             .await;
         return;
     }
-    *connections += 1;
-    drop(connections);
 
-    // now validting worker threads availability
-    if let Err(e) = db.config.validate() {
-        let _ = socket.write_all(format!("ERROR: {}\n", e).as_bytes()).await;
-        let _ = db.logger.log_error(
-            &format!("Worker thread validation failed: {}", e),
-            LogLevel::System,
-            &db,
-        );
-        return;
-    }
+    let mut socket = BufReader::new(socket);
+    let mut line = Vec::new();
 
-    let mut buffer = [0; 1024];
-    let peer_addr = socket
-        .peer_addr()
-        .unwrap_or_else(|_| "Unknown".parse().unwrap());
-
-    match socket.read(&mut buffer).await {
-        Ok(n) => {
-            let connection_str = String::from_utf8_lossy(&buffer[..n]).trim().to_string();
-
-            // Attempting authentication
-            match db
-                .auth_manager
-                .authenticate(&connection_str, db.clone())
-                .await
-            {
+    let connection_str = tokio::select! {
+        biased;
+        _ = db.shutdown_requested() => {
+            let _ = socket.get_mut().write_all(SHUTDOWN_MESSAGE).await;
+            Ok(None)
+        }
+        _ = idle_timeout(&db) => {
+            let _ = socket.get_mut().write_all(IDLE_TIMEOUT_MESSAGE).await;
+            Ok(None)
+        }
+        result = read_request(&mut socket, &mut line) => result,
+    };
+
+    match connection_str {
+        Ok(Some(connection_str)) => {
+            // Attempting authentication, trusted unix socket peers are not asked for a password
+            // and clients that reconnect may pick up their session with `RESUME <session-id>`
+            let auth_result = if let Some(session_id) = connection_str.strip_prefix("RESUME ") {
+                db.auth_manager
+                    .resume(session_id.trim(), &peer, db.clone())
+                    .await
+            } else if peer.is_trusted(&db.config) {
+                db.auth_manager
+                    .authenticate_trusted(&connection_str, &peer, db.clone())
+                    .await
+            } else {
+                db.auth_manager
+                    .authenticate(&connection_str, &peer, db.clone())
+                    .await
+            };
+
+            match auth_result {
                 Ok(session) => {
+                    connection.set_session(&session.username, &session.database);
+
                     // Send success response with session ID
                     let response = format!("AUTH OK {}\n", session.id);
 
                     let _ = db
                         .logger
                         .log_info(
-                            &format!("Auth Response  {}", response),
+                            &format!(
+                                "Auth Response  AUTH OK {}",
+                                session_fingerprint(&session.id)
+                            ),
                             LogLevel::System,
                             &db,
                         )
                         .await;
 
-                    if let Err(e) = socket.write_all(response.as_bytes()).await {
+                    if let Err(e) = socket.get_mut().write_all(response.as_bytes()).await {
                         let _ = db
                             .logger
                             .log_error(
@@ -84,23 +160,23 @@ pub async fn handle_client(mut socket: TcpStream, db: Arc<TinyCache>) {
                         return;
                     }
 
-                    handle_authenticated_requests(socket, db.clone(), session).await;
+                    handle_authenticated_requests(socket, db.clone(), session, connection).await;
                 }
                 Err(e) => {
                     let response = format!("AUTH ERROR {}\n", e);
-                    let _ = socket.write_all(response.as_bytes()).await;
+                    let _ = socket.get_mut().write_all(response.as_bytes()).await;
                     let _ = db
                         .logger
                         .log_warn(
-                            &format!("Authentication failed for {}: {}", peer_addr, e),
+                            &format!("Authentication failed for {}: {}", peer, e),
                             LogLevel::Application,
                             &db,
                         )
                         .await;
-                    return;
                 }
             }
         }
+        Ok(None) => {}
         Err(e) => {
             let _ = db
                 .logger
@@ -110,38 +186,103 @@ pub async fn handle_client(mut socket: TcpStream, db: Arc<TinyCache>) {
                     &db,
                 )
                 .await;
-            return;
         }
     }
-
-    let mut connections = db.active_connections.write().await;
-    *connections -= 1;
 }
 
-async fn handle_authenticated_requests(mut socket: TcpStream, db: Arc<TinyCache>, session: Session) {
-    let mut buffer = [0; 1024];
+async fn handle_authenticated_requests(
+    mut socket: BufReader<Box<dyn ClientStream>>,
+    db: Arc<TinyCache>,
+    session: Session,
+    connection: ConnectionGuard,
+) {
+    let max_duration = db.config.max_session_duration_secs;
+    let session_deadline = sleep(Duration::from_secs(max_duration));
+    tokio::pin!(session_deadline);
+    let mut buckets = db.rate_limiter.connection_buckets();
+    let mut subscriber: Option<Subscriber> = None;
+    // a request that is partly read when a push is written is completed by the next read
+    let mut line = Vec::new();
+
     loop {
+        // subscribed connections wait for pushes, they are never idle
+        let subscribed = subscriber
+            .as_ref()
+            .is_some_and(|subscriber| subscriber.count() > 0);
+        // a request that is being processed always completes, the shutdown, kills and timeouts
+        // are only noticed while waiting for the next one
         tokio::select! {
-            result = socket.read(&mut buffer) => {
+            biased;
+            _ = db.shutdown_requested() => {
+                let _ = socket.get_mut().write_all(SHUTDOWN_MESSAGE).await;
+                break;
+            }
+            _ = connection.killed() => {
+                let _ = socket.get_mut().write_all(KILLED_MESSAGE).await;
+                let _ = db.logger.log_info(&format!("Connection {} killed", connection.id()), LogLevel::System, &db).await;
+                break;
+            }
+            _ = &mut session_deadline, if max_duration > 0 => {
+                let _ = socket.get_mut().write_all(SESSION_EXPIRED_MESSAGE).await;
+                break;
+            }
+            _ = idle_timeout(&db), if !subscribed => {
+                let _ = socket.get_mut().write_all(IDLE_TIMEOUT_MESSAGE).await;
+                break;
+            }
+            delivery = next_delivery(&mut subscriber) => {
+                let push = match delivery {
+                    Delivery::Message(message) => PushMessage::Message(message),
+                    Delivery::Dropped(messages) => PushMessage::Dropped { messages },
+                    Delivery::Disconnected => {
+                        let _ = socket.get_mut().write_all(SLOW_CONSUMER_MESSAGE).await;
+                        let _ = db.logger.log_warn(&format!("Connection {} disconnected as a slow pub/sub consumer", connection.id()), LogLevel::System, &db).await;
+                        break;
+                    }
+                };
+                let push = serde_json::to_string(&push).unwrap() + "\r\n";
+                if let Err(e) = socket.get_mut().write_all(push.as_bytes()).await {
+                    let _ = db.logger.log_error(&format!("Failed to write push: {}", e), LogLevel::System, &db).await;
+                    break;
+                }
+            }
+            result = read_request(&mut socket, &mut line) => {
                 match result {
-                    Ok(0) => break,
-                    Ok(n) => {
-                        let request = String::from_utf8_lossy(&buffer[..n]).trim().to_string();
+                    Ok(None) => break,
+                    Ok(Some(request)) if request.is_empty() => continue,
+                    Ok(Some(request)) => {
+                        // only the command is recorded, without the connection string in front
+                        let command = match request.split_once(' ') {
+                            Some((connection_string, command)) if connection_string.starts_with("tinycache://") => command,
+                            _ => request.as_str(),
+                        };
+                        connection.record_command(command);
+                        let logout = command.trim() == "LOGOUT";
 
                         // for authenticated services, have to validate their sessions to make sure everything is good
                         if db.auth_manager.validate_session(&session.id, db.clone()).await.is_none() {
-                           let _ = socket.write_all(b"Session expired. Please reconnect.\n").await;
+                           let _ = socket.get_mut().write_all(SESSION_EXPIRED_MESSAGE).await;
                             break;
                         }
 
-                        // once we are validate, send the request, session to the process_request function which handles all requests
+                        // requests over a limit are answered without running them
+                        let response = match db.rate_limiter.check(&mut buckets, &session.username, &session.namespace, request.len()) {
+                            Ok(()) => match process_subscription_request(&request, &session, &db, &mut subscriber).await {
+                                Some(response) => response,
+                                // once we are validate, send the request, session to the process_request function which handles all requests
+                                None => process_requests(request, &session, &db).await,
+                            },
+                            Err(retry_after) => rate_limited(retry_after).to_string(),
+                        };
 
-                        let response = process_requests(request, &db).await;
-
-                        if let Err(e) = socket.write_all(response.as_bytes()).await {
+                        if let Err(e) = socket.get_mut().write_all(response.as_bytes()).await {
                             let _ = db.logger.log_error(&format!("Failed to write response: {}", e), LogLevel::System, &db).await;
                             break;
                         }
+                        // the session is gone, nothing else can run on this connection
+                        if logout {
+                            break;
+                        }
                     }
                     Err(e) => {
                         let _ = db.logger.log_error(&format!("Failed to read from socket: {}", e), LogLevel::System, &db).await;
@@ -152,3 +293,96 @@ async fn handle_authenticated_requests(mut socket: TcpStream, db: Arc<TinyCache>
         }
     }
 }
+
+/// *rate_limited* is the answer to a request over a rate limit, with a hint on when to retry
+pub(super) fn rate_limited(retry_after: Duration) -> Response {
+    Response {
+        data: Some(ResponseData::Json(json!({
+            "retry_after_ms": retry_after.as_millis().max(1),
+        }))),
+        ..Response::error("RATE_LIMITED")
+    }
+}
+
+/// *next_delivery* waits for the next push of the subscriber, never for connections that did not
+/// subscribe
+async fn next_delivery(subscriber: &mut Option<Subscriber>) -> Delivery {
+    match subscriber {
+        Some(subscriber) => subscriber.next().await,
+        None => future::pending().await,
+    }
+}
+
+/// *idle_timeout* resolves after `idle_timeout_secs`, never when the timeout is disabled
+async fn idle_timeout(db: &TinyCache) {
+    match db.config.idle_timeout_secs {
+        0 => future::pending().await,
+        secs => sleep(Duration::from_secs(secs)).await,
+    }
+}
+
+/// *read_request* reads the next newline terminated request from the client
+///
+/// Requests are framed by `\n` so that clients can pipeline several commands in a single write.
+/// The bytes read so far are kept in `line` until the request is complete, so a read cancelled by
+/// another branch of a `select!` continues where it stopped on the next call.
+/// Returns `Ok(None)` once the client closed the connection.
+async fn read_request(
+    socket: &mut BufReader<Box<dyn ClientStream>>,
+    line: &mut Vec<u8>,
+) -> io::Result<Option<String>> {
+    let limit = MAX_REQUEST_SIZE.saturating_sub(line.len() as u64);
+    let n = socket.take(limit).read_until(b'\n', line).await?;
+
+    if n == 0 && line.is_empty() {
+        return Ok(None);
+    }
+    if line.len() as u64 >= MAX_REQUEST_SIZE && !line.ends_with(b"\n") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request exceeds the maximum request size",
+        ));
+    }
+
+    let request = String::from_utf8_lossy(line).trim().to_string();
+    line.clear();
+    Ok(Some(request))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::pubsub::{PubSub, SlowConsumerPolicy};
+    use tokio::io::duplex;
+
+    #[tokio::test]
+    async fn test_push_during_partial_request() {
+        let (mut client, server) = duplex(64);
+        let mut socket = BufReader::new(Box::new(server) as Box<dyn ClientStream>);
+        let mut line = Vec::new();
+        let pubsub = PubSub::new(8, SlowConsumerPolicy::Drop);
+        let mut subscriber = Some(pubsub.subscriber());
+        subscriber
+            .as_mut()
+            .unwrap()
+            .subscribe("shop", &["news"], false);
+
+        // the first half of the request is read before the push wins the select
+        client.write_all(b"UNSUB").await.unwrap();
+        pubsub.publish("shop", "news", "hello");
+        tokio::select! {
+            biased;
+            _ = read_request(&mut socket, &mut line) => panic!("the request is not complete"),
+            delivery = next_delivery(&mut subscriber) => {
+                assert!(matches!(delivery, Delivery::Message(_)));
+            }
+        }
+
+        client.write_all(b"SCRIBE news\n").await.unwrap();
+        let request = read_request(&mut socket, &mut line).await.unwrap();
+        assert_eq!(request.as_deref(), Some("UNSUBSCRIBE news"));
+
+        drop(client);
+        assert_eq!(read_request(&mut socket, &mut line).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,213 @@
+use dashmap::DashMap;
+use serde::Serialize;
+use std::sync::{
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    Arc, Mutex,
+};
+use tokio::sync::Notify;
+
+use crate::utils::utils::compute_now_timestamp;
+
+/// What `CLIENT LIST` reports about a connection
+#[derive(Debug, Clone, Serialize)]
+pub struct ConnectionInfo {
+    pub id: u64,
+    pub peer: String,
+    pub user: Option<String>,
+    pub database: Option<String>,
+    pub age_secs: u64,
+    pub idle_secs: u64,
+    pub last_command: Option<String>,
+}
+
+struct ConnectionState {
+    peer: String,
+    user: Option<String>,
+    database: Option<String>,
+    connected_at: u64,
+    last_active: u64,
+    last_command: Option<String>,
+}
+
+struct ConnectionEntry {
+    state: Mutex<ConnectionState>,
+    kill: Arc<Notify>,
+}
+
+/// Registry of the client connections served over TCP and the Unix domain socket
+///
+/// Slots are taken with `register`, which hands out a `ConnectionGuard` that gives the slot
+/// back when it is dropped, whichever way the connection ends.
+pub struct ConnectionRegistry {
+    next_id: AtomicU64,
+    active: AtomicUsize,
+    connections: DashMap<u64, ConnectionEntry>,
+}
+
+impl Default for ConnectionRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ConnectionRegistry {
+    pub fn new() -> Self {
+        Self {
+            next_id: AtomicU64::new(1),
+            active: AtomicUsize::new(0),
+            connections: DashMap::new(),
+        }
+    }
+
+    /// *register* takes a connection slot, `None` if `max_connections` are already in use
+    pub fn register(
+        self: &Arc<Self>,
+        peer: String,
+        max_connections: usize,
+    ) -> Option<ConnectionGuard> {
+        self.active
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |active| {
+                (active < max_connections).then_some(active + 1)
+            })
+            .ok()?;
+
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let now = compute_now_timestamp();
+        let kill = Arc::new(Notify::new());
+        self.connections.insert(
+            id,
+            ConnectionEntry {
+                state: Mutex::new(ConnectionState {
+                    peer,
+                    user: None,
+                    database: None,
+                    connected_at: now,
+                    last_active: now,
+                    last_command: None,
+                }),
+                kill: Arc::clone(&kill),
+            },
+        );
+
+        Some(ConnectionGuard {
+            id,
+            kill,
+            registry: Arc::clone(self),
+        })
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.load(Ordering::Acquire)
+    }
+
+    /// *list* returns the open connections ordered by id
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let now = compute_now_timestamp();
+        let mut connections: Vec<ConnectionInfo> = self
+            .connections
+            .iter()
+            .map(|entry| {
+                let state = entry.state.lock().unwrap();
+                ConnectionInfo {
+                    id: *entry.key(),
+                    peer: state.peer.clone(),
+                    user: state.user.clone(),
+                    database: state.database.clone(),
+                    age_secs: now.saturating_sub(state.connected_at),
+                    idle_secs: now.saturating_sub(state.last_active),
+                    last_command: state.last_command.clone(),
+                }
+            })
+            .collect();
+        connections.sort_by_key(|connection| connection.id);
+        connections
+    }
+
+    /// *kill* asks the connection to close, returns `false` if there is no such connection
+    pub fn kill(&self, id: u64) -> bool {
+        match self.connections.get(&id) {
+            Some(entry) => {
+                entry.kill.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn update(&self, id: u64, update: impl FnOnce(&mut ConnectionState)) {
+        if let Some(entry) = self.connections.get(&id) {
+            update(&mut entry.state.lock().unwrap());
+        }
+    }
+}
+
+/// A registered connection, removed from the registry when dropped
+pub struct ConnectionGuard {
+    id: u64,
+    kill: Arc<Notify>,
+    registry: Arc<ConnectionRegistry>,
+}
+
+impl ConnectionGuard {
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// *set_session* records who the connection authenticated as
+    pub fn set_session(&self, user: &str, database: &str) {
+        self.registry.update(self.id, |state| {
+            state.user = Some(user.to_string());
+            state.database = Some(database.to_string());
+        });
+    }
+
+    /// *record_command* marks the connection active. Only the command name is kept, arguments
+    /// can hold values that admins listing connections should not see.
+    pub fn record_command(&self, command: &str) {
+        let name = command.split_whitespace().next().map(str::to_uppercase);
+        self.registry.update(self.id, |state| {
+            state.last_active = compute_now_timestamp();
+            state.last_command = name;
+        });
+    }
+
+    /// *killed* resolves once `CLIENT KILL` was issued for this connection
+    pub async fn killed(&self) {
+        self.kill.notified().await
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.registry.connections.remove(&self.id);
+        self.registry.active.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guard_releases_slot() {
+        let registry = Arc::new(ConnectionRegistry::new());
+
+        let first = registry.register("a".to_string(), 2).unwrap();
+        let second = registry.register("b".to_string(), 2).unwrap();
+        assert!(registry.register("c".to_string(), 2).is_none());
+
+        second.set_session("admin", "shop");
+        second.record_command("set_ex key 10 secret");
+        let listed = registry.list();
+        assert_eq!(listed.len(), 2);
+        assert_eq!(listed[1].user.as_deref(), Some("admin"));
+        assert_eq!(listed[1].last_command.as_deref(), Some("SET_EX"));
+
+        let first_id = first.id();
+        drop(first);
+        assert_eq!(registry.active(), 1);
+        assert!(!registry.kill(first_id));
+        assert!(registry.kill(second.id()));
+        assert!(registry.register("c".to_string(), 2).is_some());
+    }
+}
@@ -0,0 +1,434 @@
+use axum::{
+    extract::{ConnectInfo, Path, Query, State},
+    http::{
+        header::{AUTHORIZATION, RETRY_AFTER},
+        HeaderMap, StatusCode,
+    },
+    response::{IntoResponse, Response as HttpResponse},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+use tokio::net::TcpListener;
+
+use crate::{
+    db::db::{DatabaseType, TinyCache},
+    security::{auth::Session, lockout::LOCKED_OUT},
+    utils::{
+        logs::LogLevel,
+        response::{Response, ResponseData},
+    },
+};
+
+use super::{
+    client::rate_limited, listener::Peer, requests::dispatch_request, websocket::websocket,
+};
+
+#[derive(Deserialize)]
+struct AuthRequest {
+    connection_string: String,
+}
+
+#[derive(Deserialize)]
+struct SetParams {
+    ttl: Option<u64>,
+}
+
+/// *run_http_server* serves the HTTP/JSON API on the given address
+///
+/// The routes are a thin layer over the TCP command set: every handler translates the request
+/// into a command and runs it through `dispatch_request`, so both transports share the same
+/// semantics, error codes and sessions.
+pub async fn run_http_server(db: Arc<TinyCache>, host: &str, port: &str) -> std::io::Result<()> {
+    let address = format!("{}:{}", host, port);
+    let listener = TcpListener::bind(&address).await?;
+
+    db.logger
+        .log_info(
+            &format!("HTTP API listening on {}", address),
+            LogLevel::System,
+            &db,
+        )
+        .await?;
+
+    // stops accepting requests on shutdown and waits for the open ones, WebSockets close
+    // themselves when they see the shutdown
+    let shutdown_db = Arc::clone(&db);
+    // the peer address is needed to count failed authentications per source
+    axum::serve(
+        listener,
+        router(db).into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .with_graceful_shutdown(async move { shutdown_db.shutdown_requested().await })
+    .await
+}
+
+/// *router* builds the HTTP routes for the API
+///
+/// - `POST /auth` exchanges a connection string for a bearer session token
+/// - `GET|PUT|DELETE /db/{name}/keys/{key}` read, write (`?ttl=<secs>` optional) and delete keys
+/// - `POST /db/{name}/query` runs the request body as a `QUERY`
+/// - `GET /db/{name}/stats` returns the `DBSTATS` of the database
+/// - `GET /ws` upgrades to a WebSocket for commands and live change pushes
+pub fn router(db: Arc<TinyCache>) -> Router {
+    Router::new()
+        .route("/auth", post(authenticate))
+        .route(
+            "/db/{name}/keys/{key}",
+            get(get_key).put(put_key).delete(delete_key),
+        )
+        .route("/db/{name}/query", post(query))
+        .route("/db/{name}/stats", get(stats))
+        .route("/ws", get(websocket))
+        .with_state(db)
+}
+
+async fn authenticate(
+    State(db): State<Arc<TinyCache>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Json(body): Json<AuthRequest>,
+) -> HttpResponse {
+    match db
+        .auth_manager
+        .authenticate(&body.connection_string, &Peer::Tcp(peer), db.clone())
+        .await
+    {
+        Ok(session) => {
+            let expires_at = session
+                .expires_at
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or_default();
+
+            reply(Response::success(ResponseData::Session(json!({
+                "token": session.id,
+                "username": session.username,
+                "database": session.database,
+                "expires_at": expires_at,
+            }))))
+        }
+        Err(e) => {
+            let _ = db
+                .logger
+                .log_warn(
+                    &format!("HTTP authentication failed: {}", e),
+                    LogLevel::Application,
+                    &db,
+                )
+                .await;
+            let status = if e.starts_with(LOCKED_OUT) {
+                StatusCode::TOO_MANY_REQUESTS
+            } else {
+                StatusCode::UNAUTHORIZED
+            };
+            error(status, format!("AUTH_ERROR: {}", e))
+        }
+    }
+}
+
+async fn get_key(
+    State(db): State<Arc<TinyCache>>,
+    Path((name, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> HttpResponse {
+    run_key_command(&db, &headers, &name, &key, |key| format!("GET_KEY {}", key)).await
+}
+
+async fn put_key(
+    State(db): State<Arc<TinyCache>>,
+    Path((name, key)): Path<(String, String)>,
+    Query(params): Query<SetParams>,
+    headers: HeaderMap,
+    body: String,
+) -> HttpResponse {
+    run_key_command(&db, &headers, &name, &key, |key| match params.ttl {
+        Some(ttl) => format!("SET_EX {} {} {}", key, ttl, body),
+        None => format!("SET {} {}", key, body),
+    })
+    .await
+}
+
+async fn delete_key(
+    State(db): State<Arc<TinyCache>>,
+    Path((name, key)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> HttpResponse {
+    run_key_command(&db, &headers, &name, &key, |key| {
+        format!("DELETE_KEY {}", key)
+    })
+    .await
+}
+
+async fn query(
+    State(db): State<Arc<TinyCache>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+    body: String,
+) -> HttpResponse {
+    run_command(&db, &headers, &name, format!("QUERY {}", body)).await
+}
+
+async fn stats(
+    State(db): State<Arc<TinyCache>>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> HttpResponse {
+    run_command(&db, &headers, &name, "DBSTATS".to_string()).await
+}
+
+// Keys travel inside a whitespace separated command, so they cannot contain whitespace themselves
+async fn run_key_command(
+    db: &Arc<TinyCache>,
+    headers: &HeaderMap,
+    name: &str,
+    key: &str,
+    command: impl FnOnce(&str) -> String,
+) -> HttpResponse {
+    if key.is_empty() || key.chars().any(char::is_whitespace) {
+        return error(StatusCode::BAD_REQUEST, "INVALID_KEY");
+    }
+    run_command(db, headers, name, command(key)).await
+}
+
+async fn run_command(
+    db: &Arc<TinyCache>,
+    headers: &HeaderMap,
+    name: &str,
+    command: String,
+) -> HttpResponse {
+    let session = match authorize(db, headers, name).await {
+        Ok(session) => session,
+        Err(response) => return response,
+    };
+
+    // every request is a connection of its own, only the user and database limits carry over
+    let mut buckets = db.rate_limiter.connection_buckets();
+    if let Err(retry_after) = db.rate_limiter.check(
+        &mut buckets,
+        &session.username,
+        &session.namespace,
+        command.len(),
+    ) {
+        return too_many_requests(retry_after);
+    }
+
+    let db_type = DatabaseType::from_str(&db.config.database_type);
+    reply(dispatch_request(&session, &session.namespace, db_type, command, db).await)
+}
+
+/// *authorize* resolves the bearer token of a request to a live session
+///
+/// The session must have been created for the database named in the route.
+async fn authorize(
+    db: &Arc<TinyCache>,
+    headers: &HeaderMap,
+    name: &str,
+) -> Result<Session, HttpResponse> {
+    let token = bearer_token(headers)
+        .ok_or_else(|| error(StatusCode::UNAUTHORIZED, "MISSING_BEARER_TOKEN"))?;
+
+    let session = db
+        .auth_manager
+        .validate_session(token, db.clone())
+        .await
+        .ok_or_else(|| error(StatusCode::UNAUTHORIZED, "INVALID_OR_EXPIRED_SESSION"))?;
+
+    if session.database != name {
+        return Err(error(StatusCode::FORBIDDEN, "DATABASE_NOT_ALLOWED"));
+    }
+
+    Ok(session)
+}
+
+/// *bearer_token* extracts the session token from an `Authorization: Bearer <token>` header
+pub(super) fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+fn reply(response: Response) -> HttpResponse {
+    (status_code(&response), Json(response)).into_response()
+}
+
+fn error(status: StatusCode, message: impl Into<String>) -> HttpResponse {
+    (status, Json(Response::error(message))).into_response()
+}
+
+/// *too_many_requests* answers a request over a rate limit with `Retry-After` in whole seconds
+fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    let retry_after_secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let mut response = reply(rate_limited(retry_after));
+    response
+        .headers_mut()
+        .insert(RETRY_AFTER, retry_after_secs.into());
+    response
+}
+
+/// *status_code* maps the error codes of the command layer to HTTP status codes
+///
+/// The code is the first word of the message, errors with details append them after a colon.
+fn status_code(response: &Response) -> StatusCode {
+    if response.status == "success" {
+        return StatusCode::OK;
+    }
+
+    let message = response.message.as_deref().unwrap_or_default();
+    let code = message.split([':', ' ']).next().unwrap_or_default();
+    match code {
+        "NOT_FOUND"
+        | "DATABASE_NOT_FOUND"
+        | "NOT_FOUND_OR_NOT_NUMERIC"
+        | "PATH_NOT_FOUND"
+        | "NO_SUCH_GROUP" => StatusCode::NOT_FOUND,
+        "INVALID_OR_EXPIRED_SESSION" | "SESSION_EXPIRED" => StatusCode::UNAUTHORIZED,
+        "PERMISSION_DENIED" | "KEY_ACCESS_DENIED" => StatusCode::FORBIDDEN,
+        "USER_EXISTS" | "TOKEN_EXISTS" | "GROUP_EXISTS" | "QUEUE_FULL" => StatusCode::CONFLICT,
+        "RATE_LIMITED" => StatusCode::TOO_MANY_REQUESTS,
+        "SCHEMA_VIOLATION" => StatusCode::UNPROCESSABLE_ENTITY,
+        "WRONGTYPE"
+        | "NOT_A_NUMBER"
+        | "INDEX_OUT_OF_RANGE"
+        | "PATCH_TEST_FAILED"
+        | "DIMENSION_MISMATCH"
+        | "STREAM_ID_TOO_SMALL"
+        | "ADMIN_REQUIRES_ALL_DATABASES"
+        | "SUBSCRIBED"
+        | "SUBSCRIPTIONS_NOT_SUPPORTED"
+        | "Invalid" => StatusCode::BAD_REQUEST,
+        code if code.starts_with("INVALID_")
+            || code.starts_with("MISSING_")
+            || code.starts_with("UNKNOWN_") =>
+        {
+            StatusCode::BAD_REQUEST
+        }
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_code_success() {
+        let response = Response::success(ResponseData::String("OK".to_string()));
+        assert_eq!(status_code(&response), StatusCode::OK);
+    }
+
+    #[test]
+    fn test_status_code_not_found() {
+        for code in [
+            "NOT_FOUND",
+            "DATABASE_NOT_FOUND",
+            "NOT_FOUND_OR_NOT_NUMERIC",
+            "PATH_NOT_FOUND",
+            "NO_SUCH_GROUP",
+        ] {
+            assert_eq!(
+                status_code(&Response::error(code)),
+                StatusCode::NOT_FOUND,
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_status_code_bad_request() {
+        for message in [
+            "INVALID_JSON: expected value",
+            "WRONGTYPE",
+            "INVALID_COMMAND",
+            "INVALID_RECEIPT",
+            "MISSING_OPERATIONS",
+            "UNKNOWN_OPERATION",
+            "Invalid query: suspicious characters detected",
+            "NOT_A_NUMBER",
+            "INDEX_OUT_OF_RANGE",
+            "PATCH_TEST_FAILED",
+            "DIMENSION_MISMATCH",
+            "STREAM_ID_TOO_SMALL",
+            "ADMIN_REQUIRES_ALL_DATABASES",
+            "SUBSCRIBED",
+            "SUBSCRIPTIONS_NOT_SUPPORTED",
+        ] {
+            assert_eq!(
+                status_code(&Response::error(message)),
+                StatusCode::BAD_REQUEST,
+                "{}",
+                message
+            );
+        }
+    }
+
+    #[test]
+    fn test_status_code_unauthorized_and_conflict() {
+        for code in ["INVALID_OR_EXPIRED_SESSION", "SESSION_EXPIRED"] {
+            assert_eq!(
+                status_code(&Response::error(code)),
+                StatusCode::UNAUTHORIZED,
+                "{}",
+                code
+            );
+        }
+        for code in ["USER_EXISTS", "TOKEN_EXISTS", "GROUP_EXISTS", "QUEUE_FULL"] {
+            assert_eq!(
+                status_code(&Response::error(code)),
+                StatusCode::CONFLICT,
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_status_code_forbidden() {
+        for code in ["PERMISSION_DENIED", "KEY_ACCESS_DENIED"] {
+            assert_eq!(
+                status_code(&Response::error(code)),
+                StatusCode::FORBIDDEN,
+                "{}",
+                code
+            );
+        }
+    }
+
+    #[test]
+    fn test_too_many_requests() {
+        let response = too_many_requests(Duration::from_millis(1500));
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[RETRY_AFTER], "2");
+    }
+
+    #[test]
+    fn test_status_code_schema_violation() {
+        let response = Response::error("SCHEMA_VIOLATION: /age: \"x\" is not of type \"integer\"");
+        assert_eq!(status_code(&response), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn test_status_code_server_errors() {
+        assert_eq!(
+            status_code(&Response::error("disk full")),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        assert_eq!(
+            status_code(&Response::error("failed to retrieve logs: denied")),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+        // a code only matches whole, not as the prefix of another one
+        assert_eq!(
+            status_code(&Response::error("NOT_FOUNDATION")),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
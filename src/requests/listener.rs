@@ -0,0 +1,382 @@
+use std::{fmt, io, net::{IpAddr, SocketAddr}, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    task::JoinSet,
+    time::timeout,
+};
+
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::PathBuf,
+};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+
+use crate::{db::db::TinyCache, security::config::DBConfig, utils::logs::LogLevel};
+
+use super::{client::handle_client, http::run_http_server};
+
+/// Any bidirectional byte stream a client can be served over
+pub trait ClientStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> ClientStream for T {}
+
+/// The remote end of an accepted connection
+#[derive(Debug, Clone)]
+pub enum Peer {
+    Tcp(SocketAddr),
+    Unix { uid: u32, gid: u32 },
+}
+
+impl Peer {
+    /// *is_trusted* tells whether the peer may connect without a password.
+    ///
+    /// Only Unix socket peers can be trusted, since their uid and gid are vouched for by the
+    /// kernel rather than claimed by the client.
+    pub fn is_trusted(&self, config: &DBConfig) -> bool {
+        match self {
+            Peer::Tcp(_) => false,
+            Peer::Unix { uid, gid } => {
+                config.unix_trusted_uid == Some(*uid) || config.unix_trusted_gid == Some(*gid)
+            }
+        }
+    }
+
+    /// *ip* is the address failed authentications are counted against, Unix peers have none
+    pub fn ip(&self) -> Option<IpAddr> {
+        match self {
+            Peer::Tcp(addr) => Some(addr.ip()),
+            Peer::Unix { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for Peer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Peer::Tcp(addr) => write!(f, "{}", addr),
+            Peer::Unix { uid, gid } => write!(f, "unix(uid={}, gid={})", uid, gid),
+        }
+    }
+}
+
+/// A listening socket the server accepts clients on.
+///
+/// Every transport hands its connections to the same `handle_client`, so authentication,
+/// connection limits and request processing do not depend on how the client connected.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub async fn bind_tcp(address: &str) -> io::Result<Self> {
+        Ok(Listener::Tcp(TcpListener::bind(address).await?))
+    }
+
+    /// *bind_unix* binds a Unix domain socket and restricts it to the given file mode
+    ///
+    /// A socket file left behind by a previous run is replaced, anything else at the path makes
+    /// the bind fail. Access control is done through the file permissions, so the socket is bound
+    /// in a private directory next to the path and only moved into place once it has its mode.
+    #[cfg(unix)]
+    pub async fn bind_unix(path: &str, mode: u32) -> io::Result<Self> {
+        let path = PathBuf::from(path);
+        match tokio::fs::symlink_metadata(&path).await {
+            Ok(metadata) if metadata.file_type().is_socket() => {}
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        // the names are short, socket paths are limited to about a hundred bytes
+        let id = uuid::Uuid::new_v4().simple().to_string();
+        let private_dir = path.with_file_name(format!(".tinycache-{}", &id[..8]));
+        tokio::fs::DirBuilder::new()
+            .mode(0o700)
+            .create(&private_dir)
+            .await?;
+        let private_path = private_dir.join("sock");
+        let bound = async {
+            let listener = UnixListener::bind(&private_path)?;
+            tokio::fs::set_permissions(&private_path, Permissions::from_mode(mode)).await?;
+            // the rename replaces a stale socket in one step
+            tokio::fs::rename(&private_path, &path).await?;
+            Ok::<_, io::Error>(listener)
+        }
+        .await;
+        let _ = tokio::fs::remove_file(&private_path).await;
+        let _ = tokio::fs::remove_dir(&private_dir).await;
+
+        Ok(Listener::Unix(bound?, path))
+    }
+
+    pub async fn accept(&self) -> io::Result<(Box<dyn ClientStream>, Peer)> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (socket, addr) = listener.accept().await?;
+                Ok((Box::new(socket), Peer::Tcp(addr)))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => {
+                let (socket, _) = listener.accept().await?;
+                let cred = socket.peer_cred()?;
+                Ok((
+                    Box::new(socket),
+                    Peer::Unix {
+                        uid: cred.uid(),
+                        gid: cred.gid(),
+                    },
+                ))
+            }
+        }
+    }
+
+    /// *close* stops accepting clients, removing the socket file of a Unix listener
+    pub async fn close(self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, path) = &self {
+            let _ = tokio::fs::remove_file(path).await;
+        }
+    }
+
+    pub fn address(&self) -> String {
+        match self {
+            Listener::Tcp(listener) => listener
+                .local_addr()
+                .map(|addr| addr.to_string())
+                .unwrap_or_else(|_| "unknown".to_string()),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => path.display().to_string(),
+        }
+    }
+}
+
+/// *run_server* accepts clients on a listener and serves each one in its own task
+///
+/// Once the shutdown begins the listener is closed and connected clients get
+/// `shutdown_timeout_secs` to finish their in-flight requests, the ones still connected after
+/// that are dropped.
+pub async fn run_server(db: Arc<TinyCache>, listener: Listener) -> io::Result<()> {
+    db.logger
+        .log_info(
+            &format!("Database listening on {}", listener.address()),
+            LogLevel::System,
+            &db,
+        )
+        .await?;
+
+    let mut clients = JoinSet::new();
+
+    loop {
+        let (socket, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = db.shutdown_requested() => break,
+            // reaping finished clients so the set does not grow with every connection
+            Some(_) = clients.join_next(), if !clients.is_empty() => continue,
+        };
+        let db = Arc::clone(&db);
+
+        db.logger
+            .log_info(
+                &format!("New connection from: {}", peer),
+                LogLevel::System,
+                &db,
+            )
+            .await?;
+
+        clients.spawn(async move {
+            handle_client(socket, peer, db).await;
+        });
+    }
+
+    let address = listener.address();
+    listener.close().await;
+
+    let deadline = Duration::from_secs(db.config.shutdown_timeout_secs);
+    let drained = timeout(deadline, async {
+        while clients.join_next().await.is_some() {}
+    })
+    .await;
+
+    if drained.is_err() {
+        db.logger
+            .log_warn(
+                &format!(
+                    "{} clients on {} did not finish within {}s, closing them",
+                    clients.len(),
+                    address,
+                    deadline.as_secs()
+                ),
+                LogLevel::System,
+                &db,
+            )
+            .await?;
+        clients.shutdown().await;
+    }
+
+    db.logger
+        .log_info(
+            &format!("Stopped listening on {}", address),
+            LogLevel::System,
+            &db,
+        )
+        .await
+}
+
+/// *serve* starts every server enabled in the configuration and runs until the process is asked
+/// to terminate (SIGTERM or Ctrl-C) or `TinyCache::begin_shutdown` is called
+///
+/// The HTTP API and the Unix domain socket are optional and run in their own tasks next to the
+/// TCP server, a failure of one of them is logged without stopping the others. On shutdown the
+/// servers are drained before the WAL and the logs are synced.
+pub async fn serve(db: Arc<TinyCache>) -> io::Result<()> {
+    let config = Arc::clone(&db.config);
+    let mut servers = JoinSet::new();
+
+    // Starting the optional HTTP/JSON API next to the TCP server
+    if config.http_enabled {
+        let http_db = Arc::clone(&db);
+        let host = config.host.to_string();
+        let http_port = config.http_port.clone();
+        servers.spawn(async move {
+            if let Err(e) = run_http_server(Arc::clone(&http_db), &host, &http_port).await {
+                let _ = http_db
+                    .logger
+                    .log_error(
+                        &format!("HTTP server stopped: {}", e),
+                        LogLevel::System,
+                        &http_db,
+                    )
+                    .await;
+            }
+        });
+    }
+
+    // Starting the unix domain socket listener for co-located clients
+    #[cfg(unix)]
+    if !config.unix_socket_path.is_empty() {
+        let mode = u32::from_str_radix(&config.unix_socket_permissions, 8)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let listener = Listener::bind_unix(&config.unix_socket_path, mode).await?;
+        let unix_db = Arc::clone(&db);
+        servers.spawn(async move {
+            if let Err(e) = run_server(Arc::clone(&unix_db), listener).await {
+                let _ = unix_db
+                    .logger
+                    .log_error(
+                        &format!("Unix socket server stopped: {}", e),
+                        LogLevel::System,
+                        &unix_db,
+                    )
+                    .await;
+            }
+        });
+    }
+
+    // Starting the TCP server using the db, port and host from the configuration
+    let address = format!("{}:{}", config.host, config.port);
+    let mut tcp = tokio::spawn(run_server(
+        Arc::clone(&db),
+        Listener::bind_tcp(&address).await?,
+    ));
+
+    let stopped = tokio::select! {
+        _ = termination_signal() => None,
+        _ = db.shutdown_requested() => None,
+        result = &mut tcp => Some(result),
+    };
+
+    db.logger
+        .log_info("Shutting down, draining connections", LogLevel::System, &db)
+        .await?;
+    db.begin_shutdown();
+
+    // the listeners enforce the deadline themselves, the margin covers the HTTP API
+    let deadline = Duration::from_secs(config.shutdown_timeout_secs + 1);
+    let drained = timeout(deadline, async move {
+        let result = match stopped {
+            Some(result) => result,
+            None => tcp.await,
+        };
+        while servers.join_next().await.is_some() {}
+        result
+    })
+    .await;
+
+    let result = match drained {
+        Ok(Ok(result)) => result,
+        Ok(Err(e)) => Err(io::Error::other(e)),
+        Err(_) => Ok(()),
+    };
+
+    db.shutdown().await?;
+    result
+}
+
+/// *termination_signal* resolves when the process receives SIGTERM or Ctrl-C
+async fn termination_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+            }
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::FileTypeExt;
+
+    #[tokio::test]
+    async fn test_bind_unix() {
+        let dir = std::env::temp_dir().join(format!("tinycache-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("tinycache.sock");
+        let path_str = path.to_str().unwrap();
+
+        // anything but a socket is left alone
+        std::fs::write(&path, "data").unwrap();
+        assert!(Listener::bind_unix(path_str, 0o600).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "data");
+        std::fs::remove_file(&path).unwrap();
+
+        // a stale socket is replaced, and the socket only appears with its mode
+        let stale = Listener::bind_unix(path_str, 0o666).await.unwrap();
+        drop(stale);
+        let listener = Listener::bind_unix(path_str, 0o600).await.unwrap();
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert!(metadata.file_type().is_socket());
+        assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        assert!(tokio::net::UnixStream::connect(&path).await.is_ok());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        listener.close().await;
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -1,2 +1,6 @@
 pub mod client;
+pub mod connections;
+pub mod http;
+pub mod listener;
 pub mod requests;
+pub mod websocket;
\ No newline at end of file
@@ -1,39 +1,54 @@
 use serde_json::Value as JsonValue;
-use std::time::Duration;
+use std::{io, ops::Bound, time::Duration};
 
 use crate::{
-    db::db::{DataValue, DatabaseType, TinyCache},
+    db::{
+        db::{DataValue, DatabaseType, TinyCache, ValueError},
+        document::{parse_path, PatchOperation},
+        hash::Fields,
+        list::{self, ListEnd},
+        pubsub::Subscriber,
+        queue::QueueMessage,
+        set::{self, SetOperation},
+        sorted_set::{parse_score_bound, AddOptions, LexBound, RangeBy, RangeQuery},
+        stream::{parse_range_bound, StreamEntry, StreamId},
+        vector::{IndexConfig, Metric, Vector},
+    },
     query::{
         middleware::query_security_middleware,
         query::{aggregate, AggregationOperation, FilterCondition},
     },
+    security::{
+        acl::KeyAcl,
+        audit::{AuditAction, AuditEvent},
+        auth::Session,
+        lockout::LockoutKey,
+        tokens::TOKEN_USERNAME,
+        users::Permission,
+    },
     utils::{
         logs::LogLevel,
         response::{Response, ResponseData},
-        utils::set_database_context,
+        utils::{compute_now_millis, set_database_context},
     },
 };
 
-pub async fn process_requests(request: String, db: &TinyCache) -> String {
+/// *process_requests* runs a `<connection string> <command>` request. Requests without the
+/// connection string, as sent after an `AUTH TOKEN` handshake, run on the session's database.
+pub async fn process_requests(request: String, session: &Session, db: &TinyCache) -> String {
     let parts: Vec<&str> = request.trim().split_whitespace().collect();
     match parts.as_slice() {
+        [first, ..] if !first.starts_with("tinycache://") => {
+            let db_type = DatabaseType::from_str(&db.config.database_type);
+            dispatch_request(session, &session.namespace, db_type, parts.join(" "), db)
+                .await
+                .to_string()
+        }
         [connection_string, command @ ..] => match set_database_context(connection_string) {
             Ok((database, db_type)) => {
-                db.set_current_database(Some(&database)).await;
-                let command_str = command.join(" ");
-
-                // Check shared commands first
-                if let Some(response) =
-                    process_shared_requests(&database, command_str.clone(), db).await
-                {
-                    response
-                } else {
-                    match db_type {
-                        DatabaseType::KeyValue => {
-                            process_key_value_requests(&database, command_str, db).await
-                        }
-                    }
-                }
+                dispatch_request(session, &database, db_type, command.join(" "), db)
+                    .await
+                    .to_string()
             }
             Err(e) => format!("Error: {}\r\n", e),
         },
@@ -41,10 +56,221 @@ pub async fn process_requests(request: String, db: &TinyCache) -> String {
     }
 }
 
-async fn process_shared_requests(database: &str, request: String, db: &TinyCache) -> Option<String> {
+/// *process_subscription_request* runs the `SUBSCRIBE`, `PSUBSCRIBE`, `UNSUBSCRIBE` and
+/// `PUNSUBSCRIBE` commands of a connection on its subscriber, which the first subscription
+/// creates. Returns `None` for the other commands, to be run by `process_requests`. While the
+/// connection holds subscriptions it is in push mode, where only `PING` and `LOGOUT` are run
+/// next to the subscription commands.
+pub async fn process_subscription_request(
+    request: &str,
+    session: &Session,
+    db: &TinyCache,
+    subscriber: &mut Option<Subscriber>,
+) -> Option<String> {
+    let parts: Vec<&str> = request.split_whitespace().collect();
+    let (database, command) = match parts.as_slice() {
+        [connection_string, command @ ..] if connection_string.starts_with("tinycache://") => {
+            // invalid connection strings are reported by `process_requests`
+            let (database, _) = set_database_context(connection_string).ok()?;
+            (database, command)
+        }
+        command => (session.namespace.clone(), command),
+    };
+    let subscribed = subscriber
+        .as_ref()
+        .is_some_and(|subscriber| subscriber.count() > 0);
+
+    let (name, names) = match command {
+        [name @ ("SUBSCRIBE" | "PSUBSCRIBE"), names @ ..] if !names.is_empty() => (*name, names),
+        [name @ ("UNSUBSCRIBE" | "PUNSUBSCRIBE"), names @ ..] => (*name, names),
+        ["PING"] | ["LOGOUT"] => return None,
+        _ if subscribed => return Some(Response::error("SUBSCRIBED").to_string()),
+        _ => return None,
+    };
+    if !db
+        .auth_manager
+        .authorize(&session.username, &database, Permission::Read)
+    {
+        return Some(Response::error("PERMISSION_DENIED").to_string());
+    }
+
+    let pattern = name.starts_with('P');
+    let subscriber = subscriber.get_or_insert_with(|| db.pubsub.subscriber());
+    let (field, subscriptions) = if name.ends_with("UNSUBSCRIBE") {
+        let count = subscriber.unsubscribe(&database, names, pattern);
+        ("unsubscribed", count)
+    } else {
+        let count = subscriber.subscribe(&database, names, pattern);
+        ("subscribed", count)
+    };
+    Some(
+        Response::success(ResponseData::Json(serde_json::json!({
+            field: names,
+            "subscriptions": subscriptions,
+        })))
+        .to_string(),
+    )
+}
+
+/// *dispatch_request* runs a single command against an already resolved database context
+///
+/// The session's user must hold the permission the command needs on the database. Shared
+/// commands are checked first, everything else is routed by database type.
+/// Transports that do not speak the connection string protocol (e.g. HTTP) call this directly.
+/// Security relevant commands are written to the audit log, whether they succeed or not.
+pub async fn dispatch_request(
+    session: &Session,
+    database: &str,
+    db_type: DatabaseType,
+    command: String,
+    db: &TinyCache,
+) -> Response {
+    let Some(action) = audit_action(&command) else {
+        return run_request(session, database, db_type, command, db).await;
+    };
+
+    let detail = audit_detail(&command);
+    let response = run_request(session, database, db_type, command, db).await;
+    let mut event = AuditEvent::new(action, detail)
+        .session(session)
+        .database(Some(database.to_string()));
+    if response.status == "error" {
+        event = event.failed(response.message.clone().unwrap_or_default());
+    }
+    let _ = db.audit.record(event).await;
+    response
+}
+
+async fn run_request(
+    session: &Session,
+    database: &str,
+    db_type: DatabaseType,
+    command: String,
+    db: &TinyCache,
+) -> Response {
+    if let Some(permission) = required_permission(&command) {
+        if !db
+            .auth_manager
+            .authorize(&session.username, database, permission)
+        {
+            return Response::error("PERMISSION_DENIED");
+        }
+    }
+
+    // a session can always end itself
+    if command.trim() == "LOGOUT" {
+        return if db.auth_manager.logout(&session.id).await {
+            Response::success(ResponseData::String("OK".to_string()))
+        } else {
+            Response::error("NOT_FOUND")
+        };
+    }
+
+    db.set_current_database(Some(database)).await;
+    let acl = db.auth_manager.key_acl(&session.username, database);
+
+    // Check shared commands first
+    if let Some(response) = process_shared_requests(database, command.clone(), &acl, db).await {
+        response
+    } else {
+        match db_type {
+            DatabaseType::KeyValue => process_key_value_requests(database, command, &acl, db).await,
+        }
+    }
+}
+
+/// *required_permission* is the permission a command needs, `None` if every session may run it.
+/// Commands not listed here need `Admin`, so a new command is never open to everyone by accident.
+fn required_permission(command: &str) -> Option<Permission> {
+    let permission = match command.split_whitespace().next()? {
+        "PING" | "LOGOUT" => return None,
+        "GET_KEY" | "Get_All_KV" | "DBSTATS" => Permission::Read,
+        "SET" | "SET_EX" | "UPDATE_KEY" | "DELETE_KEY" | "INCR_KEY" | "DECR_KEY" | "STORE" => {
+            Permission::Write
+        }
+        "LRANGE" | "LLEN" | "LINDEX" => Permission::Read,
+        "LPUSH" | "RPUSH" | "LPOP" | "RPOP" | "LSET" | "LREM" | "LTRIM" | "LINSERT" => {
+            Permission::Write
+        }
+        "SISMEMBER" | "SMEMBERS" | "SCARD" | "SRANDMEMBER" | "SUNION" | "SINTER" | "SDIFF" => {
+            Permission::Read
+        }
+        "SADD" | "SREM" | "SPOP" | "SUNIONSTORE" | "SINTERSTORE" | "SDIFFSTORE" => {
+            Permission::Write
+        }
+        "HGET" | "HMGET" | "HGETALL" | "HEXISTS" | "HKEYS" | "HLEN" => Permission::Read,
+        "HSET" | "HDEL" | "HINCRBYFLOAT" => Permission::Write,
+        "ZSCORE" | "ZRANK" | "ZRANGE" | "ZCOUNT" => Permission::Read,
+        "ZADD" | "ZINCRBY" | "ZREM" | "ZPOPMIN" | "ZPOPMAX" => Permission::Write,
+        "XLEN" | "XRANGE" | "XREAD" | "XPENDING" => Permission::Read,
+        "XADD" | "XGROUP" | "XREADGROUP" | "XACK" | "XCLAIM" => Permission::Write,
+        "QSTATS" | "QDEAD" => Permission::Read,
+        "QPUSH" | "QPOP" | "QACK" | "QNACK" | "QREDRIVE" => Permission::Write,
+        "JSON_SET" | "JSON_DEL" | "JSON_ARRAPPEND" | "JSON_NUMINCRBY" | "JSON_MERGE"
+        | "JSON_PATCH" => Permission::Write,
+        "VSEARCH" | "VINFO" => Permission::Read,
+        "SUBSCRIBE" | "PSUBSCRIBE" | "UNSUBSCRIBE" | "PUNSUBSCRIBE" | "PUBSUB" => Permission::Read,
+        "PUBLISH" => Permission::Write,
+        "VADD" | "VDEL" => Permission::Write,
+        "SCHEMAS" => Permission::Read,
+        "QUERY" => Permission::Query,
+        "CLEAR_DB" => Permission::ClearDb,
+        "VIEW_LOGS" => Permission::Logs,
+        _ => Permission::Admin,
+    };
+    Some(permission)
+}
+
+/// *audit_action* is how a command is recorded in the audit log, `None` if it is not audited.
+/// Every command needing `Admin` is audited, next to the destructive ones.
+fn audit_action(command: &str) -> Option<AuditAction> {
+    let action = match command.split_whitespace().next()? {
+        "LOGOUT" => AuditAction::Logout,
+        "CREATE_USER" | "DROP_USER" | "GRANT" | "REVOKE" => AuditAction::UserChange,
+        "ACL_ADD" | "ACL_REMOVE" => AuditAction::AclChange,
+        "CREATE_TOKEN" | "REVOKE_TOKEN" => AuditAction::TokenChange,
+        "CLEAR_DB" => AuditAction::ClearDb,
+        _ if required_permission(command) == Some(Permission::Admin) => AuditAction::AdminCommand,
+        _ => return None,
+    };
+    Some(action)
+}
+
+/// *schema_prefix* is the key prefix a schema command names, `*` naming the whole database
+fn schema_prefix(prefix: &str) -> &str {
+    if prefix == "*" {
+        ""
+    } else {
+        prefix
+    }
+}
+
+/// *schema_prefix_name* is how `SCHEMAS` shows a prefix, the inverse of `schema_prefix`
+fn schema_prefix_name(prefix: &str) -> &str {
+    if prefix.is_empty() {
+        "*"
+    } else {
+        prefix
+    }
+}
+
+/// *audit_detail* is the command as written to the audit log, without the passwords it carries
+fn audit_detail(command: &str) -> String {
+    match command.split_whitespace().collect::<Vec<&str>>().as_slice() {
+        ["CREATE_USER", username, _] => format!("CREATE_USER {} ***", username),
+        _ => command.trim().to_string(),
+    }
+}
+
+async fn process_shared_requests(
+    database: &str,
+    request: String,
+    acl: &KeyAcl,
+    db: &TinyCache,
+) -> Option<Response> {
     let parts: Vec<&str> = request.trim().split_whitespace().collect();
     match parts.as_slice() {
-        ["PING"] => Some(Response::success(ResponseData::String("PONG".to_string())).to_string()),
+        ["PING"] => Some(Response::success(ResponseData::String("PONG".to_string()))),
 
         ////////////////////////////////////////////////////////////////////////////////////////////
         /////////////////////////////////// DB MONITORING && LOGS //////////////////////////////////
@@ -52,15 +278,13 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
         ["DBSTATS"] => Some({
             if let Some(stats) = db.get_database_stats(database).await {
                 Response::success(ResponseData::Json(serde_json::to_value(stats).unwrap()))
-                    .to_string()
             } else {
-                Response::error("DATABASE_NOT_FOUND").to_string()
+                Response::error("DATABASE_NOT_FOUND")
             }
         }),
         ["VIEW_LOGS"] => Some(match db.logger.view_application_logs(&db, database).await {
-            Ok(logs) => Response::success(ResponseData::Json(serde_json::to_value(logs).unwrap()))
-                .to_string(),
-            Err(e) => Response::error(format!("failed to retrieve logs: {}", e)).to_string(),
+            Ok(logs) => Response::success(ResponseData::Json(serde_json::to_value(logs).unwrap())),
+            Err(e) => Response::error(format!("failed to retrieve logs: {}", e)),
         }),
         ["VIEW_SYSTEM_LOGS"] => Some(
             match db
@@ -70,20 +294,257 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
             {
                 Ok(logs) => {
                     Response::success(ResponseData::Json(serde_json::to_value(logs).unwrap()))
-                        .to_string()
-                }
-                Err(e) => {
-                    Response::error(format!("Failed to retrieve system logs: {}", e)).to_string()
                 }
+                Err(e) => Response::error(format!("Failed to retrieve system logs: {}", e)),
             },
         ),
         ["ALL_DBSTATS"] => Some({
             let stats = db.get_all_database_stats().await;
-            Response::success(ResponseData::Json(serde_json::to_value(stats).unwrap())).to_string()
+            Response::success(ResponseData::Json(serde_json::to_value(stats).unwrap()))
         }),
-        ["CLEAR_DB"] => Some(match db.drop_db(database).await {
-            Ok(()) => Response::success(ResponseData::String("OK".to_string())).to_string(),
-            Err(e) => Response::error(e.to_string()).to_string(),
+        // users restricted to some keys may not clear the others
+        ["CLEAR_DB"] if acl.is_restricted() => Some(Response::error("KEY_ACCESS_DENIED")),
+        // audited with its user by `dispatch_request`
+        ["CLEAR_DB"] => Some(match db.remove_db(database).await {
+            Ok(()) => Response::success(ResponseData::String("OK".to_string())),
+            Err(e) => Response::error(e.to_string()),
+        }),
+        ["CLIENT", "LIST"] => Some(Response::success(ResponseData::Json(
+            serde_json::to_value(db.connections.list()).unwrap(),
+        ))),
+        ["CLIENT", "KILL", id] => Some(match id.parse::<u64>() {
+            Ok(id) if db.connections.kill(id) => {
+                Response::success(ResponseData::String("OK".to_string()))
+            }
+            Ok(_) => Response::error("NOT_FOUND"),
+            Err(_) => Response::error("INVALID_CLIENT_ID"),
+        }),
+        ["LOCKOUTS"] => Some(Response::success(ResponseData::Json(
+            serde_json::to_value(db.auth_manager.lockouts.list()).unwrap(),
+        ))),
+        ["CLEAR_LOCKOUTS"] => Some(Response::success(ResponseData::String(
+            db.auth_manager.lockouts.clear(None).to_string(),
+        ))),
+        ["CLEAR_LOCKOUTS", key] => Some(match LockoutKey::parse(key) {
+            Some(key) => match db.auth_manager.lockouts.clear(Some(&key)) {
+                0 => Response::error("NOT_FOUND"),
+                cleared => Response::success(ResponseData::String(cleared.to_string())),
+            },
+            None => Response::error("INVALID_LOCKOUT_KEY"),
+        }),
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ///////////////////////////////////////// PUB/SUB //////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ["PUBLISH", channel, message @ ..] if !message.is_empty() => {
+            Some(Response::success(ResponseData::Json(JsonValue::from(
+                db.pubsub.publish(database, channel, &message.join(" ")),
+            ))))
+        }
+        ["PUBSUB", "CHANNELS"] => Some(Response::success(ResponseData::List(
+            db.pubsub.channels(database).into_iter().collect(),
+        ))),
+        // subscriptions are held by TCP and Unix socket connections, see `process_subscription_request`
+        ["SUBSCRIBE" | "PSUBSCRIBE" | "UNSUBSCRIBE" | "PUNSUBSCRIBE", ..] => {
+            Some(Response::error("SUBSCRIPTIONS_NOT_SUPPORTED"))
+        }
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ///////////////////////////////////////// SCHEMAS //////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        // `*` stands for the empty prefix, the schema of the whole database
+        ["SCHEMAS"] => Some({
+            let schemas: serde_json::Map<String, JsonValue> = db
+                .schemas
+                .list(database)
+                .into_iter()
+                .map(|(prefix, schema)| (schema_prefix_name(&prefix).to_string(), schema))
+                .collect();
+            Response::success(ResponseData::Json(JsonValue::Object(schemas)))
+        }),
+        ["SET_SCHEMA", prefix, schema @ ..] if !schema.is_empty() => {
+            Some(match serde_json::from_str(&schema.join(" ")) {
+                Ok(schema) => match db.set_schema(database, schema_prefix(prefix), schema).await {
+                    Ok(Ok(())) => Response::success(ResponseData::String("OK".to_string())),
+                    Ok(Err(reason)) => Response::error(reason),
+                    Err(e) => Response::error(e.to_string()),
+                },
+                Err(e) => Response::error(format!("INVALID_JSON: {}", e)),
+            })
+        }
+        ["DROP_SCHEMA", prefix] => Some(
+            match db.drop_schema(database, schema_prefix(prefix)).await {
+                Ok(true) => Response::success(ResponseData::String("OK".to_string())),
+                Ok(false) => Response::error("NOT_FOUND"),
+                Err(e) => Response::error(e.to_string()),
+            },
+        ),
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ////////////////////////////////////////// USERS ///////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ["USERS"] => Some(Response::success(ResponseData::Json(
+            serde_json::to_value(db.auth_manager.users.list()).unwrap(),
+        ))),
+        // the config admin and the token username are taken without being in the store
+        ["CREATE_USER", username, password] => Some(
+            if *username == db.config.admin || *username == TOKEN_USERNAME {
+                Response::error("USER_EXISTS")
+            } else {
+                match db.auth_manager.users.create_user(username, password).await {
+                    Ok(()) => Response::success(ResponseData::String("OK".to_string())),
+                    Err(e) => Response::error(e),
+                }
+            },
+        ),
+        ["DROP_USER", username] => Some(match db.auth_manager.users.drop_user(username).await {
+            Ok(true) => Response::success(ResponseData::String("OK".to_string())),
+            Ok(false) => Response::error("NOT_FOUND"),
+            Err(e) => Response::error(e),
+        }),
+        [command @ ("GRANT" | "REVOKE"), username, target, grant] => {
+            Some(match Permission::parse_grant(grant) {
+                Some(permissions) => {
+                    let users = &db.auth_manager.users;
+                    let result = if *command == "GRANT" {
+                        users.grant(username, target, &permissions).await
+                    } else {
+                        users.revoke(username, target, &permissions).await
+                    };
+                    match result {
+                        Ok(()) => Response::success(ResponseData::String("OK".to_string())),
+                        Err(e) => Response::error(e),
+                    }
+                }
+                None => Response::error("INVALID_ROLE"),
+            })
+        }
+        [command @ ("ACL_ADD" | "ACL_REMOVE"), username, target, pattern] => {
+            let users = &db.auth_manager.users;
+            Some(if *command == "ACL_ADD" {
+                match users.add_key_pattern(username, target, pattern).await {
+                    Ok(()) => Response::success(ResponseData::String("OK".to_string())),
+                    Err(e) => Response::error(e),
+                }
+            } else {
+                match users.remove_key_pattern(username, target, pattern).await {
+                    Ok(true) => Response::success(ResponseData::String("OK".to_string())),
+                    Ok(false) => Response::error("NOT_FOUND"),
+                    Err(e) => Response::error(e),
+                }
+            })
+        }
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ////////////////////////////////////////// AUDIT ///////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        // AUDIT [from] [to] takes unix seconds, both ends are inclusive and may be left open
+        ["AUDIT", range @ ..] if range.len() <= 2 => {
+            let bounds: Result<Vec<u64>, _> = range.iter().map(|bound| bound.parse()).collect();
+            Some(match bounds {
+                Ok(bounds) => {
+                    match db
+                        .audit
+                        .query(bounds.first().copied(), bounds.get(1).copied())
+                        .await
+                    {
+                        Ok(entries) => Response::success(ResponseData::Json(
+                            serde_json::to_value(entries).unwrap(),
+                        )),
+                        Err(e) => Response::error(e.to_string()),
+                    }
+                }
+                Err(_) => Response::error("INVALID_TIME_RANGE"),
+            })
+        }
+        ["AUDIT_VERIFY"] => Some(match db.audit.verify().await {
+            Ok(verification) => Response::success(ResponseData::Json(
+                serde_json::to_value(verification).unwrap(),
+            )),
+            Err(e) => Response::error(e.to_string()),
+        }),
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ////////////////////////////////////////// TOKENS //////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ["TOKENS"] => Some(Response::success(ResponseData::Json(
+            serde_json::to_value(db.auth_manager.tokens.list()).unwrap(),
+        ))),
+        // The secret is only ever shown in this response, the store keeps its hash
+        ["CREATE_TOKEN", name, target, grant, ttl @ ..] if ttl.len() <= 1 => {
+            let ttl = match ttl.first().map(|secs| secs.parse::<u64>()) {
+                Some(Ok(secs)) => Some(Some(Duration::from_secs(secs))),
+                Some(Err(_)) => None,
+                None => Some(None),
+            };
+            Some(match (Permission::parse_grant(grant), ttl) {
+                (None, _) => Response::error("INVALID_ROLE"),
+                (_, None) => Response::error("INVALID_TTL"),
+                (Some(permissions), Some(ttl)) => {
+                    match db
+                        .auth_manager
+                        .tokens
+                        .create(name, target, &permissions, ttl)
+                        .await
+                    {
+                        Ok(secret) => Response::success(ResponseData::String(secret)),
+                        Err(e) => Response::error(e),
+                    }
+                }
+            })
+        }
+        ["REVOKE_TOKEN", name] => Some(match db.auth_manager.tokens.revoke(name).await {
+            Ok(true) => Response::success(ResponseData::String("OK".to_string())),
+            Ok(false) => Response::error("NOT_FOUND"),
+            Err(e) => Response::error(e),
+        }),
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ///////////////////////////////////////// VECTORS //////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ["VINDEX", dimension, metric] => {
+            Some(match (dimension.parse::<usize>(), Metric::parse(metric)) {
+                (Ok(dimension), Some(metric)) if dimension > 0 => typed_response(
+                    db.vector_index(database, IndexConfig { dimension, metric })
+                        .await,
+                    |_| ResponseData::String("OK".to_string()),
+                ),
+                (_, None) => Response::error("INVALID_METRIC"),
+                _ => Response::error("INVALID_DIMENSION"),
+            })
+        }
+        ["VINFO"] => Some(match db.vector_index_info(database) {
+            Some((config, vectors)) => Response::success(ResponseData::Json(serde_json::json!({
+                "dimension": config.dimension,
+                "metric": config.metric,
+                "vectors": vectors,
+            }))),
+            None => Response::error("NOT_FOUND"),
+        }),
+        // keyless, the keys the ACL hides are left out of the results
+        ["VSEARCH", rest @ ..] if !rest.is_empty() => Some({
+            let rest = rest.join(" ");
+            let mut values = serde_json::Deserializer::from_str(&rest).into_iter::<JsonValue>();
+            let query = values.next().and_then(Result::ok);
+            let arguments: Vec<&str> = rest[values.byte_offset()..].split_whitespace().collect();
+            match (
+                query.as_ref().and_then(parse_vector),
+                parse_vector_search(&arguments),
+            ) {
+                (Some(query), Ok((count, filters))) => {
+                    match db
+                        .vector_search(database, &query, count, &filters, acl)
+                        .await
+                    {
+                        Ok(matches) => Response::success(ResponseData::Json(
+                            serde_json::to_value(matches).unwrap(),
+                        )),
+                        Err(e) => Response::error(e.code()),
+                    }
+                }
+                (None, _) => Response::error("INVALID_VECTOR"),
+                (_, Err(e)) => Response::error(e),
+            }
         }),
 
         ////////////////////////////////////////////////////////////////////////////////////////////
@@ -101,7 +562,7 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
             match query_security_middleware(database, &request, db).await {
                 Ok(()) => {
                     if rest.is_empty() {
-                        Response::error("MISSING_OPERATIONS").to_string()
+                        Response::error("MISSING_OPERATIONS")
                     } else {
                         let mut operations = Vec::new();
                         let mut i = 0;
@@ -118,9 +579,7 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                         ));
                                         i += 2;
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_FIELD_FOR_SUM").to_string(),
-                                        );
+                                        return Some(Response::error("MISSING_FIELD_FOR_SUM"));
                                     }
                                 }
                                 "AVG" => {
@@ -130,10 +589,7 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                         ));
                                         i += 2;
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_FIELD_FOR_AVERAGE")
-                                                .to_string(),
-                                        );
+                                        return Some(Response::error("MISSING_FIELD_FOR_AVERAGE"));
                                     }
                                 }
                                 "GROUPBY" => {
@@ -143,10 +599,7 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                         ));
                                         i += 2;
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_FIELD_FOR_GROUPBY")
-                                                .to_string(),
-                                        );
+                                        return Some(Response::error("MISSING_FIELD_FOR_GROUPBY"));
                                     }
                                 }
                                 "FILTER" => {
@@ -167,9 +620,7 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                         ));
                                         i += 4;
                                     } else {
-                                        return Some(
-                                            Response::error("INVALID_FILTER_FORMAT").to_string(),
-                                        );
+                                        return Some(Response::error("INVALID_FILTER_FORMAT"));
                                     }
                                 }
                                 "MIN" => {
@@ -179,9 +630,7 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                         ));
                                         i += 2;
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_FIELD_FOR_MIN").to_string(),
-                                        );
+                                        return Some(Response::error("MISSING_FIELD_FOR_MIN"));
                                     }
                                 }
                                 "MAX" => {
@@ -191,23 +640,18 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                         ));
                                         i += 2;
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_FIELD_FOR_MAX").to_string(),
-                                        );
+                                        return Some(Response::error("MISSING_FIELD_FOR_MAX"));
                                     }
                                 }
 
-                                "DISCTICT" => {
+                                "DISTINCT" | "DISCTICT" => {
                                     if i + 1 < rest.len() {
                                         operations.push(AggregationOperation::Distinct(
                                             rest[i + 1].to_string(),
                                         ));
                                         i += 2;
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_FIELD_FOR_DISTINCT")
-                                                .to_string(),
-                                        );
+                                        return Some(Response::error("MISSING_FIELD_FOR_DISTINCT"));
                                     }
                                 }
 
@@ -220,16 +664,14 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                             });
                                             i += 3;
                                         } else {
-                                            return Some(
-                                                Response::error("INVALID_N_VALUE_FOR_TOPN")
-                                                    .to_string(),
-                                            );
+                                            return Some(Response::error(
+                                                "INVALID_N_VALUE_FOR_TOPN",
+                                            ));
                                         }
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_PARAMETERS_FOR_TOPN")
-                                                .to_string(),
-                                        );
+                                        return Some(Response::error(
+                                            "MISSING_PARAMETERS_FOR_TOPN",
+                                        ));
                                     }
                                 }
 
@@ -242,16 +684,14 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                             });
                                             i += 3;
                                         } else {
-                                            return Some(
-                                                Response::error("INVALID_N_VALUE_FOR_BOTTOMN")
-                                                    .to_string(),
-                                            );
+                                            return Some(Response::error(
+                                                "INVALID_N_VALUE_FOR_BOTTOMN",
+                                            ));
                                         }
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_RAPAMETERS_FOR_BOTTOM")
-                                                .to_string(),
-                                        );
+                                        return Some(Response::error(
+                                            "MISSING_RAPAMETERS_FOR_BOTTOM",
+                                        ));
                                     }
                                 }
 
@@ -262,9 +702,7 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                         ));
                                         i += 2;
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_FIELD_FOR_MEDIAN").to_string(),
-                                        );
+                                        return Some(Response::error("MISSING_FIELD_FOR_MEDIAN"));
                                     }
                                 }
 
@@ -275,9 +713,7 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                         ));
                                         i += 2;
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_FIELD_FOR_STDDEV").to_string(),
-                                        );
+                                        return Some(Response::error("MISSING_FIELD_FOR_STDDEV"));
                                     }
                                 }
 
@@ -292,18 +728,14 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                             });
                                             i += 3;
                                         } else {
-                                            return Some(
-                                                Response::error(
-                                                    "INVALID_SORT_DIRECTION_USE_ASC_OR_DESC",
-                                                )
-                                                .to_string(),
-                                            );
+                                            return Some(Response::error(
+                                                "INVALID_SORT_DIRECTION_USE_ASC_OR_DESC",
+                                            ));
                                         }
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_PARAMETERS_FOR_SORT")
-                                                .to_string(),
-                                        );
+                                        return Some(Response::error(
+                                            "MISSING_PARAMETERS_FOR_SORT",
+                                        ));
                                     }
                                 }
 
@@ -319,24 +751,23 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
                                         });
                                         i += 4;
                                     } else {
-                                        return Some(
-                                            Response::error("MISSING_PARAMETERS_FOR_JOIN")
-                                                .to_string(),
-                                        );
+                                        return Some(Response::error(
+                                            "MISSING_PARAMETERS_FOR_JOIN",
+                                        ));
                                     }
                                 }
 
                                 _ => {
-                                    return Some(Response::error("UNKNOWN_OPERATION").to_string());
+                                    return Some(Response::error("UNKNOWN_OPERATION"));
                                 }
                             }
                         }
 
-                        let result = aggregate(database, operations, db).await;
-                        Response::success(ResponseData::Json(result)).to_string()
+                        let result = aggregate(database, operations, acl, db).await;
+                        Response::success(ResponseData::Json(result))
                     }
                 }
-                Err(e) => Response::error(e).to_string(),
+                Err(e) => Response::error(e),
             },
         ),
 
@@ -344,9 +775,36 @@ async fn process_shared_requests(database: &str, request: String, db: &TinyCache
     }
 }
 
-async fn process_key_value_requests(database: &str, request: String, db: &TinyCache) -> String {
+async fn process_key_value_requests(
+    database: &str,
+    request: String,
+    acl: &KeyAcl,
+    db: &TinyCache,
+) -> Response {
     let parts: Vec<&str> = request.trim().split_whitespace().collect();
-    let response = match parts.as_slice() {
+
+    // every key-value command names its key first, the multi-key set commands only keys and
+    // the stream reads name theirs after `STREAMS`
+    let keys = match parts.as_slice() {
+        [command, keys @ ..]
+            if command.starts_with("SUNION")
+                || command.starts_with("SINTER")
+                || command.starts_with("SDIFF") =>
+        {
+            keys
+        }
+        ["XREAD", arguments @ ..] | ["XREADGROUP", _, _, _, arguments @ ..] => {
+            split_streams(arguments).map_or(&[][..], |(_, keys, _)| keys)
+        }
+        ["XGROUP", _, key, ..] => std::slice::from_ref(key),
+        [_, key, ..] => std::slice::from_ref(key),
+        _ => &[],
+    };
+    if !keys.iter().all(|key| acl.allows(key)) {
+        return Response::error("KEY_ACCESS_DENIED");
+    }
+
+    match parts.as_slice() {
         ////////////////////////////////////////////////////////////////////////////////////////////
         /////////////////////////////////////// KEY_VALUE //////////////////////////////////////////
         ////////////////////////////////////////////////////////////////////////////////////////////
@@ -414,11 +872,25 @@ async fn process_key_value_requests(database: &str, request: String, db: &TinyCa
         }
         ["GET_KEY", key] => match db.get_key_value(database, key).await {
             Some(DataValue::String(s)) => Response::success(ResponseData::String(s)),
-            Some(DataValue::List(l)) => Response::success(ResponseData::List(l)),
+            Some(DataValue::List(l)) => Response::success(ResponseData::List(l.into())),
             Some(DataValue::Set(s)) => {
                 Response::success(ResponseData::Set(s.keys().cloned().collect()))
             }
             Some(DataValue::Json(j)) => Response::success(ResponseData::Json(j)),
+            Some(DataValue::Hash(h)) => Response::success(ResponseData::Json(fields_json(&h))),
+            Some(DataValue::SortedSet(z)) => {
+                Response::success(ResponseData::Json(scored_json(z.into())))
+            }
+            Some(DataValue::Vector(v)) => {
+                Response::success(ResponseData::Json(serde_json::to_value(v).unwrap()))
+            }
+            Some(DataValue::Stream(x)) => {
+                let entries = x.range(Bound::Unbounded, Bound::Unbounded, None);
+                Response::success(ResponseData::Json(entries_json(entries)))
+            }
+            Some(DataValue::Queue(q)) => {
+                Response::success(ResponseData::Json(serde_json::to_value(q).unwrap()))
+            }
             None => Response::error("NOT_FOUND"),
         },
 
@@ -444,7 +916,10 @@ async fn process_key_value_requests(database: &str, request: String, db: &TinyCa
         },
 
         ["Get_All_KV"] => {
-            let data = db.view_data(database).await;
+            let mut data = db.view_data(database).await;
+            if let Some(entries) = data.as_object_mut() {
+                entries.retain(|key, _| acl.allows(key));
+            }
             Response::success(ResponseData::Json(data))
         }
 
@@ -494,10 +969,1022 @@ async fn process_key_value_requests(database: &str, request: String, db: &TinyCa
                 Response::error("INVALID_COMMAND")
             }
         }
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ///////////////////////////////////////// LISTS ////////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        [command @ ("LPUSH" | "RPUSH"), key, values @ ..] if !values.is_empty() => {
+            let end = if *command == "LPUSH" {
+                ListEnd::Head
+            } else {
+                ListEnd::Tail
+            };
+            let values = values.iter().map(|value| value.to_string()).collect();
+            typed_response(db.list_push(database, key, values, end).await, |len| {
+                ResponseData::Json(JsonValue::from(len))
+            })
+        }
+
+        // without a count the popped value is returned as is, with one as a list
+        [command @ ("LPOP" | "RPOP"), key, count @ ..] if count.len() <= 1 => {
+            let end = if *command == "LPOP" {
+                ListEnd::Head
+            } else {
+                ListEnd::Tail
+            };
+            match count.first().map(|count| count.parse::<usize>()) {
+                None => match db.list_pop(database, key, 1, end).await {
+                    Ok(Ok(mut values)) if !values.is_empty() => {
+                        Response::success(ResponseData::String(values.remove(0)))
+                    }
+                    Ok(Ok(_)) => Response::error("NOT_FOUND"),
+                    Ok(Err(e)) => Response::error(e.code()),
+                    Err(e) => Response::error(e.to_string()),
+                },
+                Some(Ok(count)) => typed_response(
+                    db.list_pop(database, key, count, end).await,
+                    ResponseData::List,
+                ),
+                Some(Err(_)) => Response::error("INVALID_COUNT"),
+            }
+        }
+
+        ["LRANGE", key, start, stop] => match (start.parse::<i64>(), stop.parse::<i64>()) {
+            (Ok(start), Ok(stop)) => typed_response(
+                Ok(db
+                    .read_list(database, key, |values| list::range(values, start, stop))
+                    .await),
+                ResponseData::List,
+            ),
+            _ => Response::error("INVALID_INDEX"),
+        },
+
+        ["LLEN", key] => typed_response(
+            Ok(db.read_list(database, key, |values| values.len()).await),
+            |len| ResponseData::Json(JsonValue::from(len)),
+        ),
+
+        ["LINDEX", key, index] => match index.parse::<i64>() {
+            Ok(index) => {
+                match db
+                    .read_list(database, key, |values| list::index(values, index))
+                    .await
+                {
+                    Ok(Some(value)) => Response::success(ResponseData::String(value)),
+                    Ok(None) => Response::error("NOT_FOUND"),
+                    Err(e) => Response::error(e.code()),
+                }
+            }
+            Err(_) => Response::error("INVALID_INDEX"),
+        },
+
+        ["LSET", key, index, value] => match index.parse::<i64>() {
+            Ok(index) => typed_response(
+                db.list_set(database, key, index, value.to_string()).await,
+                |_| ResponseData::String("OK".to_string()),
+            ),
+            Err(_) => Response::error("INVALID_INDEX"),
+        },
+
+        ["LREM", key, count, value] => match count.parse::<i64>() {
+            Ok(count) => typed_response(
+                db.list_remove(database, key, count, value.to_string())
+                    .await,
+                |removed| ResponseData::Json(JsonValue::from(removed)),
+            ),
+            Err(_) => Response::error("INVALID_COUNT"),
+        },
+
+        ["LTRIM", key, start, stop] => match (start.parse::<i64>(), stop.parse::<i64>()) {
+            (Ok(start), Ok(stop)) => {
+                typed_response(db.list_trim(database, key, start, stop).await, |_| {
+                    ResponseData::String("OK".to_string())
+                })
+            }
+            _ => Response::error("INVALID_INDEX"),
+        },
+
+        // answers -1 when the pivot is not in the list
+        ["LINSERT", key, position @ ("BEFORE" | "AFTER"), pivot, value] => typed_response(
+            db.list_insert(
+                database,
+                key,
+                *position == "BEFORE",
+                pivot.to_string(),
+                value.to_string(),
+            )
+            .await,
+            |len| ResponseData::Json(len.map_or(JsonValue::from(-1), JsonValue::from)),
+        ),
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ///////////////////////////////////////// SETS /////////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ["SADD", key, members @ ..] if !members.is_empty() => {
+            let members = members.iter().map(|member| member.to_string()).collect();
+            typed_response(db.set_add(database, key, members).await, |added| {
+                ResponseData::Json(JsonValue::from(added))
+            })
+        }
+
+        ["SREM", key, members @ ..] if !members.is_empty() => {
+            let members = members.iter().map(|member| member.to_string()).collect();
+            typed_response(db.set_remove(database, key, members).await, |removed| {
+                ResponseData::Json(JsonValue::from(removed))
+            })
+        }
+
+        ["SISMEMBER", key, member] => typed_response(
+            Ok(db
+                .read_set(database, key, |set| set.contains_key(*member))
+                .await),
+            |is_member| ResponseData::Json(JsonValue::from(is_member)),
+        ),
+
+        ["SMEMBERS", key] => typed_response(
+            Ok(db
+                .read_set(database, key, |set| set.keys().cloned().collect())
+                .await),
+            ResponseData::Set,
+        ),
+
+        ["SCARD", key] => typed_response(
+            Ok(db.read_set(database, key, |set| set.len()).await),
+            |len| ResponseData::Json(JsonValue::from(len)),
+        ),
+
+        // without a count the member is returned as is, with one as a set
+        ["SPOP", key, count @ ..] if count.len() <= 1 => {
+            match count.first().map(|count| count.parse::<usize>()) {
+                None => match db.set_pop(database, key, 1).await {
+                    Ok(Ok(mut members)) if !members.is_empty() => {
+                        Response::success(ResponseData::String(members.remove(0)))
+                    }
+                    Ok(Ok(_)) => Response::error("NOT_FOUND"),
+                    Ok(Err(e)) => Response::error(e.code()),
+                    Err(e) => Response::error(e.to_string()),
+                },
+                Some(Ok(count)) => {
+                    typed_response(db.set_pop(database, key, count).await, ResponseData::Set)
+                }
+                Some(Err(_)) => Response::error("INVALID_COUNT"),
+            }
+        }
+
+        // a negative count may return the same member more than once
+        ["SRANDMEMBER", key, count @ ..] if count.len() <= 1 => {
+            match count.first().map(|count| count.parse::<i64>()) {
+                None => match db.read_set(database, key, |set| set::sample(set, 1)).await {
+                    Ok(mut members) if !members.is_empty() => {
+                        Response::success(ResponseData::String(members.remove(0)))
+                    }
+                    Ok(_) => Response::error("NOT_FOUND"),
+                    Err(e) => Response::error(e.code()),
+                },
+                Some(Ok(count)) => {
+                    let len = count.unsigned_abs() as usize;
+                    let members = db
+                        .read_set(database, key, |set| match count >= 0 {
+                            true => set::sample(set, len),
+                            false => set::sample_repeated(set, len),
+                        })
+                        .await;
+                    typed_response(Ok(members), ResponseData::List)
+                }
+                Some(Err(_)) => Response::error("INVALID_COUNT"),
+            }
+        }
+
+        [command @ ("SUNION" | "SINTER" | "SDIFF"), keys @ ..] if !keys.is_empty() => {
+            let operation = set_operation(command);
+            typed_response(
+                Ok(db.set_combine(database, operation, keys).await),
+                |members| ResponseData::Set(members.into_keys().collect()),
+            )
+        }
+
+        [command @ ("SUNIONSTORE" | "SINTERSTORE" | "SDIFFSTORE"), destination, keys @ ..]
+            if !keys.is_empty() =>
+        {
+            let operation = set_operation(command);
+            typed_response(
+                db.set_store(database, operation, destination, keys).await,
+                |len| ResponseData::Json(JsonValue::from(len)),
+            )
+        }
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        //////////////////////////////////////// HASHES ////////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ["HSET", key, pairs @ ..] if !pairs.is_empty() && pairs.len().is_multiple_of(2) => {
+            let fields = pairs
+                .chunks(2)
+                .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                .collect();
+            typed_response(db.hash_set(database, key, fields).await, |added| {
+                ResponseData::Json(JsonValue::from(added))
+            })
+        }
+
+        ["HGET", key, field] => {
+            match db
+                .read_hash(database, key, |hash| hash.get(*field).cloned())
+                .await
+            {
+                Ok(Some(value)) => Response::success(ResponseData::String(value)),
+                Ok(None) => Response::error("NOT_FOUND"),
+                Err(e) => Response::error(e.code()),
+            }
+        }
+
+        // missing fields come back as null
+        ["HMGET", key, fields @ ..] if !fields.is_empty() => typed_response(
+            Ok(db
+                .read_hash(database, key, |hash| {
+                    fields
+                        .iter()
+                        .map(|field| {
+                            hash.get(*field)
+                                .map_or(JsonValue::Null, |value| JsonValue::from(value.as_str()))
+                        })
+                        .collect()
+                })
+                .await),
+            |values| ResponseData::Json(JsonValue::Array(values)),
+        ),
+
+        ["HDEL", key, fields @ ..] if !fields.is_empty() => {
+            let fields = fields.iter().map(|field| field.to_string()).collect();
+            typed_response(db.hash_delete(database, key, fields).await, |removed| {
+                ResponseData::Json(JsonValue::from(removed))
+            })
+        }
+
+        ["HGETALL", key] => typed_response(
+            Ok(db.read_hash(database, key, fields_json).await),
+            ResponseData::Json,
+        ),
+
+        ["HINCRBYFLOAT", key, field, amount] => match amount.parse::<f64>() {
+            Ok(amount) => typed_response(
+                db.hash_incr_by_float(database, key, field, amount).await,
+                |value| ResponseData::Json(JsonValue::from(value)),
+            ),
+            Err(_) => Response::error("INVALID_AMOUNT"),
+        },
+
+        ["HEXISTS", key, field] => typed_response(
+            Ok(db
+                .read_hash(database, key, |hash| hash.contains_key(*field))
+                .await),
+            |exists| ResponseData::Json(JsonValue::from(exists)),
+        ),
+
+        ["HKEYS", key] => typed_response(
+            Ok(db
+                .read_hash(database, key, |hash| hash.keys().cloned().collect())
+                .await),
+            ResponseData::List,
+        ),
+
+        ["HLEN", key] => typed_response(
+            Ok(db.read_hash(database, key, |hash| hash.len()).await),
+            |len| ResponseData::Json(JsonValue::from(len)),
+        ),
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ////////////////////////////////////// SORTED SETS /////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        // the flags come before the score member pairs
+        ["ZADD", key, rest @ ..] if !rest.is_empty() => {
+            let flags = rest
+                .iter()
+                .take_while(|flag| matches!(**flag, "NX" | "XX" | "GT" | "LT"))
+                .count();
+            let (flags, pairs) = rest.split_at(flags);
+            let members: Option<Vec<(f64, String)>> = (!pairs.is_empty() && pairs.len().is_multiple_of(2))
+                .then(|| {
+                    pairs
+                        .chunks(2)
+                        .map(|pair| Some((parse_score(pair[0])?, pair[1].to_string())))
+                        .collect()
+                })
+                .flatten();
+            match (AddOptions::parse(flags), members) {
+                (Some(options), Some(members)) => typed_response(
+                    db.sorted_set_add(database, key, members, options).await,
+                    |added| ResponseData::Json(JsonValue::from(added)),
+                ),
+                (None, _) => Response::error("INVALID_OPTIONS"),
+                (_, None) => Response::error("INVALID_SCORE"),
+            }
+        }
+
+        ["ZINCRBY", key, amount, member] => match parse_score(amount) {
+            Some(amount) => typed_response(
+                db.sorted_set_incr_by(database, key, member, amount).await,
+                |score| ResponseData::Json(JsonValue::from(score)),
+            ),
+            None => Response::error("INVALID_AMOUNT"),
+        },
+
+        ["ZSCORE", key, member] => {
+            match db
+                .read_sorted_set(database, key, |set| set.score(member))
+                .await
+            {
+                Ok(Some(score)) => Response::success(ResponseData::Json(JsonValue::from(score))),
+                Ok(None) => Response::error("NOT_FOUND"),
+                Err(e) => Response::error(e.code()),
+            }
+        }
+
+        // with REV the rank counts from the highest score
+        ["ZRANK", key, member, rev @ ..] if rev.is_empty() || rev == ["REV"] => {
+            match db
+                .read_sorted_set(database, key, |set| set.rank(member, !rev.is_empty()))
+                .await
+            {
+                Ok(Some(rank)) => Response::success(ResponseData::Json(JsonValue::from(rank))),
+                Ok(None) => Response::error("NOT_FOUND"),
+                Err(e) => Response::error(e.code()),
+            }
+        }
+
+        ["ZRANGE", key, start, stop, options @ ..] => {
+            match parse_range_query(start, stop, options) {
+                Ok((query, with_scores)) => typed_response(
+                    Ok(db
+                        .read_sorted_set(database, key, |set| set.range(&query))
+                        .await),
+                    |entries| match with_scores {
+                        true => ResponseData::Json(scored_json(entries)),
+                        false => ResponseData::List(
+                            entries.into_iter().map(|(member, _)| member).collect(),
+                        ),
+                    },
+                ),
+                Err(code) => Response::error(code),
+            }
+        }
+
+        ["ZREM", key, members @ ..] if !members.is_empty() => {
+            let members = members.iter().map(|member| member.to_string()).collect();
+            typed_response(
+                db.sorted_set_remove(database, key, members).await,
+                |removed| ResponseData::Json(JsonValue::from(removed)),
+            )
+        }
+
+        [command @ ("ZPOPMIN" | "ZPOPMAX"), key, count @ ..] if count.len() <= 1 => {
+            match count.first().map_or(Ok(1), |count| count.parse::<usize>()) {
+                Ok(count) => typed_response(
+                    db.sorted_set_pop(database, key, count, *command == "ZPOPMAX")
+                        .await,
+                    |entries| ResponseData::Json(scored_json(entries)),
+                ),
+                Err(_) => Response::error("INVALID_COUNT"),
+            }
+        }
+
+        ["ZCOUNT", key, min, max] => match (parse_score_bound(min), parse_score_bound(max)) {
+            (Some(min), Some(max)) => typed_response(
+                Ok(db
+                    .read_sorted_set(database, key, |set| set.count(min, max))
+                    .await),
+                |count| ResponseData::Json(JsonValue::from(count)),
+            ),
+            _ => Response::error("INVALID_SCORE"),
+        },
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        //////////////////////////////////////// STREAMS ///////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        // `MAXLEN` comes before the ID, `~` and `=` are accepted and trim exactly
+        ["XADD", key, rest @ ..] => {
+            let (max_len, rest) = match rest {
+                ["MAXLEN", "~" | "=", max_len, rest @ ..] | ["MAXLEN", max_len, rest @ ..] => {
+                    match max_len.parse::<usize>() {
+                        Ok(max_len) => (Some(max_len), rest),
+                        Err(_) => return Response::error("INVALID_COUNT"),
+                    }
+                }
+                rest => (None, rest),
+            };
+            let [id, pairs @ ..] = rest else {
+                return Response::error("INVALID_COMMAND");
+            };
+            if pairs.is_empty() || !pairs.len().is_multiple_of(2) {
+                return Response::error("INVALID_COMMAND");
+            }
+            let id = match *id {
+                "*" => None,
+                id => match StreamId::parse(id, 0) {
+                    Some(id) => Some(id),
+                    None => return Response::error("INVALID_STREAM_ID"),
+                },
+            };
+            let fields = pairs
+                .chunks(2)
+                .map(|pair| (pair[0].to_string(), pair[1].to_string()))
+                .collect();
+            typed_response(
+                db.stream_add(database, key, id, fields, max_len, compute_now_millis())
+                    .await,
+                |id| ResponseData::String(id.to_string()),
+            )
+        }
+
+        ["XLEN", key] => typed_response(
+            Ok(db.read_stream(database, key, |stream| stream.len()).await),
+            |len| ResponseData::Json(JsonValue::from(len)),
+        ),
+
+        ["XRANGE", key, start, end, count @ ..] => {
+            let count = match count {
+                [] => None,
+                ["COUNT", count] => match count.parse::<usize>() {
+                    Ok(count) => Some(count),
+                    Err(_) => return Response::error("INVALID_COUNT"),
+                },
+                _ => return Response::error("INVALID_COMMAND"),
+            };
+            match (
+                parse_range_bound(start, false),
+                parse_range_bound(end, true),
+            ) {
+                (Some(start), Some(end)) => typed_response(
+                    Ok(db
+                        .read_stream(database, key, |stream| stream.range(start, end, count))
+                        .await),
+                    |entries| ResponseData::Json(entries_json(entries)),
+                ),
+                _ => Response::error("INVALID_STREAM_ID"),
+            }
+        }
+
+        // `$` reads the entries added after the command was received
+        ["XREAD", arguments @ ..] => {
+            let Some((options, keys, ids)) = split_streams(arguments) else {
+                return Response::error("INVALID_COMMAND");
+            };
+            let (count, block) = match parse_stream_read_options(options) {
+                Ok(options) => options,
+                Err(code) => return Response::error(code),
+            };
+            let Some(streams) = parse_stream_ids(keys, ids, "$") else {
+                return Response::error("INVALID_STREAM_ID");
+            };
+            match db.stream_read(database, streams, count, block).await {
+                Ok(streams) => Response::success(ResponseData::Json(streams_json(streams))),
+                Err(e) => Response::error(e.code()),
+            }
+        }
+
+        // `$` starts the group after the last entry, `MKSTREAM` creates a missing stream
+        ["XGROUP", "CREATE", key, group, id, make_stream @ ..]
+            if make_stream.is_empty() || make_stream == ["MKSTREAM"] =>
+        {
+            let start = match *id {
+                "$" => None,
+                id => match StreamId::parse(id, 0) {
+                    Some(id) => Some(id),
+                    None => return Response::error("INVALID_STREAM_ID"),
+                },
+            };
+            typed_response(
+                db.stream_create_group(database, key, group, start, !make_stream.is_empty())
+                    .await,
+                |_| ResponseData::String("OK".to_string()),
+            )
+        }
+
+        ["XGROUP", "DESTROY", key, group] => {
+            match db.stream_destroy_group(database, key, group).await {
+                Ok(Ok(true)) => Response::success(ResponseData::String("DESTROYED".to_string())),
+                Ok(Ok(false)) => Response::error(ValueError::NoSuchGroup.code()),
+                Ok(Err(e)) => Response::error(e.code()),
+                Err(e) => Response::error(e.to_string()),
+            }
+        }
+
+        // `>` reads the entries the group never delivered, other IDs the pending entries of the
+        // consumer after them
+        ["XREADGROUP", "GROUP", group, consumer, arguments @ ..] => {
+            let Some((options, keys, ids)) = split_streams(arguments) else {
+                return Response::error("INVALID_COMMAND");
+            };
+            let (count, block) = match parse_stream_read_options(options) {
+                Ok(options) => options,
+                Err(code) => return Response::error(code),
+            };
+            let Some(streams) = parse_stream_ids(keys, ids, ">") else {
+                return Response::error("INVALID_STREAM_ID");
+            };
+            typed_response(
+                db.stream_read_group(database, group, consumer, streams, count, block)
+                    .await,
+                |streams| ResponseData::Json(streams_json(streams)),
+            )
+        }
+
+        ["XACK", key, group, ids @ ..] if !ids.is_empty() => match parse_ids(ids) {
+            Some(ids) => typed_response(db.stream_ack(database, key, group, ids).await, |acked| {
+                ResponseData::Json(JsonValue::from(acked))
+            }),
+            None => Response::error("INVALID_STREAM_ID"),
+        },
+
+        ["XPENDING", key, group] => typed_response(
+            Ok(db
+                .read_stream(database, key, |stream| stream.pending_summary(group))
+                .await
+                .and_then(|summary| summary)),
+            |summary| ResponseData::Json(serde_json::to_value(summary).unwrap()),
+        ),
+
+        ["XPENDING", key, group, start, end, count, consumer @ ..] if consumer.len() <= 1 => {
+            let (Some(start), Some(end)) = (
+                parse_range_bound(start, false),
+                parse_range_bound(end, true),
+            ) else {
+                return Response::error("INVALID_STREAM_ID");
+            };
+            let Ok(count) = count.parse::<usize>() else {
+                return Response::error("INVALID_COUNT");
+            };
+            let now = compute_now_millis();
+            typed_response(
+                Ok(db
+                    .read_stream(database, key, |stream| {
+                        stream.pending(group, start, end, count, consumer.first().copied(), now)
+                    })
+                    .await
+                    .and_then(|pending| pending)),
+                |pending| ResponseData::Json(serde_json::to_value(pending).unwrap()),
+            )
+        }
+
+        ["XCLAIM", key, group, consumer, min_idle, ids @ ..] if !ids.is_empty() => {
+            match (min_idle.parse::<u64>(), parse_ids(ids)) {
+                (Ok(min_idle), Some(ids)) => typed_response(
+                    db.stream_claim(
+                        database,
+                        key,
+                        group,
+                        consumer,
+                        min_idle,
+                        ids,
+                        compute_now_millis(),
+                    )
+                    .await,
+                    |entries| ResponseData::Json(entries_json(entries)),
+                ),
+                (Err(_), _) => Response::error("INVALID_TIMEOUT"),
+                (_, None) => Response::error("INVALID_STREAM_ID"),
+            }
+        }
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        //////////////////////////////////////// QUEUES ////////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        // delays and visibility timeouts are given in seconds
+        ["QPUSH", key, rest @ ..] if !rest.is_empty() => {
+            let (delay, message) = match rest {
+                ["DELAY", delay, message @ ..] if !message.is_empty() => match parse_seconds(delay)
+                {
+                    Some(delay) => (delay, message),
+                    None => return Response::error("INVALID_DELAY"),
+                },
+                message => (0, message),
+            };
+            typed_response(
+                db.queue_push(
+                    database,
+                    key,
+                    message.join(" "),
+                    delay,
+                    compute_now_millis(),
+                )
+                .await,
+                |id| ResponseData::Json(JsonValue::from(id)),
+            )
+        }
+
+        ["QPOP", key, visibility @ ..] => {
+            let visibility = match visibility {
+                [] => None,
+                ["VISIBILITY", visibility] => match parse_seconds(visibility) {
+                    Some(visibility) => Some(visibility),
+                    None => return Response::error("INVALID_TIMEOUT"),
+                },
+                _ => return Response::error("INVALID_COMMAND"),
+            };
+            match db
+                .queue_pop(database, key, visibility, compute_now_millis())
+                .await
+            {
+                Ok(Ok(Some(delivery))) => {
+                    Response::success(ResponseData::Json(serde_json::to_value(delivery).unwrap()))
+                }
+                Ok(Ok(None)) => Response::error("NOT_FOUND"),
+                Ok(Err(e)) => Response::error(e.code()),
+                Err(e) => Response::error(e.to_string()),
+            }
+        }
+
+        ["QACK", key, receipt] => {
+            typed_response(db.queue_ack(database, key, receipt).await, |_| {
+                ResponseData::String("OK".to_string())
+            })
+        }
+
+        ["QNACK", key, receipt, delay @ ..] => {
+            let delay = match delay {
+                [] => 0,
+                ["DELAY", delay] => match parse_seconds(delay) {
+                    Some(delay) => delay,
+                    None => return Response::error("INVALID_DELAY"),
+                },
+                _ => return Response::error("INVALID_COMMAND"),
+            };
+            typed_response(
+                db.queue_nack(database, key, receipt, delay, compute_now_millis())
+                    .await,
+                |_| ResponseData::String("OK".to_string()),
+            )
+        }
+
+        ["QSTATS", key] => {
+            let now = compute_now_millis();
+            typed_response(
+                Ok(db.read_queue(database, key, |queue| queue.stats(now)).await),
+                |stats| ResponseData::Json(serde_json::to_value(stats).unwrap()),
+            )
+        }
+
+        ["QDEAD", key, count @ ..] if count.len() <= 1 => {
+            match count
+                .first()
+                .map_or(Ok(usize::MAX), |count| count.parse::<usize>())
+            {
+                Ok(count) => typed_response(
+                    Ok(db
+                        .read_queue(database, key, |queue| queue.dead_letters(count))
+                        .await),
+                    |messages| ResponseData::Json(messages_json(messages)),
+                ),
+                Err(_) => Response::error("INVALID_COUNT"),
+            }
+        }
+
+        ["QREDRIVE", key] => typed_response(
+            db.queue_redrive(database, key, compute_now_millis()).await,
+            |redriven| ResponseData::Json(JsonValue::from(redriven)),
+        ),
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        /////////////////////////////////////// DOCUMENTS //////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        // paths are JSON Pointers like `/a/0` or JSONPaths like `$.a[0]`
+        ["JSON_SET", key, path, value @ ..] if !value.is_empty() => {
+            match (parse_path(path), serde_json::from_str(&value.join(" "))) {
+                (Some(pointer), Ok(value)) => {
+                    typed_response(db.json_set(database, key, &pointer, value).await, |_| {
+                        ResponseData::String("OK".to_string())
+                    })
+                }
+                (None, _) => Response::error("INVALID_PATH"),
+                (_, Err(e)) => Response::error(format!("INVALID_JSON: {}", e)),
+            }
+        }
+
+        ["JSON_DEL", key, path] => match parse_path(path) {
+            Some(pointer) => typed_response(db.json_delete(database, key, &pointer).await, |_| {
+                ResponseData::String("OK".to_string())
+            }),
+            None => Response::error("INVALID_PATH"),
+        },
+
+        // the values follow each other, `JSON_ARRAPPEND cart $.items {"id": 1} 2 "x"`
+        ["JSON_ARRAPPEND", key, path, values @ ..] if !values.is_empty() => {
+            let values = values.join(" ");
+            let values: Result<Vec<JsonValue>, _> = serde_json::Deserializer::from_str(&values)
+                .into_iter()
+                .collect();
+            match (parse_path(path), values) {
+                (Some(pointer), Ok(values)) => typed_response(
+                    db.json_array_append(database, key, &pointer, values).await,
+                    |len| ResponseData::Json(JsonValue::from(len)),
+                ),
+                (None, _) => Response::error("INVALID_PATH"),
+                (_, Err(e)) => Response::error(format!("INVALID_JSON: {}", e)),
+            }
+        }
+
+        ["JSON_NUMINCRBY", key, path, amount] => match (parse_path(path), parse_score(amount)) {
+            (Some(pointer), Some(amount)) => typed_response(
+                db.json_number_incr_by(database, key, &pointer, amount)
+                    .await,
+                ResponseData::Json,
+            ),
+            (None, _) => Response::error("INVALID_PATH"),
+            (_, None) => Response::error("INVALID_AMOUNT"),
+        },
+
+        ["JSON_MERGE", key, patch @ ..] if !patch.is_empty() => {
+            match serde_json::from_str(&patch.join(" ")) {
+                Ok(patch) => typed_response(db.json_merge(database, key, patch).await, |_| {
+                    ResponseData::String("OK".to_string())
+                }),
+                Err(e) => Response::error(format!("INVALID_JSON: {}", e)),
+            }
+        }
+
+        ["JSON_PATCH", key, patch @ ..] if !patch.is_empty() => {
+            match serde_json::from_str::<Vec<PatchOperation>>(&patch.join(" ")) {
+                Ok(operations) if operations.iter().all(PatchOperation::is_valid) => {
+                    typed_response(db.json_patch(database, key, operations).await, |_| {
+                        ResponseData::String("OK".to_string())
+                    })
+                }
+                _ => Response::error("INVALID_PATCH"),
+            }
+        }
+
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ///////////////////////////////////////// VECTORS //////////////////////////////////////////
+        ////////////////////////////////////////////////////////////////////////////////////////////
+        ["VADD", key, rest @ ..] if !rest.is_empty() => {
+            let values: Result<Vec<JsonValue>, _> =
+                serde_json::Deserializer::from_str(&rest.join(" "))
+                    .into_iter()
+                    .collect();
+            match values.as_deref() {
+                Ok([vector, metadata @ ..]) if metadata.len() <= 1 => match parse_vector(vector) {
+                    Some(vector) => {
+                        let vector = Vector {
+                            values: vector,
+                            metadata: metadata.first().cloned().unwrap_or(JsonValue::Null),
+                        };
+                        typed_response(db.vector_add(database, key, vector).await, |_| {
+                            ResponseData::String("OK".to_string())
+                        })
+                    }
+                    None => Response::error("INVALID_VECTOR"),
+                },
+                Ok(_) => Response::error("INVALID_COMMAND"),
+                Err(e) => Response::error(format!("INVALID_JSON: {}", e)),
+            }
+        }
+
+        ["VDEL", key] => typed_response(db.vector_delete(database, key).await, |deleted| {
+            ResponseData::Json(JsonValue::from(deleted as u8))
+        }),
+
         _ => Response::error("INVALID_COMMAND"),
+    }
+}
+
+/// *parse_score* reads a score, which has to be finite to be stored
+fn parse_score(score: &str) -> Option<f64> {
+    score.parse::<f64>().ok().filter(|score| score.is_finite())
+}
+
+/// *parse_range_query* reads the arguments of
+/// `ZRANGE <key> <start> <stop> [BYSCORE|BYLEX] [REV] [LIMIT <offset> <count>] [WITHSCORES]`
+/// and tells whether the scores are asked for. With `REV` a score or lex range starts at its
+/// upper bound.
+fn parse_range_query(
+    start: &str,
+    stop: &str,
+    options: &[&str],
+) -> Result<(RangeQuery, bool), &'static str> {
+    let (mut by, mut reverse, mut limit, mut with_scores) = ("BYRANK", false, None, false);
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match *option {
+            "BYSCORE" | "BYLEX" if by == "BYRANK" => by = option,
+            "REV" => reverse = true,
+            "WITHSCORES" => with_scores = true,
+            // a negative count means every member after the offset
+            "LIMIT" => {
+                let offset = options
+                    .next()
+                    .and_then(|offset| offset.parse::<usize>().ok());
+                let count = options.next().and_then(|count| count.parse::<i64>().ok());
+                match (offset, count) {
+                    (Some(offset), Some(count)) => {
+                        limit = Some((offset, usize::try_from(count).unwrap_or(usize::MAX)))
+                    }
+                    _ => return Err("INVALID_LIMIT"),
+                }
+            }
+            _ => return Err("INVALID_COMMAND"),
+        }
+    }
+
+    let (min, max) = match reverse {
+        true => (stop, start),
+        false => (start, stop),
     };
+    let by = match by {
+        "BYSCORE" => match (parse_score_bound(min), parse_score_bound(max)) {
+            (Some(min), Some(max)) => RangeBy::Score(min, max),
+            _ => return Err("INVALID_SCORE"),
+        },
+        "BYLEX" => match (LexBound::parse(min), LexBound::parse(max)) {
+            (Some(min), Some(max)) => RangeBy::Lex(min, max),
+            _ => return Err("INVALID_LEX_BOUND"),
+        },
+        _ if limit.is_some() => return Err("INVALID_LIMIT"),
+        _ => match (start.parse::<i64>(), stop.parse::<i64>()) {
+            (Ok(start), Ok(stop)) => RangeBy::Rank(start, stop),
+            _ => return Err("INVALID_INDEX"),
+        },
+    };
+    Ok((RangeQuery { by, reverse, limit }, with_scores))
+}
+
+/// *parse_vector* reads a vector written as a JSON array of finite numbers
+fn parse_vector(vector: &JsonValue) -> Option<Vec<f32>> {
+    serde_json::from_value::<Vec<f32>>(vector.clone())
+        .ok()
+        .filter(|values| !values.is_empty() && values.iter().all(|value| value.is_finite()))
+}
+
+/// *parse_vector_search* reads the arguments of `VSEARCH` following the vector,
+/// `<count> [FILTER <field> <operator> <value> ...]`. Fields are JSON Pointers into the metadata,
+/// the leading `/` may be left out, and values are JSON or else taken as strings.
+fn parse_vector_search(arguments: &[&str]) -> Result<(usize, Vec<FilterCondition>), &'static str> {
+    let [count, filters @ ..] = arguments else {
+        return Err("INVALID_COMMAND");
+    };
+    let count = count.parse::<usize>().map_err(|_| "INVALID_COUNT")?;
+    let filters = filters
+        .chunks(4)
+        .map(|filter| match filter {
+            ["FILTER", field, operator, value] => Ok(FilterCondition {
+                field: format!("/{}", field.trim_start_matches('/')),
+                operator: operator.to_string(),
+                value: serde_json::from_str(value)
+                    .unwrap_or_else(|_| JsonValue::String(value.to_string())),
+            }),
+            _ => Err("INVALID_FILTER_FORMAT"),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((count, filters))
+}
+
+/// *split_streams* splits the arguments of `XREAD`, and of `XREADGROUP` after its group and
+/// consumer, into the options and the keys and IDs following `STREAMS`, one ID per key
+fn split_streams<'a, 'b>(
+    arguments: &'a [&'b str],
+) -> Option<(&'a [&'b str], &'a [&'b str], &'a [&'b str])> {
+    // options come with a value, which is never taken for `STREAMS`
+    let mut position = 0;
+    while *arguments.get(position)? != "STREAMS" {
+        position += 2;
+    }
+    let streams = &arguments[position + 1..];
+    if streams.is_empty() || !streams.len().is_multiple_of(2) {
+        return None;
+    }
+    let (keys, ids) = streams.split_at(streams.len() / 2);
+    Some((&arguments[..position], keys, ids))
+}
 
-    response.to_string()
+/// *parse_stream_read_options* reads `[COUNT <count>] [BLOCK <milliseconds>]`
+fn parse_stream_read_options(
+    options: &[&str],
+) -> Result<(Option<usize>, Option<Duration>), &'static str> {
+    let (mut count, mut block) = (None, None);
+    for option in options.chunks(2) {
+        match option {
+            ["COUNT", value] => count = Some(value.parse().map_err(|_| "INVALID_COUNT")?),
+            ["BLOCK", value] => {
+                let millis = value.parse().map_err(|_| "INVALID_TIMEOUT")?;
+                block = Some(Duration::from_millis(millis));
+            }
+            _ => return Err("INVALID_COMMAND"),
+        }
+    }
+    Ok((count, block))
+}
+
+/// *parse_stream_ids* pairs the keys of a stream read with their IDs, `latest` standing for the
+/// `None` ID
+fn parse_stream_ids(
+    keys: &[&str],
+    ids: &[&str],
+    latest: &str,
+) -> Option<Vec<(String, Option<StreamId>)>> {
+    keys.iter()
+        .zip(ids)
+        .map(|(key, id)| match *id {
+            id if id == latest => Some((key.to_string(), None)),
+            id => Some((key.to_string(), Some(StreamId::parse(id, 0)?))),
+        })
+        .collect()
+}
+
+/// *parse_ids* reads stream IDs, all of them or none
+fn parse_ids(ids: &[&str]) -> Option<Vec<StreamId>> {
+    ids.iter().map(|id| StreamId::parse(id, 0)).collect()
+}
+
+/// *entries_json* renders stream entries as objects with their ID and fields
+fn entries_json(entries: Vec<StreamEntry>) -> JsonValue {
+    JsonValue::Array(
+        entries
+            .into_iter()
+            .map(|entry| {
+                let fields: serde_json::Map<String, JsonValue> = entry
+                    .fields
+                    .into_iter()
+                    .map(|(field, value)| (field, JsonValue::from(value)))
+                    .collect();
+                serde_json::json!({"id": entry.id, "fields": fields})
+            })
+            .collect(),
+    )
+}
+
+/// *streams_json* renders the entries read from several streams by key
+fn streams_json(streams: Vec<(String, Vec<StreamEntry>)>) -> JsonValue {
+    JsonValue::Object(
+        streams
+            .into_iter()
+            .map(|(key, entries)| (key, entries_json(entries)))
+            .collect(),
+    )
+}
+
+/// *parse_seconds* reads a number of seconds as milliseconds
+fn parse_seconds(seconds: &str) -> Option<u64> {
+    seconds
+        .parse::<u64>()
+        .ok()
+        .map(|seconds| seconds.saturating_mul(1000))
+}
+
+/// *messages_json* renders queue messages with their ID and number of deliveries
+fn messages_json(messages: Vec<QueueMessage>) -> JsonValue {
+    JsonValue::Array(
+        messages
+            .into_iter()
+            .map(|message| {
+                serde_json::json!({
+                    "id": message.id,
+                    "message": message.message,
+                    "deliveries": message.deliveries,
+                })
+            })
+            .collect(),
+    )
+}
+
+/// *scored_json* renders members with their scores as `[member, score]` pairs
+fn scored_json(entries: Vec<(String, f64)>) -> JsonValue {
+    JsonValue::Array(
+        entries
+            .into_iter()
+            .map(|(member, score)| JsonValue::Array(vec![member.into(), score.into()]))
+            .collect(),
+    )
+}
+
+/// *fields_json* renders a hash as a JSON object of string values
+fn fields_json(hash: &Fields) -> JsonValue {
+    JsonValue::Object(
+        hash.iter()
+            .map(|(field, value)| (field.clone(), JsonValue::from(value.as_str())))
+            .collect(),
+    )
+}
+
+/// *set_operation* is the operation of a `SUNION`, `SINTER` or `SDIFF` command or their `*STORE`
+/// variant
+fn set_operation(command: &str) -> SetOperation {
+    if command.starts_with("SUNION") {
+        SetOperation::Union
+    } else if command.starts_with("SINTER") {
+        SetOperation::Inter
+    } else {
+        SetOperation::Diff
+    }
+}
+
+/// *typed_response* answers a command on a typed value, `WRONGTYPE` and the like become errors
+fn typed_response<T>(
+    result: io::Result<Result<T, ValueError>>,
+    data: impl FnOnce(T) -> ResponseData,
+) -> Response {
+    match result {
+        Ok(Ok(value)) => Response::success(data(value)),
+        Ok(Err(e)) => Response::error(e.code()),
+        Err(e) => Response::error(e.to_string()),
+    }
 }
 
 // #[cfg(test)]
@@ -0,0 +1,175 @@
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Query, State,
+    },
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response as HttpResponse},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{
+    db::{
+        db::{DatabaseStats, DatabaseType, TinyCache},
+        events::KeyEvent,
+    },
+    security::{auth::Session, users::Permission},
+    utils::{logs::LogLevel, response::Response},
+};
+
+use super::{client::rate_limited, http::bearer_token, requests::dispatch_request};
+
+#[derive(Deserialize)]
+pub struct WebSocketParams {
+    token: Option<String>,
+}
+
+/// Messages the server sends without being asked, tagged so that clients can tell them
+/// apart from command responses (which always carry a `status` field).
+#[derive(Serialize)]
+#[serde(tag = "push", content = "data", rename_all = "snake_case")]
+enum PushMessage {
+    KeyEvent(KeyEvent),
+    Stats(DatabaseStats),
+}
+
+/// *websocket* upgrades `GET /ws` to a WebSocket bound to an existing session
+///
+/// Browsers cannot set headers on WebSocket requests, so the session token may be passed
+/// either as `?token=<session>` or as an `Authorization: Bearer` header.
+pub async fn websocket(
+    State(db): State<Arc<TinyCache>>,
+    Query(params): Query<WebSocketParams>,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> HttpResponse {
+    let token = match params
+        .token
+        .or_else(|| bearer_token(&headers).map(String::from))
+    {
+        Some(token) => token,
+        None => {
+            return (
+                StatusCode::UNAUTHORIZED,
+                Json(Response::error("MISSING_SESSION_TOKEN")),
+            )
+                .into_response()
+        }
+    };
+
+    match db.auth_manager.validate_session(&token, db.clone()).await {
+        Some(session) => ws.on_upgrade(move |socket| handle_socket(socket, db, session)),
+        None => (
+            StatusCode::UNAUTHORIZED,
+            Json(Response::error("INVALID_OR_EXPIRED_SESSION")),
+        )
+            .into_response(),
+    }
+}
+
+/// *handle_socket* serves a single WebSocket connection
+///
+/// Text frames are commands (without the connection string prefix) and are answered with the
+/// same `Response` envelope as the TCP protocol. Next to that the server pushes key change
+/// events of the session's database and, every `ws_stats_interval_secs`, its `DBSTATS`.
+async fn handle_socket(mut socket: WebSocket, db: Arc<TinyCache>, session: Session) {
+    let mut events = db.events.subscribe();
+    let stats_interval_secs = db.config.ws_stats_interval_secs;
+    let mut stats_interval = tokio::time::interval(Duration::from_secs(stats_interval_secs.max(1)));
+    let mut buckets = db.rate_limiter.connection_buckets();
+
+    let _ = db
+        .logger
+        .log_info(
+            &format!("WebSocket client connected to {}", session.database),
+            LogLevel::System,
+            &db,
+        )
+        .await;
+
+    loop {
+        let payload = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(command))) => {
+                    let response = if db.auth_manager.validate_session(&session.id, db.clone()).await.is_none() {
+                        Response::error("SESSION_EXPIRED")
+                    } else if let Err(retry_after) = db.rate_limiter.check(&mut buckets, &session.username, &session.namespace, command.len()) {
+                        rate_limited(retry_after)
+                    } else {
+                        let db_type = DatabaseType::from_str(&db.config.database_type);
+                        dispatch_request(&session, &session.namespace, db_type, command.as_str().to_string(), &db).await
+                    };
+                    serde_json::to_string(&response)
+                }
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => continue, // pings are answered by axum, binary frames are ignored
+                Some(Err(e)) => {
+                    let _ = db.logger.log_error(&format!("WebSocket read failed: {}", e), LogLevel::System, &db).await;
+                    break;
+                }
+            },
+            event = events.recv() => match event {
+                // events carry the internal database context, clients only get to see the database name
+                Ok(event) if event.database == session.namespace && can_read(&db, &session, event.key.as_deref()) => serde_json::to_string(&PushMessage::KeyEvent(KeyEvent {
+                    database: session.database.clone(),
+                    ..event
+                })),
+                Ok(_) => continue,
+                Err(RecvError::Lagged(skipped)) => {
+                    let _ = db.logger.log_warn(&format!("WebSocket client lagged behind, {} key events dropped", skipped), LogLevel::System, &db).await;
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = db.shutdown_requested() => {
+                let _ = socket.send(Message::Close(Some(CloseFrame {
+                    code: close_code::AWAY,
+                    reason: "server shutting down".into(),
+                }))).await;
+                break;
+            }
+            _ = stats_interval.tick(), if stats_interval_secs > 0 => {
+                if !can_read(&db, &session, None) {
+                    continue;
+                }
+                match db.get_database_stats(&session.namespace).await {
+                    Some(stats) => serde_json::to_string(&PushMessage::Stats(stats)),
+                    None => continue,
+                }
+            }
+        };
+
+        let payload = match payload {
+            Ok(payload) => payload,
+            Err(_) => continue,
+        };
+
+        if socket.send(Message::Text(payload.into())).await.is_err() {
+            break;
+        }
+    }
+
+    let _ = db
+        .logger
+        .log_info(
+            &format!("WebSocket client disconnected from {}", session.database),
+            LogLevel::System,
+            &db,
+        )
+        .await;
+}
+
+/// *can_read* tells whether the session may still receive pushes about the key, or about the
+/// database for `None`. Grants and key patterns can change while the socket is open.
+fn can_read(db: &TinyCache, session: &Session, key: Option<&str>) -> bool {
+    db.auth_manager
+        .authorize(&session.username, &session.namespace, Permission::Read)
+        && key.is_none_or(|key| {
+            db.auth_manager
+                .key_acl(&session.username, &session.namespace)
+                .allows(key)
+        })
+}
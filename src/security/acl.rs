@@ -0,0 +1,77 @@
+/// acl.rs restricts users to the keys matching their key patterns.
+/// Patterns are globs where `*` matches any run of characters and `?` a single one, so a prefix
+/// rule is written `svcA:*`. A user without patterns on a database may touch every key.
+#[derive(Debug, Clone, Default)]
+pub struct KeyAcl {
+    patterns: Option<Vec<String>>,
+}
+
+impl KeyAcl {
+    /// *new* restricts to the patterns, an empty list leaves the keys unrestricted
+    pub fn new(patterns: Vec<String>) -> Self {
+        Self {
+            patterns: (!patterns.is_empty()).then_some(patterns),
+        }
+    }
+
+    pub fn is_restricted(&self) -> bool {
+        self.patterns.is_some()
+    }
+
+    pub fn allows(&self, key: &str) -> bool {
+        match &self.patterns {
+            Some(patterns) => patterns.iter().any(|pattern| glob_match(pattern, key)),
+            None => true,
+        }
+    }
+}
+
+/// *glob_match* matches `text` against a pattern with `*` and `?` wildcards
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and of the text it was matched against, for backtracking
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            // let the last `*` swallow one more character
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_patterns() {
+        assert!(glob_match("svcA:*", "svcA:orders:1"));
+        assert!(!glob_match("svcA:*", "svcB:orders:1"));
+        assert!(glob_match("user:?:profile", "user:7:profile"));
+        assert!(!glob_match("user:?:profile", "user:42:profile"));
+        assert!(glob_match("*:session:*", "web:session:abc"));
+        assert!(glob_match("exact", "exact"));
+        assert!(!glob_match("exact", "exactly"));
+
+        let acl = KeyAcl::new(vec!["svcA:*".to_string(), "shared".to_string()]);
+        assert!(acl.is_restricted());
+        assert!(acl.allows("shared"));
+        assert!(!acl.allows("svcB:1"));
+        assert!(KeyAcl::new(Vec::new()).allows("anything"));
+    }
+}
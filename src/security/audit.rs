@@ -0,0 +1,371 @@
+/// audit.rs keeps the append-only audit log of security relevant and destructive operations.
+/// Every entry is a JSON line holding the SHA-256 hash of the previous entry and its own hash
+/// over both, so editing or removing an entry breaks the chain from there on. `AUDIT_VERIFY`
+/// walks the chain, `AUDIT` reads entries back by time range.
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, io, path::PathBuf};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+    sync::Mutex,
+};
+
+use super::{
+    auth::{session_fingerprint, Session},
+    config::DBConfig,
+};
+use crate::utils::utils::compute_now_timestamp;
+
+/// Previous hash of the first entry
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    AuthSuccess,
+    AuthFailure,
+    Logout,
+    UserChange,   // CREATE_USER, DROP_USER, GRANT, REVOKE
+    AclChange,    // ACL_ADD, ACL_REMOVE
+    TokenChange,  // CREATE_TOKEN, REVOKE_TOKEN
+    ClearDb,      // CLEAR_DB
+    DropDb,       // every database drop, whoever asked for it
+    ConfigChange, // the config differs from the one of the last start
+    AdminCommand, // any other command needing the admin permission
+}
+
+/// An operation to audit, see `AuditLog::record`
+#[derive(Debug, Clone)]
+pub struct AuditEvent {
+    action: AuditAction,
+    user: Option<String>,
+    peer: Option<String>,
+    session: Option<String>,
+    database: Option<String>,
+    detail: String,
+    outcome: String,
+}
+
+impl AuditEvent {
+    pub fn new(action: AuditAction, detail: impl Into<String>) -> Self {
+        Self {
+            action,
+            user: None,
+            peer: None,
+            session: None,
+            database: None,
+            detail: detail.into(),
+            outcome: "ok".to_string(),
+        }
+    }
+
+    /// *session* attributes the event to the session's user, peer and database, the session
+    /// itself only by its fingerprint
+    pub fn session(mut self, session: &Session) -> Self {
+        self.user = Some(session.username.clone());
+        self.peer = session.peer.clone();
+        self.session = Some(session_fingerprint(&session.id));
+        self.database = Some(session.database.clone());
+        self
+    }
+
+    pub fn user(mut self, user: Option<String>) -> Self {
+        self.user = user;
+        self
+    }
+
+    pub fn peer(mut self, peer: Option<String>) -> Self {
+        self.peer = peer;
+        self
+    }
+
+    pub fn database(mut self, database: Option<String>) -> Self {
+        self.database = database;
+        self
+    }
+
+    /// *failed* marks the operation as failed with the error it returned
+    pub fn failed(mut self, error: impl Into<String>) -> Self {
+        self.outcome = error.into();
+        self
+    }
+}
+
+/// The hashed part of an entry
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AuditRecord {
+    seq: u64,
+    timestamp: u64,
+    action: AuditAction,
+    user: Option<String>,
+    peer: Option<String>,
+    session: Option<String>,
+    database: Option<String>,
+    detail: String,
+    outcome: String,
+    // digest per config key, only on `ConfigChange`, to tell which keys the next change touches
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    config: Option<BTreeMap<String, String>>,
+    prev_hash: String,
+}
+
+impl AuditRecord {
+    fn compute_hash(&self) -> String {
+        let serialized = serde_json::to_string(self).unwrap_or_default();
+        hex::encode(Sha256::digest(
+            format!("{}{}", self.prev_hash, serialized).as_bytes(),
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    #[serde(flatten)]
+    record: AuditRecord,
+    hash: String,
+}
+
+/// What `AUDIT_VERIFY` reports, `broken_at` is the first entry that does not chain up
+#[derive(Debug, Clone, Serialize)]
+pub struct AuditVerification {
+    pub entries: u64,
+    pub valid: bool,
+    pub broken_at: Option<u64>,
+}
+
+struct AuditTail {
+    file: File,
+    seq: u64,
+    last_hash: String,
+    config: Option<BTreeMap<String, String>>, // config digests of the last `ConfigChange`
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    tail: Mutex<AuditTail>,
+}
+
+impl AuditLog {
+    /// *open* opens the log at `path` for appending, continuing the chain of its last entry
+    pub async fn open(path: PathBuf) -> io::Result<Self> {
+        let entries = read_entries(&path).await?;
+        let config = entries
+            .iter()
+            .rev()
+            .find_map(|entry| entry.record.config.clone());
+        let (seq, last_hash) = entries
+            .last()
+            .map_or((0, GENESIS_HASH.to_string()), |entry| {
+                (entry.record.seq, entry.hash.clone())
+            });
+
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        Ok(Self {
+            path,
+            tail: Mutex::new(AuditTail {
+                file,
+                seq,
+                last_hash,
+                config,
+            }),
+        })
+    }
+
+    /// *record* appends the event and syncs it to disk before returning
+    pub async fn record(&self, event: AuditEvent) -> io::Result<()> {
+        let mut tail = self.tail.lock().await;
+        Self::append(&mut tail, event, None).await
+    }
+
+    /// *record_config* records a `ConfigChange` naming the changed keys when the config differs
+    /// from the one recorded last. Only digests of the values are kept, never the values.
+    pub async fn record_config(&self, config: &DBConfig) -> io::Result<()> {
+        let digests: BTreeMap<String, String> = match serde_json::to_value(config)? {
+            serde_json::Value::Object(values) => values
+                .into_iter()
+                .map(|(key, value)| {
+                    let digest = hex::encode(Sha256::digest(value.to_string().as_bytes()));
+                    (key, digest)
+                })
+                .collect(),
+            _ => BTreeMap::new(),
+        };
+
+        let mut tail = self.tail.lock().await;
+        let changed: Vec<&str> = match &tail.config {
+            Some(previous) => digests
+                .iter()
+                .filter(|(key, digest)| previous.get(*key) != Some(*digest))
+                .map(|(key, _)| key.as_str())
+                .chain(
+                    previous
+                        .keys()
+                        .filter(|key| !digests.contains_key(*key))
+                        .map(String::as_str),
+                )
+                .collect(),
+            None => vec!["*"],
+        };
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        let event = AuditEvent::new(
+            AuditAction::ConfigChange,
+            format!("changed: {}", changed.join(", ")),
+        );
+        Self::append(&mut tail, event, Some(digests.clone())).await?;
+        tail.config = Some(digests);
+        Ok(())
+    }
+
+    async fn append(
+        tail: &mut AuditTail,
+        event: AuditEvent,
+        config: Option<BTreeMap<String, String>>,
+    ) -> io::Result<()> {
+        let record = AuditRecord {
+            seq: tail.seq + 1,
+            timestamp: compute_now_timestamp(),
+            action: event.action,
+            user: event.user,
+            peer: event.peer,
+            session: event.session,
+            database: event.database,
+            detail: event.detail,
+            outcome: event.outcome,
+            config,
+            prev_hash: tail.last_hash.clone(),
+        };
+        let entry = AuditEntry {
+            hash: record.compute_hash(),
+            record,
+        };
+
+        let line = serde_json::to_string(&entry)? + "\n";
+        tail.file.write_all(line.as_bytes()).await?;
+        tail.file.sync_data().await?;
+
+        tail.seq = entry.record.seq;
+        tail.last_hash = entry.hash;
+        Ok(())
+    }
+
+    /// *query* returns the entries recorded between `from` and `to`, unix seconds inclusive
+    pub async fn query(&self, from: Option<u64>, to: Option<u64>) -> io::Result<Vec<AuditEntry>> {
+        let _tail = self.tail.lock().await;
+        let entries = read_entries(&self.path).await?;
+        Ok(entries
+            .into_iter()
+            .filter(|entry| from.is_none_or(|from| entry.record.timestamp >= from))
+            .filter(|entry| to.is_none_or(|to| entry.record.timestamp <= to))
+            .collect())
+    }
+
+    /// *verify* recomputes the hash chain and reports the first entry that does not match
+    pub async fn verify(&self) -> io::Result<AuditVerification> {
+        let _tail = self.tail.lock().await;
+        let entries = read_entries(&self.path).await?;
+
+        let mut prev_hash = GENESIS_HASH.to_string();
+        let mut broken_at = None;
+        for (index, entry) in entries.iter().enumerate() {
+            if entry.record.seq != index as u64 + 1
+                || entry.record.prev_hash != prev_hash
+                || entry.record.compute_hash() != entry.hash
+            {
+                broken_at = Some(index as u64 + 1);
+                break;
+            }
+            prev_hash = entry.hash.clone();
+        }
+
+        Ok(AuditVerification {
+            entries: entries.len() as u64,
+            valid: broken_at.is_none(),
+            broken_at,
+        })
+    }
+}
+
+/// *read_entries* parses the log, a line that is not an entry is kept as a broken one so that
+/// verification fails on it instead of skipping it
+async fn read_entries(path: &PathBuf) -> io::Result<Vec<AuditEntry>> {
+    let contents = match fs::read_to_string(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    Ok(contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| {
+            serde_json::from_str(line).unwrap_or_else(|_| AuditEntry {
+                record: AuditRecord {
+                    seq: 0,
+                    timestamp: 0,
+                    action: AuditAction::AdminCommand,
+                    user: None,
+                    peer: None,
+                    session: None,
+                    database: None,
+                    detail: line.to_string(),
+                    outcome: "unreadable".to_string(),
+                    config: None,
+                    prev_hash: String::new(),
+                },
+                hash: String::new(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hash_chain() {
+        let path =
+            std::env::temp_dir().join(format!("tinycache-audit-{}.log", uuid::Uuid::new_v4()));
+
+        let audit = AuditLog::open(path.clone()).await.unwrap();
+        audit
+            .record(AuditEvent::new(AuditAction::AuthSuccess, "").user(Some("admin".into())))
+            .await
+            .unwrap();
+        audit
+            .record(AuditEvent::new(AuditAction::ClearDb, "CLEAR_DB").failed("PERMISSION_DENIED"))
+            .await
+            .unwrap();
+        let mut config = DBConfig::default();
+        audit.record_config(&config).await.unwrap();
+        audit.record_config(&config).await.unwrap();
+
+        // reopening continues the chain and remembers the config
+        let audit = AuditLog::open(path.clone()).await.unwrap();
+        config.idle_timeout_secs += 1;
+        audit.record_config(&config).await.unwrap();
+
+        let entries = audit.query(None, None).await.unwrap();
+        assert_eq!(entries.len(), 4);
+        assert_eq!(entries[3].record.detail, "changed: idle_timeout_secs");
+        assert!(audit.verify().await.unwrap().valid);
+        assert!(audit.query(Some(u64::MAX), None).await.unwrap().is_empty());
+
+        // editing an entry breaks the chain from there on
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::write(&path, contents.replace("PERMISSION_DENIED", "ok")).unwrap();
+        let verification = audit.verify().await.unwrap();
+        assert!(!verification.valid);
+        assert_eq!(verification.broken_at, Some(2));
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -1,16 +1,29 @@
 use argon2::{Argon2, PasswordHash, PasswordVerifier};
 use std::{
     collections::HashMap,
+    io,
+    net::IpAddr,
+    path::PathBuf,
     sync::{Arc, RwLock},
     time::{Duration, SystemTime},
 };
+use tokio::{fs, sync::Mutex};
 use uuid::Uuid;
 
-use super::config::DBConfig;
+use super::{
+    acl::KeyAcl,
+    audit::{AuditAction, AuditEvent},
+    config::DBConfig,
+    lockout::{LockoutTracker, LOCKED_OUT},
+    tokens::{TokenStore, TOKEN_PRINCIPAL_PREFIX, TOKEN_USERNAME},
+    users::{Permission, UserStore, ALL_DATABASES},
+};
 use crate::db::db::TinyCache;
+use crate::requests::listener::Peer;
 use crate::utils::logs::LogLevel;
 use crate::utils::utils::parse_connection_string;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum DeploymentMode {
@@ -20,59 +33,178 @@ pub enum DeploymentMode {
 
 use colored::*;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[allow(dead_code)]
 pub struct Session {
     pub id: String,
     pub username: String,
     pub database: String,
+    pub namespace: String, // the database context requests of this session are stored under
+    #[serde(default)]
+    pub peer: Option<String>, // the address the session was opened or last resumed from
     pub created_at: SystemTime,
     pub expires_at: SystemTime,
 }
 
+/// *session_fingerprint* stands in for a session id in logs. Ids are resumable credentials, the
+/// start of their SHA-256 hash tells sessions apart without revealing them.
+pub fn session_fingerprint(session_id: &str) -> String {
+    hex::encode(&Sha256::digest(session_id.as_bytes())[..6])
+}
+
 pub struct AuthManager {
     config: Arc<DBConfig>,
     sessions: Arc<RwLock<HashMap<String, Session>>>,
+    pub lockouts: LockoutTracker, // *lockouts* throttles failed authentication attempts
+    pub users: UserStore,         // *users* holds the accounts next to the configured admin
+    pub tokens: TokenStore,       // *tokens* holds the API tokens of service accounts
+    sessions_path: Option<PathBuf>, // *sessions_path* is set when sessions outlive restarts
+    sessions_save_lock: Mutex<()>,
 }
 
 impl AuthManager {
-    pub fn new(config: DBConfig) -> Self {
+    pub fn new(
+        config: DBConfig,
+        users: UserStore,
+        tokens: TokenStore,
+        sessions_path: Option<PathBuf>,
+    ) -> Self {
         println!("{}", ">>> auth manager initialised".dimmed());
         Self {
+            lockouts: LockoutTracker::new(&config),
             config: Arc::new(config),
             sessions: Arc::new(RwLock::new(HashMap::new())),
+            users,
+            tokens,
+            sessions_path,
+            sessions_save_lock: Mutex::new(()),
+        }
+    }
+
+    /// *key_acl* returns the keys the user may touch on the database, the admin may touch all
+    pub fn key_acl(&self, username: &str, database: &str) -> KeyAcl {
+        if username == self.config.admin {
+            return KeyAcl::default();
         }
+        self.users.key_acl(username, database)
     }
 
+    /// *authorize* tells whether the user may run commands needing `permission` on the database.
+    /// The configured admin may do everything, `Admin` is only ever granted server wide.
+    /// Token principals (`token:<name>`) are checked against the grants of their token.
+    pub fn authorize(&self, username: &str, database: &str, permission: Permission) -> bool {
+        if username == self.config.admin {
+            return true;
+        }
+
+        let database = if permission == Permission::Admin {
+            ALL_DATABASES
+        } else {
+            database
+        };
+        let permissions = match username.strip_prefix(TOKEN_PRINCIPAL_PREFIX) {
+            Some(name) => self.tokens.permissions(name, database),
+            None => self.users.permissions(username, database),
+        };
+        permissions.contains(&permission)
+    }
+
+    /// *authenticate* verifies the connection string of a client connecting from `peer`
+    ///
+    /// Besides `tinycache://<username>:<password>@<database>:<type>` clients may present an API
+    /// token, either as `tinycache://token:<token>@<database>:<type>` or `AUTH TOKEN <token>
+    /// [database]`, which connects to the token's database when none is named.
+    /// Failed attempts are counted per source IP and per username, blocked ones are rejected
+    /// with an error starting with `LOCKED_OUT`. Every attempt is written to the audit log.
     pub async fn authenticate(
         &self,
         connection_string: &str,
+        peer: &Peer,
         db: Arc<TinyCache>,
     ) -> Result<Session, String> {
-        let (username, password, database, db_type) = parse_connection_string(connection_string)?;
+        self.authenticate_connection(connection_string, peer, db, true)
+            .await
+    }
 
-        if username != self.config.admin {
-            return Err("Invalid username".to_string());
-        }
+    /// *authenticate_trusted* creates a session without verifying the password
+    ///
+    /// Only used for peers whose identity is established by the transport itself, such as a
+    /// trusted uid/gid on the Unix domain socket. Username, database and type are still checked.
+    pub async fn authenticate_trusted(
+        &self,
+        connection_string: &str,
+        peer: &Peer,
+        db: Arc<TinyCache>,
+    ) -> Result<Session, String> {
+        self.authenticate_connection(connection_string, peer, db, false)
+            .await
+    }
 
-        if database != self.config.database {
-            return Err("Invalid database name".to_string());
-        }
+    async fn authenticate_connection(
+        &self,
+        connection_string: &str,
+        peer: &Peer,
+        db: Arc<TinyCache>,
+        verify_password: bool,
+    ) -> Result<Session, String> {
+        let result = self
+            .open_session(connection_string, peer, db.clone(), verify_password)
+            .await;
 
-        if db_type != self.config.database_type {
-            return Err("Invalid database type".to_string());
-        }
+        let event = match &result {
+            Ok(session) => AuditEvent::new(AuditAction::AuthSuccess, "").session(session),
+            Err(e) => {
+                let (user, database) = match self.parse_credentials(connection_string) {
+                    Ok((user, _, database, _)) => (Some(user), database),
+                    Err(_) => (None, None),
+                };
+                AuditEvent::new(AuditAction::AuthFailure, "")
+                    .user(user)
+                    .peer(Some(peer.to_string()))
+                    .database(database)
+                    .failed(e.as_str())
+            }
+        };
+        let _ = db.audit.record(event).await;
 
-        let parsed_hash = PasswordHash::new(&self.config.password)
-            .map_err(|_| "Invalid password hash format".to_string())?;
+        result
+    }
 
-        let argon2 = Argon2::default();
-        let is_valid = argon2
-            .verify_password(password.as_bytes(), &parsed_hash)
-            .is_ok();
+    async fn open_session(
+        &self,
+        connection_string: &str,
+        peer: &Peer,
+        db: Arc<TinyCache>,
+        verify_password: bool,
+    ) -> Result<Session, String> {
+        let source = peer.ip();
+        let (username, password, database, db_type) = self.parse_credentials(connection_string)?;
+        let is_token = username == TOKEN_USERNAME;
+        // token failures only count against the source, the shared username would let anyone
+        // lock out every token
+        let lockout_user = if is_token { "" } else { username.as_str() };
 
-        if !is_valid {
-            return Err("Invalid password".to_string());
+        // trusted peers are never locked out, so an admin on the socket can always clear lockouts
+        if verify_password {
+            self.check_lockout(source, lockout_user)?;
+        }
+
+        let verified = if is_token {
+            self.verify_token(&password, database, &db_type).await
+        } else {
+            let database = database.unwrap_or_default();
+            self.verify_credentials(&username, &password, &database, &db_type, verify_password)
+                .map(|_| (username.clone(), database))
+        };
+        let (principal, database) = match verified {
+            Ok(verified) => verified,
+            Err(e) => {
+                self.record_failure(source, lockout_user, &db).await;
+                return Err(e);
+            }
+        };
+        if verify_password {
+            self.lockouts.record_success(source, lockout_user);
         }
 
         let _ = db
@@ -80,17 +212,194 @@ impl AuthManager {
             .log_info("user credentials are valid", LogLevel::System, &db)
             .await;
 
-        let session = self.create_session(username, database, db).await?;
+        let namespace = database.clone();
+        let session = self
+            .create_session(principal, database, namespace, peer.to_string(), db)
+            .await?;
 
         self.cleanup_expired_sessions().await;
 
         Ok(session)
     }
 
+    /// *resume* picks up a live session on a new connection, `RESUME <session-id>`, without
+    /// verifying the password again. Unknown ids count as failures against the source IP.
+    pub async fn resume(
+        &self,
+        session_id: &str,
+        peer: &Peer,
+        db: Arc<TinyCache>,
+    ) -> Result<Session, String> {
+        let source = peer.ip();
+        let result = match self.check_lockout(source, "") {
+            Err(e) => Err(e),
+            Ok(()) => match self.validate_session(session_id, db.clone()).await {
+                Some(mut session) => {
+                    self.lockouts.record_success(source, "");
+                    session.peer = Some(peer.to_string());
+                    if let Ok(mut sessions) = self.sessions.write() {
+                        if let Some(stored) = sessions.get_mut(session_id) {
+                            stored.peer = session.peer.clone();
+                        }
+                    }
+                    Ok(session)
+                }
+                None => {
+                    self.record_failure(source, "", &db).await;
+                    Err("Invalid or expired session".to_string())
+                }
+            },
+        };
+
+        let event = match &result {
+            Ok(session) => AuditEvent::new(AuditAction::AuthSuccess, "RESUME").session(session),
+            Err(e) => AuditEvent::new(AuditAction::AuthFailure, "RESUME")
+                .peer(Some(peer.to_string()))
+                .failed(e.as_str()),
+        };
+        let _ = db.audit.record(event).await;
+
+        result
+    }
+
+    /// *logout* ends the session, returns `false` if it did not exist
+    pub async fn logout(&self, session_id: &str) -> bool {
+        let removed = match self.sessions.write() {
+            Ok(mut sessions) => sessions.remove(session_id).is_some(),
+            Err(_) => false,
+        };
+        if removed {
+            let _ = self.save_sessions().await;
+        }
+        removed
+    }
+
+    fn check_lockout(&self, source: Option<IpAddr>, username: &str) -> Result<(), String> {
+        self.lockouts
+            .check(source, username)
+            .map_err(|retry_after| {
+                format!(
+                    "{}, retry in {}s",
+                    LOCKED_OUT,
+                    retry_after.as_secs_f64().ceil() as u64
+                )
+            })
+    }
+
+    async fn record_failure(&self, source: Option<IpAddr>, username: &str, db: &TinyCache) {
+        for key in self.lockouts.record_failure(source, username) {
+            let _ = db
+                .logger
+                .log_warn(
+                    &format!(
+                        "{} locked out for {}s after {} failed authentication attempts",
+                        key, self.config.auth_lockout_secs, self.config.auth_max_failures
+                    ),
+                    LogLevel::System,
+                    db,
+                )
+                .await;
+        }
+    }
+
+    /// *parse_credentials* splits the first line of a client into username, password, database
+    /// and type. `AUTH TOKEN` lines may leave the database out.
+    fn parse_credentials(
+        &self,
+        line: &str,
+    ) -> Result<(String, String, Option<String>, String), String> {
+        if let Some(rest) = line.strip_prefix("AUTH TOKEN ") {
+            let mut parts = rest.split_whitespace();
+            let token = parts.next().ok_or("Missing token")?;
+            return Ok((
+                TOKEN_USERNAME.to_string(),
+                token.to_string(),
+                parts.next().map(str::to_string),
+                self.config.database_type.clone(),
+            ));
+        }
+
+        let (username, password, database, db_type) = parse_connection_string(line)?;
+        Ok((username, password, Some(database), db_type))
+    }
+
+    /// *verify_token* checks an API token and returns its principal and the database to use
+    async fn verify_token(
+        &self,
+        token: &str,
+        database: Option<String>,
+        db_type: &str,
+    ) -> Result<(String, String), String> {
+        if db_type != self.config.database_type {
+            return Err("Invalid database type".to_string());
+        }
+
+        let name = self
+            .tokens
+            .authenticate(token)
+            .await
+            .ok_or("Invalid or expired token")?;
+        let database = database
+            .or_else(|| self.tokens.default_database(&name))
+            .ok_or("Missing database")?;
+
+        if self.tokens.permissions(&name, &database).is_empty() {
+            return Err("No access to database".to_string());
+        }
+
+        Ok((format!("{}{}", TOKEN_PRINCIPAL_PREFIX, name), database))
+    }
+
+    fn verify_credentials(
+        &self,
+        username: &str,
+        password: &str,
+        database: &str,
+        db_type: &str,
+        verify_password: bool,
+    ) -> Result<(), String> {
+        if db_type != self.config.database_type {
+            return Err("Invalid database type".to_string());
+        }
+
+        if username == self.config.admin {
+            if verify_password {
+                let parsed_hash = PasswordHash::new(&self.config.password)
+                    .map_err(|_| "Invalid password hash format".to_string())?;
+
+                let argon2 = Argon2::default();
+                let is_valid = argon2
+                    .verify_password(password.as_bytes(), &parsed_hash)
+                    .is_ok();
+
+                if !is_valid {
+                    return Err("Invalid password".to_string());
+                }
+            }
+            return Ok(());
+        }
+
+        if !self.users.exists(username) {
+            return Err("Invalid username".to_string());
+        }
+
+        if verify_password && !self.users.verify(username, password) {
+            return Err("Invalid password".to_string());
+        }
+
+        if self.users.permissions(username, database).is_empty() {
+            return Err("No access to database".to_string());
+        }
+
+        Ok(())
+    }
+
     async fn create_session(
         &self,
         username: String,
         database: String,
+        namespace: String,
+        peer: String,
         db: Arc<TinyCache>,
     ) -> Result<Session, String> {
         let session_id = Uuid::new_v4().to_string();
@@ -100,6 +409,8 @@ impl AuthManager {
             id: session_id.clone(),
             username,
             database,
+            namespace,
+            peer: Some(peer),
             created_at: now,
             expires_at: now + Duration::from_secs(self.config.session_ttl),
         };
@@ -111,11 +422,15 @@ impl AuthManager {
                 .map_err(|_| "Failed to acquire write lock".to_string())?;
             sessions.insert(session_id.clone(), session.clone());
         }
+        let _ = self.save_sessions().await;
 
         let _ = db
             .logger
             .log_info(
-                &format!("session with id {} created successfully", session_id),
+                &format!(
+                    "session {} created successfully",
+                    session_fingerprint(&session_id)
+                ),
                 LogLevel::System,
                 &db,
             )
@@ -153,7 +468,10 @@ impl AuthManager {
             let _ = db
                 .logger
                 .log_info(
-                    &format!("session with id {} validated successfully", session_id),
+                    &format!(
+                        "session {} validated successfully",
+                        session_fingerprint(session_id)
+                    ),
                     LogLevel::System,
                     &db,
                 )
@@ -176,4 +494,50 @@ impl AuthManager {
         }
         None
     }
+
+    /// *load_sessions* restores the live sessions saved by `save_sessions`, a missing file
+    /// means there are none
+    pub async fn load_sessions(&self) -> io::Result<()> {
+        let Some(path) = &self.sessions_path else {
+            return Ok(());
+        };
+        let mut restored: HashMap<String, Session> = match fs::read(path).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+
+        let now = SystemTime::now();
+        restored.retain(|_, session| session.expires_at > now);
+        if let Ok(mut sessions) = self.sessions.write() {
+            sessions.extend(restored);
+        }
+        Ok(())
+    }
+
+    /// *save_sessions* writes the live sessions with write-then-rename, only when
+    /// `persist_sessions` is set
+    pub async fn save_sessions(&self) -> io::Result<()> {
+        let Some(path) = &self.sessions_path else {
+            return Ok(());
+        };
+        let _guard = self.sessions_save_lock.lock().await;
+        let serialized = {
+            let sessions = self
+                .sessions
+                .read()
+                .map_err(|_| io::Error::other("Failed to acquire read lock"))?;
+            let now = SystemTime::now();
+            let live: HashMap<&String, &Session> = sessions
+                .iter()
+                .filter(|(_, session)| session.expires_at > now)
+                .collect();
+            serde_json::to_vec_pretty(&live)?
+        };
+
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(&tmp_path, serialized).await?;
+        fs::rename(&tmp_path, path).await
+    }
 }
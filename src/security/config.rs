@@ -11,7 +11,10 @@ use tokio::{
     io::{self, AsyncWriteExt},
 };
 
-use crate::constants::constants::{DEFAULT_PORT, KEY_VALUE, LFRU, LFU, LRU, CONFIG_FILE};
+use crate::constants::constants::{
+    CONFIG_FILE, DEFAULT_HTTP_PORT, DEFAULT_PORT, KEY_VALUE, LFRU, LFU, LRU, PUBSUB_DISCONNECT,
+    PUBSUB_DROP,
+};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DBConfig {
@@ -22,16 +25,72 @@ pub struct DBConfig {
     pub database_type: String,
     pub salt: String,
     pub session_ttl: u64,
+    #[serde(default)]
+    pub persist_sessions: bool, // Keeps sessions in `sessions.json` so they can be resumed after a restart
+    #[serde(default = "default_auth_max_failures")]
+    pub auth_max_failures: u32, // Failed attempts before a source or user is locked out (0 = off)
+    #[serde(default = "default_auth_backoff_ms")]
+    pub auth_backoff_ms: u64, // Wait after the first failure, doubled with every further failure
+    #[serde(default = "default_auth_lockout_secs")]
+    pub auth_lockout_secs: u64, // Duration of a lockout
 
     // server configurations
     pub host: IpAddr,           // IP address the database server listens on
     pub port: String,           // Network port for database comminication
     pub max_connections: usize, // Maximum concurrent client connections
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64, // Closes connections that send nothing for this long (0 = off)
+    #[serde(default)]
+    pub max_session_duration_secs: u64, // Closes connections older than this (0 = off)
+    #[serde(default)]
+    pub http_enabled: bool, // Starts the HTTP/JSON API alongside the TCP server
+    #[serde(default = "default_http_port")]
+    pub http_port: String, // Network port for the HTTP/JSON API
+    #[serde(default = "default_ws_stats_interval_secs")]
+    pub ws_stats_interval_secs: u64, // Interval of DBSTATS pushes to WebSocket clients (0 = off)
+    #[serde(default)]
+    pub unix_socket_path: String, // Unix domain socket to listen on next to TCP (empty = off)
+    #[serde(default = "default_unix_socket_permissions")]
+    pub unix_socket_permissions: String, // Octal file mode of the socket, e.g. "660"
+    #[serde(default)]
+    pub unix_trusted_uid: Option<u32>, // Peers with this uid skip password verification
+    #[serde(default)]
+    pub unix_trusted_gid: Option<u32>, // Peers with this gid skip password verification
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64, // Time in-flight requests get to finish on shutdown
+    #[serde(default = "default_pubsub_buffer_size")]
+    pub pubsub_buffer_size: usize, // Messages buffered for each pub/sub subscriber
+    #[serde(default = "default_pubsub_slow_consumer")]
+    pub pubsub_slow_consumer: String, // "drop" or "disconnect", for subscribers whose buffer is full
+    #[serde(default = "default_max_stream_size")]
+    pub max_stream_size: usize, // Entries a stream keeps before the oldest are trimmed (0 = unlimited)
+    #[serde(default = "default_max_queue_size")]
+    pub max_queue_size: usize, // Messages a queue holds before pushes fail (0 = unlimited)
+    #[serde(default = "default_queue_visibility_timeout_secs")]
+    pub queue_visibility_timeout_secs: u64, // Time a popped message stays hidden without QPOP VISIBILITY
+    #[serde(default = "default_queue_max_deliveries")]
+    pub queue_max_deliveries: u64, // Deliveries before a message is dead lettered (0 = never)
+
+    // rate limiting, in requests and request bytes per second (0 = unlimited)
+    #[serde(default)]
+    pub connection_commands_per_sec: u64,
+    #[serde(default)]
+    pub connection_bytes_per_sec: u64,
+    #[serde(default)]
+    pub user_commands_per_sec: u64,
+    #[serde(default)]
+    pub user_bytes_per_sec: u64,
+    #[serde(default)]
+    pub database_commands_per_sec: u64,
+    #[serde(default)]
+    pub database_bytes_per_sec: u64,
 
     // memory database settings
     pub max_entries: usize,    // Maximum number of entries per database cache
     pub default_ttl_secs: u64, // Default TTL for entries (0 = no expiry)
     pub checkpoint_interval_secs: u64, // Interval for periodic checkpoints
+    #[serde(default)]
+    pub snapshot_on_shutdown: bool, // Writes a snapshot of every database on shutdown
 
     // Performance tuning
     pub worker_threads: usize, // Number of worker threads for parallel processing for optimized CPU utilization
@@ -47,14 +106,42 @@ impl Default for DBConfig {
             database_type: KEY_VALUE.to_string(),
             salt: String::new(),
             session_ttl: 86400,
+            persist_sessions: false,
+            auth_max_failures: default_auth_max_failures(),
+            auth_backoff_ms: default_auth_backoff_ms(),
+            auth_lockout_secs: default_auth_lockout_secs(),
 
             host: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
             port: DEFAULT_PORT.to_string(),
             max_connections: 20,
+            idle_timeout_secs: default_idle_timeout_secs(),
+            max_session_duration_secs: 0,
+            http_enabled: false,
+            http_port: DEFAULT_HTTP_PORT.to_string(),
+            ws_stats_interval_secs: 5,
+            unix_socket_path: String::new(),
+            unix_socket_permissions: default_unix_socket_permissions(),
+            unix_trusted_uid: None,
+            unix_trusted_gid: None,
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
+            pubsub_buffer_size: default_pubsub_buffer_size(),
+            pubsub_slow_consumer: default_pubsub_slow_consumer(),
+            max_stream_size: default_max_stream_size(),
+            max_queue_size: default_max_queue_size(),
+            queue_visibility_timeout_secs: default_queue_visibility_timeout_secs(),
+            queue_max_deliveries: default_queue_max_deliveries(),
+
+            connection_commands_per_sec: 0,
+            connection_bytes_per_sec: 0,
+            user_commands_per_sec: 0,
+            user_bytes_per_sec: 0,
+            database_commands_per_sec: 0,
+            database_bytes_per_sec: 0,
 
             max_entries: 1200,
             default_ttl_secs: 604800,
             checkpoint_interval_secs: 3600,
+            snapshot_on_shutdown: false,
 
             worker_threads: num_cpus::get(),
             eviction_policy: LFRU.to_string(),
@@ -62,6 +149,62 @@ impl Default for DBConfig {
     }
 }
 
+fn default_http_port() -> String {
+    DEFAULT_HTTP_PORT.to_string()
+}
+
+fn default_ws_stats_interval_secs() -> u64 {
+    5
+}
+
+fn default_unix_socket_permissions() -> String {
+    "660".to_string()
+}
+
+fn default_auth_max_failures() -> u32 {
+    5
+}
+
+fn default_auth_backoff_ms() -> u64 {
+    500
+}
+
+fn default_auth_lockout_secs() -> u64 {
+    300
+}
+
+fn default_idle_timeout_secs() -> u64 {
+    300
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+fn default_pubsub_buffer_size() -> usize {
+    1024
+}
+
+fn default_pubsub_slow_consumer() -> String {
+    PUBSUB_DROP.to_string()
+}
+
+fn default_max_stream_size() -> usize {
+    10000
+}
+
+fn default_max_queue_size() -> usize {
+    5000
+}
+
+fn default_queue_visibility_timeout_secs() -> u64 {
+    30
+}
+
+fn default_queue_max_deliveries() -> u64 {
+    5
+}
+
 impl DBConfig {
     /// load_or_create is used to load the configuration if the database is already configured, else create a new one
     ///
@@ -127,6 +270,16 @@ impl DBConfig {
             return Err("eviction_policy must be 'LFRU', 'LRU', or 'LFU'".to_string());
         }
 
+        if ![PUBSUB_DROP, PUBSUB_DISCONNECT].contains(&self.pubsub_slow_consumer.as_str()) {
+            return Err("pubsub_slow_consumer must be 'drop' or 'disconnect'".to_string());
+        }
+
+        if u32::from_str_radix(&self.unix_socket_permissions, 8).is_err() {
+            return Err(
+                "unix_socket_permissions must be an octal file mode, e.g. '660'".to_string(),
+            );
+        }
+
         Ok(())
     }
 }
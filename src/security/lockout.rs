@@ -0,0 +1,265 @@
+/// lockout.rs tracks failed authentication attempts per source IP and per username.
+/// Every failure makes the next attempt from the same source or for the same user wait twice as
+/// long, and `auth_max_failures` consecutive failures lock them out for `auth_lockout_secs`.
+/// Blocked attempts are rejected before the password is verified, so they cost no Argon2 work.
+/// The configured admin is only counted per source IP, so failures from elsewhere cannot lock
+/// the one account that can clear lockouts out of every source.
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use super::config::DBConfig;
+
+/// Start of the error returned for blocked attempts
+pub const LOCKED_OUT: &str = "Too many failed attempts";
+
+/// What failed attempts are counted against
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum LockoutKey {
+    Ip(IpAddr),
+    User(String),
+}
+
+impl fmt::Display for LockoutKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockoutKey::Ip(ip) => write!(f, "ip:{}", ip),
+            LockoutKey::User(user) => write!(f, "user:{}", user),
+        }
+    }
+}
+
+impl LockoutKey {
+    /// *parse* reads a key in its `ip:<address>` or `user:<name>` form
+    pub fn parse(key: &str) -> Option<Self> {
+        match key.split_once(':')? {
+            ("ip", ip) => ip.parse().ok().map(LockoutKey::Ip),
+            ("user", user) if !user.is_empty() => Some(LockoutKey::User(user.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// What `LOCKOUTS` reports about a blocked source or user
+#[derive(Debug, Clone, Serialize)]
+pub struct LockoutInfo {
+    pub key: String,
+    pub failures: u32,
+    pub locked: bool,
+    pub retry_after_secs: u64,
+}
+
+struct Attempts {
+    failures: u32,
+    last_failure: Instant,
+    blocked_until: Instant,
+}
+
+pub struct LockoutTracker {
+    max_failures: u32,
+    admin: String,
+    backoff: Duration,
+    lockout: Duration,
+    attempts: Mutex<HashMap<LockoutKey, Attempts>>,
+}
+
+impl LockoutTracker {
+    pub fn new(config: &DBConfig) -> Self {
+        Self {
+            max_failures: config.auth_max_failures,
+            admin: config.admin.clone(),
+            backoff: Duration::from_millis(config.auth_backoff_ms),
+            lockout: Duration::from_secs(config.auth_lockout_secs),
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        self.max_failures > 0
+    }
+
+    fn keys(&self, source: Option<IpAddr>, username: &str) -> Vec<LockoutKey> {
+        let mut keys: Vec<LockoutKey> = source.map(LockoutKey::Ip).into_iter().collect();
+        // token authentications have no username of their own, only the source counts
+        if !username.is_empty() && username != self.admin {
+            keys.push(LockoutKey::User(username.to_string()));
+        }
+        keys
+    }
+
+    /// *check* returns how long to wait if the source or the user is currently blocked
+    pub fn check(&self, source: Option<IpAddr>, username: &str) -> Result<(), Duration> {
+        if !self.enabled() {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        let attempts = self.attempts.lock().unwrap();
+        let wait = self
+            .keys(source, username)
+            .iter()
+            .filter_map(|key| attempts.get(key))
+            .map(|attempts| attempts.blocked_until.saturating_duration_since(now))
+            .max()
+            .unwrap_or_default();
+
+        if wait.is_zero() {
+            Ok(())
+        } else {
+            Err(wait)
+        }
+    }
+
+    /// *record_failure* counts a failed attempt, returning the keys it locked out
+    pub fn record_failure(&self, source: Option<IpAddr>, username: &str) -> Vec<LockoutKey> {
+        if !self.enabled() {
+            return Vec::new();
+        }
+
+        let now = Instant::now();
+        let mut attempts = self.attempts.lock().unwrap();
+        // failures are forgotten once nothing failed for a whole lockout period
+        attempts.retain(|_, attempts| {
+            attempts.blocked_until > now || now.duration_since(attempts.last_failure) < self.lockout
+        });
+
+        let mut locked = Vec::new();
+        for key in self.keys(source, username) {
+            let entry = attempts.entry(key.clone()).or_insert(Attempts {
+                failures: 0,
+                last_failure: now,
+                blocked_until: now,
+            });
+            let was_locked = entry.failures >= self.max_failures && entry.blocked_until > now;
+            entry.failures += 1;
+            entry.last_failure = now;
+
+            if entry.failures >= self.max_failures {
+                entry.blocked_until = now + self.lockout;
+                if !was_locked {
+                    locked.push(key);
+                }
+            } else {
+                let backoff = self.backoff * 2u32.saturating_pow(entry.failures - 1);
+                entry.blocked_until = now + backoff.min(self.lockout);
+            }
+        }
+        locked
+    }
+
+    /// *record_success* forgets the failures of the source and the user
+    pub fn record_success(&self, source: Option<IpAddr>, username: &str) {
+        if !self.enabled() {
+            return;
+        }
+
+        let mut attempts = self.attempts.lock().unwrap();
+        for key in self.keys(source, username) {
+            attempts.remove(&key);
+        }
+    }
+
+    /// *list* returns the sources and users with failed attempts, longest blocked first
+    pub fn list(&self) -> Vec<LockoutInfo> {
+        let now = Instant::now();
+        let attempts = self.attempts.lock().unwrap();
+        let mut lockouts: Vec<LockoutInfo> = attempts
+            .iter()
+            .filter(|(_, attempts)| now.duration_since(attempts.last_failure) < self.lockout)
+            .map(|(key, attempts)| LockoutInfo {
+                key: key.to_string(),
+                failures: attempts.failures,
+                locked: attempts.failures >= self.max_failures && attempts.blocked_until > now,
+                retry_after_secs: attempts
+                    .blocked_until
+                    .saturating_duration_since(now)
+                    .as_secs(),
+            })
+            .collect();
+        lockouts.sort_by(|a, b| {
+            b.retry_after_secs
+                .cmp(&a.retry_after_secs)
+                .then_with(|| a.key.cmp(&b.key))
+        });
+        lockouts
+    }
+
+    /// *clear* forgets the failures of one key, or of every key when `key` is `None`.
+    /// Returns the number of keys cleared.
+    pub fn clear(&self, key: Option<&LockoutKey>) -> usize {
+        let mut attempts = self.attempts.lock().unwrap();
+        match key {
+            Some(key) => attempts.remove(key).map_or(0, |_| 1),
+            None => {
+                let cleared = attempts.len();
+                attempts.clear();
+                cleared
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_then_lockout() {
+        let config = DBConfig {
+            auth_max_failures: 3,
+            auth_backoff_ms: 1000,
+            auth_lockout_secs: 60,
+            ..DBConfig::default()
+        };
+        let tracker = LockoutTracker::new(&config);
+        let ip: IpAddr = "10.0.0.7".parse().unwrap();
+
+        assert!(tracker.check(Some(ip), "admin").is_ok());
+        assert!(tracker.record_failure(Some(ip), "admin").is_empty());
+        let wait = tracker.check(Some(ip), "admin").unwrap_err();
+        assert!(wait <= Duration::from_secs(1));
+        // the user is blocked from any source
+        assert!(tracker.check(None, "admin").is_err());
+
+        tracker.record_failure(Some(ip), "admin");
+        let locked = tracker.record_failure(Some(ip), "admin");
+        assert_eq!(
+            locked,
+            vec![LockoutKey::Ip(ip), LockoutKey::User("admin".to_string())]
+        );
+        assert!(tracker.check(Some(ip), "other").unwrap_err() > Duration::from_secs(30));
+        assert!(tracker.list().iter().all(|lockout| lockout.locked));
+
+        assert_eq!(tracker.clear(LockoutKey::parse("ip:10.0.0.7").as_ref()), 1);
+        assert!(tracker.check(Some(ip), "other").is_ok());
+        assert!(tracker.check(Some(ip), "admin").is_err());
+        assert_eq!(tracker.clear(None), 1);
+        assert!(tracker.check(Some(ip), "admin").is_ok());
+    }
+
+    #[test]
+    fn test_admin_only_locked_out_per_ip() {
+        let config = DBConfig {
+            admin: "root".to_string(),
+            auth_max_failures: 1,
+            auth_lockout_secs: 60,
+            ..DBConfig::default()
+        };
+        let tracker = LockoutTracker::new(&config);
+        let ip: IpAddr = "10.0.0.7".parse().unwrap();
+
+        assert_eq!(
+            tracker.record_failure(Some(ip), "root"),
+            vec![LockoutKey::Ip(ip)]
+        );
+        assert!(tracker.check(Some(ip), "root").is_err());
+        assert!(tracker
+            .check(Some("10.0.0.8".parse().unwrap()), "root")
+            .is_ok());
+    }
+}
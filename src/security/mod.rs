@@ -1,3 +1,9 @@
+pub mod acl;
+pub mod audit;
 pub mod auth;
 pub mod config;
-pub mod mongo_config;
\ No newline at end of file
+pub mod lockout;
+pub mod mongo_config;
+pub mod rate_limit;
+pub mod tokens;
+pub mod users;
\ No newline at end of file
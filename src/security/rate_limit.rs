@@ -0,0 +1,248 @@
+/// rate_limit.rs holds the token buckets that throttle clients.
+/// Limits are set per connection, per user and per database, in commands and in request bytes
+/// per second. A bucket holds at most one second worth of tokens, so that is also the burst.
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+use super::config::DBConfig;
+
+/// Which limit rejected a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scope {
+    Connection,
+    User,
+    Database,
+}
+
+/// Number of requests rejected on a database, by the limit that rejected them
+#[derive(Debug, Default, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ThrottleStats {
+    pub connection: u64,
+    pub user: u64,
+    pub database: u64,
+}
+
+struct TokenBucket {
+    rate: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: u64, now: Instant) -> Self {
+        Self {
+            rate: rate as f64,
+            tokens: rate as f64,
+            refilled_at: now,
+        }
+    }
+
+    /// *wait* refills the bucket and returns how long until `amount` tokens are available.
+    /// Requests larger than the bucket only need a full bucket, otherwise they would never pass.
+    fn wait(&mut self, amount: f64, now: Instant) -> Duration {
+        let elapsed = now
+            .saturating_duration_since(self.refilled_at)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.rate);
+        self.refilled_at = now;
+
+        let missing = amount.min(self.rate) - self.tokens;
+        if missing > 0.0 {
+            Duration::from_secs_f64(missing / self.rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    fn take(&mut self, amount: f64) {
+        self.tokens -= amount.min(self.rate);
+    }
+}
+
+/// The command and byte buckets of one connection, user or database
+pub struct Buckets {
+    commands: Option<TokenBucket>,
+    bytes: Option<TokenBucket>,
+}
+
+impl Buckets {
+    fn new(commands_per_sec: u64, bytes_per_sec: u64, now: Instant) -> Self {
+        Self {
+            commands: (commands_per_sec > 0).then(|| TokenBucket::new(commands_per_sec, now)),
+            bytes: (bytes_per_sec > 0).then(|| TokenBucket::new(bytes_per_sec, now)),
+        }
+    }
+
+    fn wait(&mut self, bytes: usize, now: Instant) -> Duration {
+        let commands = self
+            .commands
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.wait(1.0, now));
+        let bytes = self
+            .bytes
+            .as_mut()
+            .map_or(Duration::ZERO, |bucket| bucket.wait(bytes as f64, now));
+        commands.max(bytes)
+    }
+
+    fn take(&mut self, bytes: usize) {
+        if let Some(bucket) = self.commands.as_mut() {
+            bucket.take(1.0);
+        }
+        if let Some(bucket) = self.bytes.as_mut() {
+            bucket.take(bytes as f64);
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Limit {
+    commands_per_sec: u64,
+    bytes_per_sec: u64,
+}
+
+impl Limit {
+    fn enabled(&self) -> bool {
+        self.commands_per_sec > 0 || self.bytes_per_sec > 0
+    }
+
+    fn buckets(&self) -> Buckets {
+        Buckets::new(self.commands_per_sec, self.bytes_per_sec, Instant::now())
+    }
+}
+
+/// *RateLimiter* keeps the user and database buckets, connections own their buckets
+pub struct RateLimiter {
+    connection: Limit,
+    user: Limit,
+    database: Limit,
+    users: DashMap<String, Buckets>,
+    databases: DashMap<String, Buckets>,
+    throttled: DashMap<String, ThrottleStats>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &DBConfig) -> Self {
+        Self {
+            connection: Limit {
+                commands_per_sec: config.connection_commands_per_sec,
+                bytes_per_sec: config.connection_bytes_per_sec,
+            },
+            user: Limit {
+                commands_per_sec: config.user_commands_per_sec,
+                bytes_per_sec: config.user_bytes_per_sec,
+            },
+            database: Limit {
+                commands_per_sec: config.database_commands_per_sec,
+                bytes_per_sec: config.database_bytes_per_sec,
+            },
+            users: DashMap::new(),
+            databases: DashMap::new(),
+            throttled: DashMap::new(),
+        }
+    }
+
+    /// *connection_buckets* creates the buckets of a new connection
+    pub fn connection_buckets(&self) -> Buckets {
+        self.connection.buckets()
+    }
+
+    /// *check* takes a command and its bytes from every bucket that applies, or none of them.
+    /// On rejection it returns how long the caller should wait before retrying.
+    pub fn check(
+        &self,
+        connection: &mut Buckets,
+        user: &str,
+        database: &str,
+        bytes: usize,
+    ) -> Result<(), Duration> {
+        let now = Instant::now();
+        let mut user_buckets = self.user.enabled().then(|| {
+            self.users
+                .entry(user.to_string())
+                .or_insert_with(|| self.user.buckets())
+        });
+        let mut database_buckets = self.database.enabled().then(|| {
+            self.databases
+                .entry(database.to_string())
+                .or_insert_with(|| self.database.buckets())
+        });
+
+        let mut waits = vec![(Scope::Connection, connection.wait(bytes, now))];
+        if let Some(buckets) = user_buckets.as_mut() {
+            waits.push((Scope::User, buckets.wait(bytes, now)));
+        }
+        if let Some(buckets) = database_buckets.as_mut() {
+            waits.push((Scope::Database, buckets.wait(bytes, now)));
+        }
+
+        let (scope, wait) = waits
+            .into_iter()
+            .max_by_key(|(_, wait)| *wait)
+            .unwrap_or((Scope::Connection, Duration::ZERO));
+        if !wait.is_zero() {
+            let mut stats = self.throttled.entry(database.to_string()).or_default();
+            match scope {
+                Scope::Connection => stats.connection += 1,
+                Scope::User => stats.user += 1,
+                Scope::Database => stats.database += 1,
+            }
+            return Err(wait);
+        }
+
+        connection.take(bytes);
+        if let Some(buckets) = user_buckets.as_mut() {
+            buckets.take(bytes);
+        }
+        if let Some(buckets) = database_buckets.as_mut() {
+            buckets.take(bytes);
+        }
+        Ok(())
+    }
+
+    /// *stats* returns the number of requests rejected on the database
+    pub fn stats(&self, database: &str) -> ThrottleStats {
+        self.throttled
+            .get(database)
+            .map(|stats| stats.clone())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_user_limit_shared_between_connections() {
+        let config = DBConfig {
+            user_commands_per_sec: 2,
+            connection_bytes_per_sec: 100,
+            ..DBConfig::default()
+        };
+        let limiter = RateLimiter::new(&config);
+        let mut first = limiter.connection_buckets();
+        let mut second = limiter.connection_buckets();
+
+        assert!(limiter.check(&mut first, "admin", "shop", 10).is_ok());
+        assert!(limiter.check(&mut second, "admin", "shop", 10).is_ok());
+        let wait = limiter.check(&mut second, "admin", "shop", 10).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(1));
+
+        // another user only hits its connection's byte limit, with a full bucket even for
+        // a request larger than the limit
+        let mut third = limiter.connection_buckets();
+        assert!(limiter.check(&mut third, "reporting", "shop", 500).is_ok());
+        assert!(limiter.check(&mut third, "reporting", "shop", 10).is_err());
+
+        assert_eq!(
+            limiter.stats("shop"),
+            ThrottleStats {
+                connection: 1,
+                user: 1,
+                database: 0
+            }
+        );
+    }
+}
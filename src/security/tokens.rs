@@ -0,0 +1,307 @@
+/// tokens.rs is the persisted store of the API tokens used by service accounts.
+/// A token is a random secret handed out once by `CREATE_TOKEN`, only its SHA-256 hash is kept.
+/// Tokens carry their own grants and an optional expiry, and act as the principal
+/// `token:<name>` once authenticated, so they never share rights with a user account.
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        RwLock,
+    },
+    time::Duration,
+};
+use tokio::{fs, sync::Mutex};
+
+use super::users::{Permission, ALL_DATABASES};
+use crate::utils::utils::{compute_expiry_using_ttl, compute_now_timestamp};
+
+/// Username of connection strings that authenticate with a token, `tinycache://token:<t>@db:kv`
+pub const TOKEN_USERNAME: &str = "token";
+
+/// Prefix of the session usernames of token principals
+pub const TOKEN_PRINCIPAL_PREFIX: &str = "token:";
+
+/// Prefix of every token secret, makes leaked tokens easy to spot
+const TOKEN_SECRET_PREFIX: &str = "tct_";
+
+/// Seconds the saved `last_used_at` of tokens may lag behind, authentications only save the
+/// store when it was saved longer ago, `flush` saves the rest
+const LAST_USED_SAVE_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Token {
+    token_hash: String,
+    grants: BTreeMap<String, BTreeSet<Permission>>,
+    created_at: u64,
+    expires_at: Option<u64>,
+    last_used_at: Option<u64>,
+}
+
+impl Token {
+    fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// What `TOKENS` reports about a token, everything but the hash
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenInfo {
+    pub name: String,
+    pub grants: BTreeMap<String, BTreeSet<Permission>>,
+    pub created_at: u64,
+    pub expires_at: Option<u64>,
+    pub last_used_at: Option<u64>,
+}
+
+pub struct TokenStore {
+    path: PathBuf,
+    tokens: RwLock<HashMap<String, Token>>,
+    save_lock: Mutex<()>,
+    saved_at: AtomicU64,      // when the store was last saved
+    unsaved_uses: AtomicBool, // whether a use was recorded after that
+}
+
+impl TokenStore {
+    /// *load* reads the store from `path`, a missing file is an empty store
+    pub async fn load(path: PathBuf) -> io::Result<Self> {
+        let tokens = match fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            tokens: RwLock::new(tokens),
+            save_lock: Mutex::new(()),
+            saved_at: AtomicU64::new(0),
+            unsaved_uses: AtomicBool::new(false),
+        })
+    }
+
+    /// *create* issues a token with `permissions` on the database and returns its secret,
+    /// which cannot be recovered afterwards
+    pub async fn create(
+        &self,
+        name: &str,
+        database: &str,
+        permissions: &[Permission],
+        ttl: Option<Duration>,
+    ) -> Result<String, String> {
+        if name.is_empty() || name.contains([':', '@']) {
+            return Err("INVALID_TOKEN_NAME".to_string());
+        }
+        Permission::check_grant(database, permissions)?;
+
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let secret = format!("{}{}", TOKEN_SECRET_PREFIX, hex::encode(bytes));
+
+        {
+            let mut tokens = self.tokens.write().unwrap();
+            if tokens.contains_key(name) {
+                return Err("TOKEN_EXISTS".to_string());
+            }
+            tokens.insert(
+                name.to_string(),
+                Token {
+                    token_hash: hash_secret(&secret),
+                    grants: BTreeMap::from([(
+                        database.to_string(),
+                        permissions.iter().copied().collect(),
+                    )]),
+                    created_at: compute_now_timestamp(),
+                    expires_at: compute_expiry_using_ttl(ttl),
+                    last_used_at: None,
+                },
+            );
+        }
+        self.save().await.map(|_| secret)
+    }
+
+    /// *authenticate* resolves a secret to the name of its live token and records the use.
+    /// Uses are saved at most every `LAST_USED_SAVE_SECS`, not with every authentication.
+    pub async fn authenticate(&self, secret: &str) -> Option<String> {
+        let token_hash = hash_secret(secret);
+        let now = compute_now_timestamp();
+        let name = {
+            let mut tokens = self.tokens.write().unwrap();
+            let (name, token) = tokens
+                .iter_mut()
+                .find(|(_, token)| token.token_hash == token_hash)?;
+            if token.is_expired(now) {
+                return None;
+            }
+            token.last_used_at = Some(now);
+            name.clone()
+        };
+
+        // the use is still recorded in memory if the write fails
+        if now.saturating_sub(self.saved_at.load(Ordering::SeqCst)) >= LAST_USED_SAVE_SECS {
+            let _ = self.save().await;
+        } else {
+            self.unsaved_uses.store(true, Ordering::SeqCst);
+        }
+        Some(name)
+    }
+
+    /// *flush* saves the uses recorded since the last save, if any
+    pub async fn flush(&self) -> Result<(), String> {
+        if self.unsaved_uses.load(Ordering::SeqCst) {
+            self.save().await
+        } else {
+            Ok(())
+        }
+    }
+
+    /// *permissions* returns what a live token may do on the database, including its `*` grants
+    pub fn permissions(&self, name: &str, database: &str) -> BTreeSet<Permission> {
+        let tokens = self.tokens.read().unwrap();
+        match tokens.get(name) {
+            Some(token) if !token.is_expired(compute_now_timestamp()) => [database, ALL_DATABASES]
+                .iter()
+                .filter_map(|name| token.grants.get(*name))
+                .flatten()
+                .copied()
+                .collect(),
+            _ => BTreeSet::new(),
+        }
+    }
+
+    /// *default_database* is the database a token connects to when none is named, the one it
+    /// was created for unless that is `*`
+    pub fn default_database(&self, name: &str) -> Option<String> {
+        let tokens = self.tokens.read().unwrap();
+        tokens
+            .get(name)?
+            .grants
+            .keys()
+            .find(|database| *database != ALL_DATABASES)
+            .cloned()
+    }
+
+    /// *revoke* deletes the token, returns `false` if there is no such token
+    pub async fn revoke(&self, name: &str) -> Result<bool, String> {
+        if self.tokens.write().unwrap().remove(name).is_none() {
+            return Ok(false);
+        }
+        self.save().await.map(|_| true)
+    }
+
+    /// *list* returns the tokens ordered by name
+    pub fn list(&self) -> Vec<TokenInfo> {
+        let tokens = self.tokens.read().unwrap();
+        let mut list: Vec<TokenInfo> = tokens
+            .iter()
+            .map(|(name, token)| TokenInfo {
+                name: name.clone(),
+                grants: token.grants.clone(),
+                created_at: token.created_at,
+                expires_at: token.expires_at,
+                last_used_at: token.last_used_at,
+            })
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// *save* writes the store with write-then-rename, so a crash never loses the tokens
+    async fn save(&self) -> Result<(), String> {
+        let _guard = self.save_lock.lock().await;
+        // uses recorded while the store is written are left for the next save
+        self.unsaved_uses.store(false, Ordering::SeqCst);
+        let serialized = {
+            let tokens = self.tokens.read().unwrap();
+            serde_json::to_vec_pretty(&*tokens).map_err(|e| e.to_string())?
+        };
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        let written = match fs::write(&tmp_path, serialized).await {
+            Ok(()) => fs::rename(&tmp_path, &self.path).await,
+            Err(e) => Err(e),
+        };
+        match written {
+            Ok(()) => {
+                self.saved_at
+                    .store(compute_now_timestamp(), Ordering::SeqCst);
+                Ok(())
+            }
+            Err(e) => {
+                self.unsaved_uses.store(true, Ordering::SeqCst);
+                Err(e.to_string())
+            }
+        }
+    }
+}
+
+fn hash_secret(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_token_lifecycle() {
+        let path =
+            std::env::temp_dir().join(format!("tinycache-tokens-{}.json", uuid::Uuid::new_v4()));
+        let store = TokenStore::load(path.clone()).await.unwrap();
+
+        let secret = store
+            .create("billing", "shop", &[Permission::Read], None)
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .create("billing", "shop", &[Permission::Read], None)
+                .await,
+            Err("TOKEN_EXISTS".to_string())
+        );
+        store
+            .create("expired", "shop", &[Permission::Read], Some(Duration::ZERO))
+            .await
+            .unwrap();
+
+        let store = TokenStore::load(path.clone()).await.unwrap();
+        assert!(!std::fs::read_to_string(&path).unwrap().contains(&secret));
+        assert_eq!(
+            store.authenticate(&secret).await,
+            Some("billing".to_string())
+        );
+        assert_eq!(store.authenticate("tct_wrong").await, None);
+        assert!(store.list()[0].last_used_at.is_some());
+        assert_eq!(
+            store.permissions("billing", "shop"),
+            BTreeSet::from([Permission::Read])
+        );
+        assert!(store.permissions("expired", "shop").is_empty());
+        assert_eq!(store.default_database("billing"), Some("shop".to_string()));
+
+        // uses right after a save stay in memory until the store is flushed
+        let reporting = store
+            .create("reporting", "shop", &[Permission::Read], None)
+            .await
+            .unwrap();
+        store.authenticate(&reporting).await.unwrap();
+        let last_used = |store: &TokenStore| {
+            let tokens = store.list();
+            let token = tokens.iter().find(|token| token.name == "reporting");
+            token.unwrap().last_used_at
+        };
+        assert!(last_used(&store).is_some());
+        assert!(last_used(&TokenStore::load(path.clone()).await.unwrap()).is_none());
+        store.flush().await.unwrap();
+        assert!(last_used(&TokenStore::load(path.clone()).await.unwrap()).is_some());
+
+        assert_eq!(store.revoke("billing").await, Ok(true));
+        assert_eq!(store.authenticate(&secret).await, None);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,370 @@
+/// users.rs is the persisted store of the user accounts and their grants.
+/// A grant gives a user a set of permissions on one database, or on every database with `*`.
+/// Key patterns narrow a user further down to the matching keys of a database, see `acl.rs`.
+/// The admin from `DBConfig` is not part of the store, it always has every permission.
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io,
+    path::PathBuf,
+    sync::RwLock,
+};
+use tokio::{fs, sync::Mutex};
+
+use super::acl::KeyAcl;
+
+/// Database name of the grants that apply to every database
+pub const ALL_DATABASES: &str = "*";
+
+/// A command category a user can be granted on a database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Read,    // key reads, DBSTATS and change pushes
+    Write,   // key writes and deletes
+    Query,   // QUERY
+    ClearDb, // CLEAR_DB
+    Logs,    // VIEW_LOGS
+    Admin,   // users, connections, lockouts and server wide stats and logs, only granted on `*`
+}
+
+impl Permission {
+    const ALL: [Permission; 6] = [
+        Permission::Read,
+        Permission::Write,
+        Permission::Query,
+        Permission::ClearDb,
+        Permission::Logs,
+        Permission::Admin,
+    ];
+
+    /// *parse_grant* expands a role (`read_only`, `read_write`, `admin`) or a single permission
+    /// name into the permissions it grants
+    pub fn parse_grant(name: &str) -> Option<Vec<Permission>> {
+        let permissions = match name.to_lowercase().as_str() {
+            "read_only" => vec![Permission::Read, Permission::Query],
+            "read_write" => vec![Permission::Read, Permission::Write, Permission::Query],
+            "admin" => Permission::ALL.to_vec(),
+            "read" => vec![Permission::Read],
+            "write" => vec![Permission::Write],
+            "query" => vec![Permission::Query],
+            "clear_db" => vec![Permission::ClearDb],
+            "logs" => vec![Permission::Logs],
+            _ => return None,
+        };
+        Some(permissions)
+    }
+
+    /// *check_grant* rejects `admin` on anything but `*`, it is only ever checked server wide
+    /// and would otherwise be stored without effect
+    pub fn check_grant(database: &str, permissions: &[Permission]) -> Result<(), String> {
+        if database != ALL_DATABASES && permissions.contains(&Permission::Admin) {
+            return Err("ADMIN_REQUIRES_ALL_DATABASES".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct User {
+    password_hash: String,
+    grants: BTreeMap<String, BTreeSet<Permission>>,
+    #[serde(default)]
+    key_patterns: BTreeMap<String, BTreeSet<String>>,
+}
+
+/// What `USERS` reports about an account, everything but the password hash
+#[derive(Debug, Clone, Serialize)]
+pub struct UserInfo {
+    pub username: String,
+    pub grants: BTreeMap<String, BTreeSet<Permission>>,
+    pub key_patterns: BTreeMap<String, BTreeSet<String>>,
+}
+
+pub struct UserStore {
+    path: PathBuf,
+    users: RwLock<HashMap<String, User>>,
+    save_lock: Mutex<()>,
+}
+
+impl UserStore {
+    /// *load* reads the store from `path`, a missing file is an empty store
+    pub async fn load(path: PathBuf) -> io::Result<Self> {
+        let users = match fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path,
+            users: RwLock::new(users),
+            save_lock: Mutex::new(()),
+        })
+    }
+
+    pub fn exists(&self, username: &str) -> bool {
+        self.users.read().unwrap().contains_key(username)
+    }
+
+    /// *verify* checks the password of a user, `false` for unknown users
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        let password_hash = match self.users.read().unwrap().get(username) {
+            Some(user) => user.password_hash.clone(),
+            None => return false,
+        };
+
+        PasswordHash::new(&password_hash).is_ok_and(|hash| {
+            Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok()
+        })
+    }
+
+    /// *permissions* returns what the user may do on the database, including its `*` grants
+    pub fn permissions(&self, username: &str, database: &str) -> BTreeSet<Permission> {
+        let users = self.users.read().unwrap();
+        let Some(user) = users.get(username) else {
+            return BTreeSet::new();
+        };
+
+        [database, ALL_DATABASES]
+            .iter()
+            .filter_map(|name| user.grants.get(*name))
+            .flatten()
+            .copied()
+            .collect()
+    }
+
+    /// *key_acl* returns the keys the user may touch on the database, including its `*` patterns
+    pub fn key_acl(&self, username: &str, database: &str) -> KeyAcl {
+        let users = self.users.read().unwrap();
+        let Some(user) = users.get(username) else {
+            return KeyAcl::default();
+        };
+
+        KeyAcl::new(
+            [database, ALL_DATABASES]
+                .iter()
+                .filter_map(|name| user.key_patterns.get(*name))
+                .flatten()
+                .cloned()
+                .collect(),
+        )
+    }
+
+    pub async fn create_user(&self, username: &str, password: &str) -> Result<(), String> {
+        if username.is_empty() || username.contains([':', '@']) {
+            return Err("INVALID_USERNAME".to_string());
+        }
+        if password.is_empty() {
+            return Err("INVALID_PASSWORD".to_string());
+        }
+
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|e| e.to_string())?
+            .to_string();
+
+        {
+            let mut users = self.users.write().unwrap();
+            if users.contains_key(username) {
+                return Err("USER_EXISTS".to_string());
+            }
+            users.insert(
+                username.to_string(),
+                User {
+                    password_hash,
+                    grants: BTreeMap::new(),
+                    key_patterns: BTreeMap::new(),
+                },
+            );
+        }
+        self.save().await
+    }
+
+    /// *drop_user* removes the user, returns `false` if there is no such user
+    pub async fn drop_user(&self, username: &str) -> Result<bool, String> {
+        if self.users.write().unwrap().remove(username).is_none() {
+            return Ok(false);
+        }
+        self.save().await.map(|_| true)
+    }
+
+    pub async fn grant(
+        &self,
+        username: &str,
+        database: &str,
+        permissions: &[Permission],
+    ) -> Result<(), String> {
+        Permission::check_grant(database, permissions)?;
+        {
+            let mut users = self.users.write().unwrap();
+            let user = users.get_mut(username).ok_or("NOT_FOUND")?;
+            user.grants
+                .entry(database.to_string())
+                .or_default()
+                .extend(permissions);
+        }
+        self.save().await
+    }
+
+    pub async fn revoke(
+        &self,
+        username: &str,
+        database: &str,
+        permissions: &[Permission],
+    ) -> Result<(), String> {
+        {
+            let mut users = self.users.write().unwrap();
+            let user = users.get_mut(username).ok_or("NOT_FOUND")?;
+            if let Some(granted) = user.grants.get_mut(database) {
+                granted.retain(|permission| !permissions.contains(permission));
+                if granted.is_empty() {
+                    user.grants.remove(database);
+                }
+            }
+        }
+        self.save().await
+    }
+
+    /// *add_key_pattern* restricts the user to the keys matching the pattern, next to its others
+    pub async fn add_key_pattern(
+        &self,
+        username: &str,
+        database: &str,
+        pattern: &str,
+    ) -> Result<(), String> {
+        {
+            let mut users = self.users.write().unwrap();
+            let user = users.get_mut(username).ok_or("NOT_FOUND")?;
+            user.key_patterns
+                .entry(database.to_string())
+                .or_default()
+                .insert(pattern.to_string());
+        }
+        self.save().await
+    }
+
+    /// *remove_key_pattern* drops a pattern, returns `false` if the user did not have it.
+    /// Removing the last pattern of a database lifts the restriction.
+    pub async fn remove_key_pattern(
+        &self,
+        username: &str,
+        database: &str,
+        pattern: &str,
+    ) -> Result<bool, String> {
+        {
+            let mut users = self.users.write().unwrap();
+            let user = users.get_mut(username).ok_or("NOT_FOUND")?;
+            let Some(patterns) = user.key_patterns.get_mut(database) else {
+                return Ok(false);
+            };
+            if !patterns.remove(pattern) {
+                return Ok(false);
+            }
+            if patterns.is_empty() {
+                user.key_patterns.remove(database);
+            }
+        }
+        self.save().await.map(|_| true)
+    }
+
+    /// *list* returns the users ordered by name
+    pub fn list(&self) -> Vec<UserInfo> {
+        let users = self.users.read().unwrap();
+        let mut list: Vec<UserInfo> = users
+            .iter()
+            .map(|(username, user)| UserInfo {
+                username: username.clone(),
+                grants: user.grants.clone(),
+                key_patterns: user.key_patterns.clone(),
+            })
+            .collect();
+        list.sort_by(|a, b| a.username.cmp(&b.username));
+        list
+    }
+
+    /// *save* writes the store with write-then-rename, so a crash never loses the accounts
+    async fn save(&self) -> Result<(), String> {
+        let _guard = self.save_lock.lock().await;
+        let serialized = {
+            let users = self.users.read().unwrap();
+            serde_json::to_vec_pretty(&*users).map_err(|e| e.to_string())?
+        };
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, serialized)
+            .await
+            .map_err(|e| e.to_string())?;
+        fs::rename(&tmp_path, &self.path)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_grants_persist() {
+        let path =
+            std::env::temp_dir().join(format!("tinycache-users-{}.json", uuid::Uuid::new_v4()));
+        let store = UserStore::load(path.clone()).await.unwrap();
+
+        store.create_user("reporting", "s3cret").await.unwrap();
+        assert_eq!(
+            store.create_user("reporting", "other").await,
+            Err("USER_EXISTS".to_string())
+        );
+        store
+            .grant(
+                "reporting",
+                "shop",
+                &Permission::parse_grant("read_write").unwrap(),
+            )
+            .await
+            .unwrap();
+        store
+            .grant("reporting", ALL_DATABASES, &[Permission::Logs])
+            .await
+            .unwrap();
+        assert_eq!(
+            store
+                .grant(
+                    "reporting",
+                    "shop",
+                    &Permission::parse_grant("admin").unwrap()
+                )
+                .await,
+            Err("ADMIN_REQUIRES_ALL_DATABASES".to_string())
+        );
+        store
+            .revoke("reporting", "shop", &[Permission::Write])
+            .await
+            .unwrap();
+
+        let store = UserStore::load(path.clone()).await.unwrap();
+        assert!(store.verify("reporting", "s3cret"));
+        assert!(!store.verify("reporting", "wrong"));
+        assert_eq!(
+            store.permissions("reporting", "shop"),
+            BTreeSet::from([Permission::Read, Permission::Query, Permission::Logs])
+        );
+        assert_eq!(
+            store.permissions("reporting", "other"),
+            BTreeSet::from([Permission::Logs])
+        );
+
+        assert_eq!(store.drop_user("reporting").await, Ok(true));
+        assert!(!store.exists("reporting"));
+        let _ = std::fs::remove_file(&path);
+    }
+}
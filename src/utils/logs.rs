@@ -269,6 +269,13 @@ impl Logger {
         Ok(())
     }
 
+    /// Flushes buffered log entries to the current log file.
+    pub async fn flush(&self) -> io::Result<()> {
+        let mut writer = self.current_file.write().await;
+        writer.flush().await?;
+        writer.get_ref().sync_all().await
+    }
+
     pub async fn log_info(
         &self,
         message: &str,
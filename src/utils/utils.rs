@@ -23,6 +23,14 @@ pub fn compute_now_timestamp() -> u64 {
     now
 }
 
+/// *compute_now_millis* is the current time in milliseconds since the epoch
+pub fn compute_now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_millis() as u64
+}
+
 pub fn compute_expiry_using_ttl(ttl: Option<Duration>) -> Option<u64> {
     let expiry = ttl.and_then(|duration| {
         (SystemTime::now() + duration)
@@ -81,6 +89,11 @@ pub async fn display_startup_info(db: &Arc<TinyCache>) {
     println!("├─ Host & Port: {}:{}", config.host, config.port);
     println!("├─ Eviction Policy: {}", config.eviction_policy);
     println!("├─ Default TTL: {}", config.default_ttl_secs);
+    if config.http_enabled {
+        println!("├─ HTTP API: {}:{}", config.host, config.http_port);
+    } else {
+        println!("├─ HTTP API: disabled");
+    }
     println!("└─ Max Connections: {}", config.max_connections);
 
     // Worker Configuration
@@ -228,8 +241,8 @@ pub fn compute_expiry() -> Option<Duration> {
 ///
 /// The database type is also set as well for proper request routing and handling
 ///
-/// The context uses the username, password and database name
-/// in the format username:password@database
+/// The context is the database name, so every user granted access to a database
+/// works on the same data. Access itself is checked per command by the `AuthManager`.
 pub fn set_database_context(
     connection_string: &str,
 ) -> Result<(String, DatabaseType), &'static str> {
@@ -271,8 +284,7 @@ pub fn set_database_context(
 
     let db_type = DatabaseType::from_str(db_type_str);
 
-    // Combine credentials and database for the full database name
-    Ok((format!("{}@{}", credentials, database), db_type))
+    Ok((database.to_string(), db_type))
 }
 
 pub fn print_art() {
@@ -0,0 +1,62 @@
+use rustyline::{
+    completion::{Completer, Pair},
+    Context, Helper, Highlighter, Hinter, Validator,
+};
+use tinycache_protocol::commands::{COMMANDS, QUERY_OPERATIONS};
+
+/// Line editor helper completing command names, and query operations after `QUERY`
+#[derive(Helper, Highlighter, Hinter, Validator)]
+pub struct CommandHelper;
+
+impl Completer for CommandHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map(|i| i + 1).unwrap_or(0);
+        let word = &line[start..];
+
+        let candidates = if start == 0 {
+            COMMANDS
+        } else if line.split_whitespace().next() == Some("QUERY") {
+            QUERY_OPERATIONS
+        } else {
+            return Ok((pos, Vec::new()));
+        };
+
+        Ok((start, complete_word(word, candidates)))
+    }
+}
+
+/// *complete_word* returns the candidates starting with `word`, ignoring case
+fn complete_word(word: &str, candidates: &[(&str, &str)]) -> Vec<Pair> {
+    let word = word.to_uppercase();
+    candidates
+        .iter()
+        .filter(|(name, _)| name.to_uppercase().starts_with(&word))
+        .map(|(name, usage)| Pair {
+            display: usage.to_string(),
+            replacement: format!("{} ", name),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_complete_word() {
+        let names: Vec<_> = complete_word("ge", COMMANDS)
+            .into_iter()
+            .map(|pair| pair.replacement)
+            .collect();
+        assert_eq!(names, vec!["GET_KEY ", "Get_All_KV "]);
+        assert!(complete_word("XYZ", COMMANDS).is_empty());
+    }
+}
@@ -0,0 +1,95 @@
+use colored::*;
+use serde_json::Value as JsonValue;
+use tinycache_protocol::{Response, ResponseData};
+
+/// *format_response* renders a response for humans, in the spirit of `redis-cli`
+pub fn format_response(response: &Response) -> String {
+    if response.status != "success" {
+        let message = response.message.as_deref().unwrap_or("unknown error");
+        let retry_after = match &response.data {
+            Some(ResponseData::Json(data)) => data["retry_after_ms"].as_u64(),
+            _ => None,
+        };
+        return match retry_after {
+            Some(ms) => format!("(error) {} (retry after {} ms)", message, ms),
+            None => format!("(error) {}", message),
+        }
+        .red()
+        .to_string();
+    }
+
+    match &response.data {
+        Some(data) => format_data(data),
+        None => "(nil)".dimmed().to_string(),
+    }
+}
+
+fn format_data(data: &ResponseData) -> String {
+    match data {
+        ResponseData::String(s) => s.green().to_string(),
+        ResponseData::None(s) if s.is_empty() => "(nil)".dimmed().to_string(),
+        ResponseData::None(s) => format!("(nil) {}", s).dimmed().to_string(),
+        ResponseData::Error(e) => format!("(error) {}", e).red().to_string(),
+        ResponseData::List(items) | ResponseData::Set(items) => format_items(items),
+        ResponseData::Json(value) | ResponseData::Session(value) => format_json(value),
+        ResponseData::Batch(entries) => {
+            let mut entries: Vec<_> = entries.iter().collect();
+            entries.sort();
+            entries
+                .into_iter()
+                .map(|(key, value)| format!("{} {}", key.cyan(), value))
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    }
+}
+
+fn format_items(items: &[String]) -> String {
+    if items.is_empty() {
+        return "(empty)".dimmed().to_string();
+    }
+    items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| format!("{} {}", format!("{})", i + 1).dimmed(), item))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn format_json(value: &JsonValue) -> String {
+    match value {
+        JsonValue::String(s) => s.green().to_string(),
+        JsonValue::Number(n) => n.to_string().yellow().to_string(),
+        JsonValue::Bool(b) => b.to_string().yellow().to_string(),
+        JsonValue::Null => "(nil)".dimmed().to_string(),
+        value => serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_response() {
+        colored::control::set_override(false);
+
+        assert_eq!(
+            format_response(&Response::error("NOT_FOUND")),
+            "(error) NOT_FOUND"
+        );
+        assert_eq!(
+            format_response(&Response::success(ResponseData::List(vec![
+                "a".to_string(),
+                "b".to_string()
+            ]))),
+            "1) a\n2) b"
+        );
+        assert_eq!(
+            format_response(&Response::success(ResponseData::Json(
+                serde_json::json!({"count": 2})
+            ))),
+            "{\n  \"count\": 2\n}"
+        );
+    }
+}
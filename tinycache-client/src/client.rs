@@ -0,0 +1,461 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value as JsonValue;
+use std::{sync::Arc, time::Duration};
+use tinycache_protocol::{Response, ResponseData};
+
+use crate::{
+    error::{Error, Result},
+    pool::Pool,
+    query::Query,
+};
+
+/// Number of connections a client keeps open unless configured otherwise
+pub const DEFAULT_POOL_SIZE: usize = 4;
+
+/// Builder for a [`Client`]
+pub struct ClientBuilder {
+    address: String,
+    connection_string: String,
+    pool_size: usize,
+}
+
+impl ClientBuilder {
+    pub fn new(address: impl Into<String>, connection_string: impl Into<String>) -> Self {
+        ClientBuilder {
+            address: address.into(),
+            connection_string: connection_string.into(),
+            pool_size: DEFAULT_POOL_SIZE,
+        }
+    }
+
+    /// *pool_size* sets the maximum number of connections used concurrently
+    pub fn pool_size(mut self, pool_size: usize) -> Self {
+        self.pool_size = pool_size;
+        self
+    }
+
+    /// *build* opens the first connection so that bad addresses or credentials surface here
+    /// rather than on the first command
+    pub async fn build(self) -> Result<Client> {
+        let pool = Arc::new(Pool::new(
+            self.address,
+            self.connection_string,
+            self.pool_size,
+        ));
+        drop(pool.get().await?);
+        Ok(Client { pool })
+    }
+}
+
+/// Async client for a TinyCache server
+///
+/// The client is cheap to clone, all clones share the same connection pool. When the server
+/// expires a session the client reconnects with the connection string and retries the
+/// commands that were not executed yet.
+#[derive(Clone)]
+pub struct Client {
+    pool: Arc<Pool>,
+}
+
+impl Client {
+    /// *connect* connects with the default pool size
+    pub async fn connect(
+        address: impl Into<String>,
+        connection_string: impl Into<String>,
+    ) -> Result<Self> {
+        ClientBuilder::new(address, connection_string).build().await
+    }
+
+    pub fn builder(
+        address: impl Into<String>,
+        connection_string: impl Into<String>,
+    ) -> ClientBuilder {
+        ClientBuilder::new(address, connection_string)
+    }
+
+    pub async fn ping(&self) -> Result<()> {
+        self.execute("PING").await?;
+        Ok(())
+    }
+
+    /// *get* returns the value stored under `key`, `None` if it does not exist
+    pub async fn get(&self, key: &str) -> Result<Option<ResponseData>> {
+        let response = self
+            .send_one(format!("GET_KEY {}", check_key(key)?))
+            .await?;
+        if is_not_found(&response) {
+            return Ok(None);
+        }
+        into_data(response).map(Some)
+    }
+
+    /// *get_json* returns the JSON value stored under `key` deserialized into `T`
+    pub async fn get_json<T: DeserializeOwned>(&self, key: &str) -> Result<Option<T>> {
+        match self.get(key).await? {
+            Some(ResponseData::Json(value)) => Ok(Some(serde_json::from_value(value)?)),
+            Some(ResponseData::String(value)) => {
+                Ok(Some(serde_json::from_value(JsonValue::String(value))?))
+            }
+            Some(other) => Err(Error::Protocol(format!("unexpected value {:?}", other))),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<()> {
+        self.execute(&set_command(key, value)?).await?;
+        Ok(())
+    }
+
+    /// *set_ex* stores a value that expires after `ttl` (whole seconds)
+    pub async fn set_ex<T: Serialize + ?Sized>(
+        &self,
+        key: &str,
+        ttl: Duration,
+        value: &T,
+    ) -> Result<()> {
+        self.execute(&set_ex_command(key, ttl, value)?).await?;
+        Ok(())
+    }
+
+    /// *update* replaces an existing value, returns `false` if the key does not exist
+    pub async fn update<T: Serialize + ?Sized>(&self, key: &str, value: &T) -> Result<bool> {
+        let command = format!(
+            "UPDATE_KEY {} {}",
+            check_key(key)?,
+            serde_json::to_string(value)?
+        );
+        let response = self.send_one(command).await?;
+        if is_not_found(&response) {
+            return Ok(false);
+        }
+        into_data(response).map(|_| true)
+    }
+
+    /// *delete* removes a key, returns `false` if it did not exist
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        let response = self
+            .send_one(format!("DELETE_KEY {}", check_key(key)?))
+            .await?;
+        if is_not_found(&response) {
+            return Ok(false);
+        }
+        into_data(response).map(|_| true)
+    }
+
+    /// *incr* adds `amount` to a numeric value and returns the new value
+    pub async fn incr(&self, key: &str, amount: f64) -> Result<f64> {
+        self.numeric(format!("INCR_KEY {} {}", check_key(key)?, amount))
+            .await
+    }
+
+    /// *decr* subtracts `amount` from a numeric value and returns the new value
+    pub async fn decr(&self, key: &str, amount: f64) -> Result<f64> {
+        self.numeric(format!("DECR_KEY {} {}", check_key(key)?, amount))
+            .await
+    }
+
+    /// *query* runs a [`Query`] and returns its result document
+    pub async fn query(&self, query: &Query) -> Result<JsonValue> {
+        match self.execute(&query.to_command()).await? {
+            ResponseData::Json(value) => Ok(value),
+            other => Err(Error::Protocol(format!(
+                "unexpected query result {:?}",
+                other
+            ))),
+        }
+    }
+
+    /// *execute* sends a raw command and returns the data of a successful response
+    pub async fn execute(&self, command: &str) -> Result<ResponseData> {
+        into_data(self.send_one(command.to_string()).await?)
+    }
+
+    /// *send* sends a raw command and returns the response envelope as is, error responses
+    /// included
+    pub async fn send(&self, command: &str) -> Result<Response> {
+        self.send_one(command.to_string()).await
+    }
+
+    /// *pipeline* starts a batch of commands that is sent in a single round trip
+    pub fn pipeline(&self) -> Pipeline<'_> {
+        Pipeline {
+            client: self,
+            commands: Vec::new(),
+            error: None,
+        }
+    }
+
+    async fn numeric(&self, command: String) -> Result<f64> {
+        match self.execute(&command).await? {
+            ResponseData::Json(value) => value
+                .as_f64()
+                .ok_or_else(|| Error::Protocol(format!("expected a number, got {}", value))),
+            other => Err(Error::Protocol(format!(
+                "expected a number, got {:?}",
+                other
+            ))),
+        }
+    }
+
+    async fn send_one(&self, command: String) -> Result<Response> {
+        let mut responses = self.send_batch(vec![command]).await?;
+        responses
+            .pop()
+            .ok_or_else(|| Error::Protocol("missing response".to_string()))
+    }
+
+    /// *send_batch* runs commands on a pooled connection, re-authenticating once if the
+    /// session expires midway
+    async fn send_batch(&self, commands: Vec<String>) -> Result<Vec<Response>> {
+        let mut connection = self.pool.get().await?;
+
+        let mut batch = match connection.send(&commands).await {
+            Ok(batch) => batch,
+            Err(e) => {
+                connection.discard();
+                return Err(e);
+            }
+        };
+        if !batch.session_expired {
+            return Ok(batch.responses);
+        }
+
+        // the server closes the connection after reporting the expiry, so only the commands
+        // it did not answer are sent again
+        if let Err(e) = connection.reconnect().await {
+            connection.discard();
+            return Err(e);
+        }
+        let mut responses = std::mem::take(&mut batch.responses);
+        match connection.send(&commands[responses.len()..]).await {
+            Ok(retry) if !retry.session_expired => {
+                responses.extend(retry.responses);
+                Ok(responses)
+            }
+            Ok(_) => {
+                connection.discard();
+                Err(Error::SessionExpired)
+            }
+            Err(e) => {
+                connection.discard();
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A batch of commands sent to the server in a single write
+///
+/// Responses come back in the order the commands were added. Errors of individual commands
+/// are reported in their `Response` rather than failing the whole batch.
+pub struct Pipeline<'a> {
+    client: &'a Client,
+    commands: Vec<String>,
+    error: Option<Error>,
+}
+
+impl Pipeline<'_> {
+    pub fn get(&mut self, key: &str) -> &mut Self {
+        let command = check_key(key).map(|key| format!("GET_KEY {}", key));
+        self.push(command)
+    }
+
+    pub fn set<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> &mut Self {
+        let command = set_command(key, value);
+        self.push(command)
+    }
+
+    pub fn set_ex<T: Serialize + ?Sized>(
+        &mut self,
+        key: &str,
+        ttl: Duration,
+        value: &T,
+    ) -> &mut Self {
+        let command = set_ex_command(key, ttl, value);
+        self.push(command)
+    }
+
+    pub fn delete(&mut self, key: &str) -> &mut Self {
+        let command = check_key(key).map(|key| format!("DELETE_KEY {}", key));
+        self.push(command)
+    }
+
+    pub fn incr(&mut self, key: &str, amount: f64) -> &mut Self {
+        let command = check_key(key).map(|key| format!("INCR_KEY {} {}", key, amount));
+        self.push(command)
+    }
+
+    pub fn decr(&mut self, key: &str, amount: f64) -> &mut Self {
+        let command = check_key(key).map(|key| format!("DECR_KEY {} {}", key, amount));
+        self.push(command)
+    }
+
+    /// *command* adds a raw command to the batch
+    pub fn command(&mut self, command: impl Into<String>) -> &mut Self {
+        self.push(Ok(command.into()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// *execute* sends the batch, failing early if any command could not be built
+    pub async fn execute(&mut self) -> Result<Vec<Response>> {
+        if let Some(e) = self.error.take() {
+            self.commands.clear();
+            return Err(e);
+        }
+        if self.commands.is_empty() {
+            return Ok(Vec::new());
+        }
+        let commands = std::mem::take(&mut self.commands);
+        self.client.send_batch(commands).await
+    }
+
+    fn push(&mut self, command: Result<String>) -> &mut Self {
+        match command {
+            Ok(command) => self.commands.push(command),
+            Err(e) => {
+                self.error.get_or_insert(e);
+            }
+        }
+        self
+    }
+}
+
+// Keys travel inside a whitespace separated command, so they cannot contain whitespace themselves
+fn check_key(key: &str) -> Result<&str> {
+    if key.is_empty() || key.chars().any(char::is_whitespace) {
+        return Err(Error::InvalidArgument(format!("invalid key {:?}", key)));
+    }
+    Ok(key)
+}
+
+fn set_command<T: Serialize + ?Sized>(key: &str, value: &T) -> Result<String> {
+    Ok(format!(
+        "SET {} {}",
+        check_key(key)?,
+        serde_json::to_string(value)?
+    ))
+}
+
+fn set_ex_command<T: Serialize + ?Sized>(key: &str, ttl: Duration, value: &T) -> Result<String> {
+    Ok(format!(
+        "SET_EX {} {} {}",
+        check_key(key)?,
+        ttl.as_secs(),
+        serde_json::to_string(value)?
+    ))
+}
+
+fn is_not_found(response: &Response) -> bool {
+    response.status == "error" && response.message.as_deref() == Some("NOT_FOUND")
+}
+
+fn into_data(response: Response) -> Result<ResponseData> {
+    if response.status == "error" && response.message.as_deref() == Some("RATE_LIMITED") {
+        let retry_after = match &response.data {
+            Some(ResponseData::Json(data)) => data["retry_after_ms"].as_u64().unwrap_or(0),
+            _ => 0,
+        };
+        return Err(Error::RateLimited(Duration::from_millis(retry_after)));
+    }
+    if response.status != "success" {
+        return Err(Error::Server(response.message.unwrap_or_default()));
+    }
+    response
+        .data
+        .ok_or_else(|| Error::Protocol("success response without data".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    /// Serves a connection that expires the session after `expire_after` requests, then
+    /// answers every request on the next connection
+    async fn fake_server(expire_after: usize) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        tokio::spawn(async move {
+            let mut served = 0;
+            for connection in 0.. {
+                let (socket, _) = listener.accept().await.unwrap();
+                let (reader, mut writer) = socket.into_split();
+                let mut lines = BufReader::new(reader).lines();
+
+                lines.next_line().await.unwrap();
+                writer.write_all(b"AUTH OK session\n").await.unwrap();
+
+                while let Ok(Some(line)) = lines.next_line().await {
+                    if connection == 0 && served == expire_after {
+                        writer
+                            .write_all(b"Session expired. Please reconnect.\n")
+                            .await
+                            .unwrap();
+                        break;
+                    }
+                    served += 1;
+                    let command = line.split_once(' ').unwrap().1.to_string();
+                    let response = Response::success(ResponseData::String(command));
+                    let reply = format!("{}\r\n", serde_json::to_string(&response).unwrap());
+                    writer.write_all(reply.as_bytes()).await.unwrap();
+                }
+            }
+        });
+
+        address
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_resumes_after_session_expiry() {
+        let address = fake_server(2).await;
+        let client = Client::connect(address, "tinycache://u:p@db:kv")
+            .await
+            .unwrap();
+
+        let responses = client
+            .pipeline()
+            .set("a", &1)
+            .get("a")
+            .incr("a", 2.0)
+            .delete("a")
+            .execute()
+            .await
+            .unwrap();
+
+        let commands: Vec<_> = responses
+            .into_iter()
+            .map(|response| match response.data {
+                Some(ResponseData::String(command)) => command,
+                other => panic!("unexpected data {:?}", other),
+            })
+            .collect();
+        assert_eq!(
+            commands,
+            vec!["SET a 1", "GET_KEY a", "INCR_KEY a 2", "DELETE_KEY a"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_invalid_key_is_rejected() {
+        let address = fake_server(usize::MAX).await;
+        let client = Client::connect(address, "tinycache://u:p@db:kv")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            client.set("bad key", &1).await,
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
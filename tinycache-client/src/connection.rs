@@ -0,0 +1,128 @@
+use std::io;
+use tinycache_protocol::Response;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+};
+
+use crate::error::{Error, Result};
+
+/// Line the server sends right before closing a connection whose session has expired
+const SESSION_EXPIRED: &str = "Session expired";
+
+/// Line the server sends before closing a connection that stayed idle too long
+const IDLE_TIMEOUT: &str = "Idle timeout";
+
+/// Line the server sends to waiting clients right before it shuts down
+const SHUTTING_DOWN: &str = "Server is shutting down";
+
+/// Line the server sends before closing a connection on `CLIENT KILL`
+const KILLED: &str = "Connection killed";
+
+/// Responses of a batch of commands
+///
+/// When the session expires midway the server stops answering, `responses` then only holds
+/// the answers to the commands that were executed before it did.
+pub(crate) struct Batch {
+    pub responses: Vec<Response>,
+    pub session_expired: bool,
+}
+
+/// A single authenticated connection to the server
+pub(crate) struct Connection {
+    reader: BufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+    connection_string: String,
+}
+
+impl Connection {
+    /// *connect* opens a connection and authenticates it with the connection string
+    pub async fn connect(address: &str, connection_string: &str) -> Result<Self> {
+        let stream = TcpStream::connect(address).await?;
+        stream.set_nodelay(true)?;
+        let (reader, writer) = stream.into_split();
+
+        let mut connection = Connection {
+            reader: BufReader::new(reader),
+            writer,
+            connection_string: connection_string.to_string(),
+        };
+
+        connection
+            .writer
+            .write_all(format!("{}\n", connection_string).as_bytes())
+            .await?;
+
+        let line = connection.read_line().await?;
+        if line.starts_with("AUTH OK") {
+            Ok(connection)
+        } else if let Some(reason) = line.strip_prefix("AUTH ERROR") {
+            Err(Error::Auth(reason.trim().to_string()))
+        } else {
+            Err(Error::Protocol(line))
+        }
+    }
+
+    /// *send* writes all commands in a single write and reads their responses in order
+    pub async fn send(&mut self, commands: &[String]) -> Result<Batch> {
+        let mut payload = String::new();
+        for command in commands {
+            if command.contains('\n') {
+                return Err(Error::InvalidArgument(
+                    "commands cannot contain line breaks".to_string(),
+                ));
+            }
+            payload.push_str(&self.connection_string);
+            payload.push(' ');
+            payload.push_str(command);
+            payload.push('\n');
+        }
+
+        self.writer.write_all(payload.as_bytes()).await?;
+        self.writer.flush().await?;
+
+        let mut responses = Vec::with_capacity(commands.len());
+        for _ in commands {
+            let line = self.read_line().await?;
+            // an idle pooled connection finds the timeout line waiting, it is reopened like an
+            // expired session
+            if line.starts_with(SESSION_EXPIRED) || line.starts_with(IDLE_TIMEOUT) {
+                return Ok(Batch {
+                    responses,
+                    session_expired: true,
+                });
+            }
+            if line.starts_with(SHUTTING_DOWN) {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "server is shutting down",
+                )));
+            }
+            if line.starts_with(KILLED) {
+                return Err(Error::Io(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "connection killed by an administrator",
+                )));
+            }
+            let response = serde_json::from_str(&line)
+                .map_err(|e| Error::Protocol(format!("{}: {}", e, line)))?;
+            responses.push(response);
+        }
+
+        Ok(Batch {
+            responses,
+            session_expired: false,
+        })
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line).await? == 0 {
+            return Err(Error::Io(io::ErrorKind::UnexpectedEof.into()));
+        }
+        Ok(line.trim_end().to_string())
+    }
+}
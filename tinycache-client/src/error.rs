@@ -0,0 +1,59 @@
+use std::{fmt, io, time::Duration};
+
+/// Errors returned by the TinyCache client
+#[derive(Debug)]
+pub enum Error {
+    /// The connection to the server failed or was closed
+    Io(io::Error),
+    /// The server rejected the connection string
+    Auth(String),
+    /// The server answered a command with an error response
+    Server(String),
+    /// The command was over a server rate limit, it can be retried after the given delay
+    RateLimited(Duration),
+    /// The server sent something that is not part of the protocol
+    Protocol(String),
+    /// The session expired again right after re-authenticating
+    SessionExpired,
+    /// The command could not be built from the given arguments
+    InvalidArgument(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "connection error: {}", e),
+            Error::Auth(e) => write!(f, "authentication failed: {}", e),
+            Error::Server(e) => write!(f, "server error: {}", e),
+            Error::RateLimited(retry_after) => {
+                write!(f, "rate limited, retry after {:?}", retry_after)
+            }
+            Error::Protocol(e) => write!(f, "protocol error: {}", e),
+            Error::SessionExpired => write!(f, "session expired"),
+            Error::InvalidArgument(e) => write!(f, "invalid argument: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::InvalidArgument(e.to_string())
+    }
+}
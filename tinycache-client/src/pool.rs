@@ -0,0 +1,106 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::{Arc, Mutex},
+};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{
+    connection::Connection,
+    error::{Error, Result},
+};
+
+/// A bounded pool of authenticated connections
+///
+/// At most `size` connections are checked out at once, idle ones are reused and new ones are
+/// only opened when no idle connection is left.
+pub(crate) struct Pool {
+    address: String,
+    connection_string: String,
+    idle: Mutex<Vec<Connection>>,
+    permits: Arc<Semaphore>,
+}
+
+impl Pool {
+    pub fn new(address: String, connection_string: String, size: usize) -> Self {
+        Pool {
+            address,
+            connection_string,
+            idle: Mutex::new(Vec::new()),
+            permits: Arc::new(Semaphore::new(size.max(1))),
+        }
+    }
+
+    /// *get* checks out a connection, waiting while the pool is exhausted
+    pub async fn get(self: &Arc<Self>) -> Result<PooledConnection> {
+        let permit = self
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| Error::Protocol("connection pool closed".to_string()))?;
+
+        let idle = self.idle.lock().unwrap().pop();
+        let connection = match idle {
+            Some(connection) => connection,
+            None => self.connect().await?,
+        };
+
+        Ok(PooledConnection {
+            pool: Arc::clone(self),
+            connection: Some(connection),
+            _permit: permit,
+        })
+    }
+
+    pub async fn connect(&self) -> Result<Connection> {
+        Connection::connect(&self.address, &self.connection_string).await
+    }
+
+    pub fn release(&self, connection: Connection) {
+        self.idle.lock().unwrap().push(connection);
+    }
+}
+
+/// A connection checked out of the pool, handed back when dropped
+pub(crate) struct PooledConnection {
+    pool: Arc<Pool>,
+    connection: Option<Connection>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection {
+    /// *reconnect* replaces the connection with a freshly authenticated one
+    pub async fn reconnect(&mut self) -> Result<()> {
+        self.connection = None;
+        self.connection = Some(self.pool.connect().await?);
+        Ok(())
+    }
+
+    /// *discard* drops the connection instead of returning it to the pool, used after errors
+    /// that leave the stream in an unknown state
+    pub fn discard(&mut self) {
+        self.connection = None;
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.connection.as_ref().expect("connection was discarded")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.connection.as_mut().expect("connection was discarded")
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let Some(connection) = self.connection.take() {
+            self.pool.release(connection);
+        }
+    }
+}
@@ -0,0 +1,147 @@
+use serde_json::Value as JsonValue;
+use tinycache_protocol::{AggregationOperation, FilterCondition};
+
+/// Builder for `QUERY` commands
+///
+/// Operations run on the server in the order they were added, a `filter` narrows the dataset
+/// for every operation that follows it.
+///
+/// ```
+/// use tinycache_client::Query;
+///
+/// let query = Query::new().filter("/age", "gt", 30).count().avg("/score");
+/// assert_eq!(query.to_command(), "QUERY FILTER /age gt 30 COUNT AVG /score");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    operations: Vec<AggregationOperation>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn count(self) -> Self {
+        self.operation(AggregationOperation::Count)
+    }
+
+    pub fn sum(self, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::Sum(field.into()))
+    }
+
+    pub fn avg(self, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::Average(field.into()))
+    }
+
+    pub fn group_by(self, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::GroupBy(field.into()))
+    }
+
+    /// *filter* keeps the documents whose `field` compares to `value` with `operator`
+    /// (`eq`, `gt`, `lt`, `gte`, `lte` or `contains`)
+    pub fn filter(
+        self,
+        field: impl Into<String>,
+        operator: impl Into<String>,
+        value: impl Into<JsonValue>,
+    ) -> Self {
+        self.operation(AggregationOperation::Filter(FilterCondition {
+            field: field.into(),
+            operator: operator.into(),
+            value: value.into(),
+        }))
+    }
+
+    pub fn min(self, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::Min(field.into()))
+    }
+
+    pub fn max(self, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::Max(field.into()))
+    }
+
+    pub fn distinct(self, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::Distinct(field.into()))
+    }
+
+    pub fn top_n(self, n: usize, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::TopN {
+            field: field.into(),
+            n,
+        })
+    }
+
+    pub fn bottom_n(self, n: usize, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::BottomN {
+            field: field.into(),
+            n,
+        })
+    }
+
+    pub fn median(self, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::Median(field.into()))
+    }
+
+    pub fn std_dev(self, field: impl Into<String>) -> Self {
+        self.operation(AggregationOperation::StdDev(field.into()))
+    }
+
+    pub fn sort(self, field: impl Into<String>, descending: bool) -> Self {
+        self.operation(AggregationOperation::Sort {
+            field: field.into(),
+            descending,
+        })
+    }
+
+    pub fn join(
+        self,
+        source_key: impl Into<String>,
+        source_field: impl Into<String>,
+        target_field: impl Into<String>,
+    ) -> Self {
+        self.operation(AggregationOperation::Join {
+            source_key: source_key.into(),
+            source_field: source_field.into(),
+            target_field: target_field.into(),
+        })
+    }
+
+    pub fn operation(mut self, operation: AggregationOperation) -> Self {
+        self.operations.push(operation);
+        self
+    }
+
+    pub fn operations(&self) -> &[AggregationOperation] {
+        &self.operations
+    }
+
+    /// *to_command* renders the query as the `QUERY` command sent to the server
+    pub fn to_command(&self) -> String {
+        let mut command = "QUERY".to_string();
+        for operation in &self.operations {
+            command.push(' ');
+            command.push_str(&operation.to_command());
+        }
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_to_command() {
+        let query = Query::new()
+            .filter("/city", "eq", "Berlin")
+            .top_n(3, "/score")
+            .sort("/name", true)
+            .join("users", "/id", "/user_id");
+
+        assert_eq!(
+            query.to_command(),
+            "QUERY FILTER /city eq Berlin TOPN 3 /score SORT /name desc JOIN users /id /user_id"
+        );
+    }
+}
@@ -0,0 +1,155 @@
+/// Commands understood by the server, used by clients for completion and help output
+pub const COMMANDS: &[(&str, &str)] = &[
+    ("PING", "PING"),
+    ("LOGOUT", "LOGOUT"),
+    ("DBSTATS", "DBSTATS"),
+    ("ALL_DBSTATS", "ALL_DBSTATS"),
+    ("VIEW_LOGS", "VIEW_LOGS"),
+    ("VIEW_SYSTEM_LOGS", "VIEW_SYSTEM_LOGS"),
+    ("CLEAR_DB", "CLEAR_DB"),
+    ("PUBLISH", "PUBLISH <channel> <message>"),
+    ("SUBSCRIBE", "SUBSCRIBE <channel> [channel ...]"),
+    ("PSUBSCRIBE", "PSUBSCRIBE <pattern> [pattern ...]"),
+    ("UNSUBSCRIBE", "UNSUBSCRIBE [channel ...]"),
+    ("PUNSUBSCRIBE", "PUNSUBSCRIBE [pattern ...]"),
+    ("PUBSUB", "PUBSUB CHANNELS"),
+    ("SCHEMAS", "SCHEMAS"),
+    ("SET_SCHEMA", "SET_SCHEMA <prefix | *> <json schema>"),
+    ("DROP_SCHEMA", "DROP_SCHEMA <prefix | *>"),
+    ("CLIENT", "CLIENT LIST | CLIENT KILL <id>"),
+    ("LOCKOUTS", "LOCKOUTS"),
+    ("CLEAR_LOCKOUTS", "CLEAR_LOCKOUTS [ip:<address> | user:<name>]"),
+    ("USERS", "USERS"),
+    ("CREATE_USER", "CREATE_USER <username> <password>"),
+    ("DROP_USER", "DROP_USER <username>"),
+    ("GRANT", "GRANT <username> <database | *> <role | permission>"),
+    ("REVOKE", "REVOKE <username> <database | *> <role | permission>"),
+    ("ACL_ADD", "ACL_ADD <username> <database | *> <key pattern>"),
+    ("ACL_REMOVE", "ACL_REMOVE <username> <database | *> <key pattern>"),
+    ("AUDIT", "AUDIT [from_unix_secs] [to_unix_secs]"),
+    ("AUDIT_VERIFY", "AUDIT_VERIFY"),
+    ("TOKENS", "TOKENS"),
+    (
+        "CREATE_TOKEN",
+        "CREATE_TOKEN <name> <database | *> <role | permission> [ttl_secs]",
+    ),
+    ("REVOKE_TOKEN", "REVOKE_TOKEN <name>"),
+    ("QUERY", "QUERY <operation> [operation ...]"),
+    ("SET", "SET <key> <json>"),
+    ("SET_EX", "SET_EX <key> <ttl_secs> <value>"),
+    ("GET_KEY", "GET_KEY <key>"),
+    ("UPDATE_KEY", "UPDATE_KEY <key> <json>"),
+    ("DELETE_KEY", "DELETE_KEY <key>"),
+    ("INCR_KEY", "INCR_KEY <key> <amount>"),
+    ("DECR_KEY", "DECR_KEY <key> <amount>"),
+    ("LPUSH", "LPUSH <key> <value> [value ...]"),
+    ("RPUSH", "RPUSH <key> <value> [value ...]"),
+    ("LPOP", "LPOP <key> [count]"),
+    ("RPOP", "RPOP <key> [count]"),
+    ("LRANGE", "LRANGE <key> <start> <stop>"),
+    ("LLEN", "LLEN <key>"),
+    ("LINDEX", "LINDEX <key> <index>"),
+    ("LSET", "LSET <key> <index> <value>"),
+    ("LREM", "LREM <key> <count> <value>"),
+    ("LTRIM", "LTRIM <key> <start> <stop>"),
+    ("LINSERT", "LINSERT <key> BEFORE|AFTER <pivot> <value>"),
+    ("SADD", "SADD <key> <member> [member ...]"),
+    ("SREM", "SREM <key> <member> [member ...]"),
+    ("SISMEMBER", "SISMEMBER <key> <member>"),
+    ("SMEMBERS", "SMEMBERS <key>"),
+    ("SCARD", "SCARD <key>"),
+    ("SPOP", "SPOP <key> [count]"),
+    ("SRANDMEMBER", "SRANDMEMBER <key> [count]"),
+    ("SUNION", "SUNION <key> [key ...]"),
+    ("SINTER", "SINTER <key> [key ...]"),
+    ("SDIFF", "SDIFF <key> [key ...]"),
+    ("SUNIONSTORE", "SUNIONSTORE <destination> <key> [key ...]"),
+    ("SINTERSTORE", "SINTERSTORE <destination> <key> [key ...]"),
+    ("SDIFFSTORE", "SDIFFSTORE <destination> <key> [key ...]"),
+    ("HSET", "HSET <key> <field> <value> [field value ...]"),
+    ("HGET", "HGET <key> <field>"),
+    ("HMGET", "HMGET <key> <field> [field ...]"),
+    ("HDEL", "HDEL <key> <field> [field ...]"),
+    ("HGETALL", "HGETALL <key>"),
+    ("HINCRBYFLOAT", "HINCRBYFLOAT <key> <field> <amount>"),
+    ("HEXISTS", "HEXISTS <key> <field>"),
+    ("HKEYS", "HKEYS <key>"),
+    ("HLEN", "HLEN <key>"),
+    ("ZADD", "ZADD <key> [NX|XX] [GT|LT] <score> <member> [score member ...]"),
+    ("ZINCRBY", "ZINCRBY <key> <amount> <member>"),
+    ("ZSCORE", "ZSCORE <key> <member>"),
+    ("ZRANK", "ZRANK <key> <member> [REV]"),
+    (
+        "ZRANGE",
+        "ZRANGE <key> <start> <stop> [BYSCORE|BYLEX] [REV] [LIMIT <offset> <count>] [WITHSCORES]",
+    ),
+    ("ZREM", "ZREM <key> <member> [member ...]"),
+    ("ZPOPMIN", "ZPOPMIN <key> [count]"),
+    ("ZPOPMAX", "ZPOPMAX <key> [count]"),
+    ("ZCOUNT", "ZCOUNT <key> <min> <max>"),
+    (
+        "XADD",
+        "XADD <key> [MAXLEN <count>] <*|id> <field> <value> [field value ...]",
+    ),
+    ("XLEN", "XLEN <key>"),
+    ("XRANGE", "XRANGE <key> <start> <end> [COUNT <count>]"),
+    (
+        "XREAD",
+        "XREAD [COUNT <count>] [BLOCK <ms>] STREAMS <key> [key ...] <id> [id ...]",
+    ),
+    (
+        "XGROUP",
+        "XGROUP CREATE <key> <group> <id|$> [MKSTREAM] | XGROUP DESTROY <key> <group>",
+    ),
+    (
+        "XREADGROUP",
+        "XREADGROUP GROUP <group> <consumer> [COUNT <count>] [BLOCK <ms>] STREAMS <key> [key ...] <id> [id ...]",
+    ),
+    ("XACK", "XACK <key> <group> <id> [id ...]"),
+    (
+        "XPENDING",
+        "XPENDING <key> <group> [<start> <end> <count> [consumer]]",
+    ),
+    (
+        "XCLAIM",
+        "XCLAIM <key> <group> <consumer> <min-idle-ms> <id> [id ...]",
+    ),
+    ("QPUSH", "QPUSH <key> [DELAY <seconds>] <message>"),
+    ("QPOP", "QPOP <key> [VISIBILITY <seconds>]"),
+    ("QACK", "QACK <key> <receipt>"),
+    ("QNACK", "QNACK <key> <receipt> [DELAY <seconds>]"),
+    ("QSTATS", "QSTATS <key>"),
+    ("QDEAD", "QDEAD <key> [count]"),
+    ("QREDRIVE", "QREDRIVE <key>"),
+    ("JSON_SET", "JSON_SET <key> <path> <json>"),
+    ("JSON_DEL", "JSON_DEL <key> <path>"),
+    ("JSON_ARRAPPEND", "JSON_ARRAPPEND <key> <path> <json> [json ...]"),
+    ("JSON_NUMINCRBY", "JSON_NUMINCRBY <key> <path> <amount>"),
+    ("JSON_MERGE", "JSON_MERGE <key> <merge patch>"),
+    ("JSON_PATCH", "JSON_PATCH <key> <json patch>"),
+    ("VINDEX", "VINDEX <dimension> <COSINE | L2 | DOT>"),
+    ("VINFO", "VINFO"),
+    ("VADD", "VADD <key> <vector> [json metadata]"),
+    ("VDEL", "VDEL <key>"),
+    ("VSEARCH", "VSEARCH <vector> <k> [FILTER <field> <operator> <value> ...]"),
+    ("STORE", "STORE <key> <json>"),
+    ("Get_All_KV", "Get_All_KV"),
+];
+
+/// Operations accepted after `QUERY`
+pub const QUERY_OPERATIONS: &[(&str, &str)] = &[
+    ("COUNT", "COUNT"),
+    ("SUM", "SUM <field>"),
+    ("AVG", "AVG <field>"),
+    ("GROUPBY", "GROUPBY <field>"),
+    ("FILTER", "FILTER <field> <operator> <value>"),
+    ("MIN", "MIN <field>"),
+    ("MAX", "MAX <field>"),
+    ("DISTINCT", "DISTINCT <field>"),
+    ("TOPN", "TOPN <n> <field>"),
+    ("BOTTOMN", "BOTTOMN <n> <field>"),
+    ("MEDIAN", "MEDIAN <field>"),
+    ("STDDEV", "STDDEV <field>"),
+    ("SORT", "SORT <field> asc|desc"),
+    ("JOIN", "JOIN <source_key> <source_field> <target_field>"),
+];
@@ -0,0 +1,11 @@
+//! # TinyCache Protocol
+//!
+//! Types that travel over the wire between the TinyCache server and its clients. The server
+//! serializes every command result into a [`response::Response`] envelope, and clients
+//! deserialize it back into the same types.
+pub mod commands;
+pub mod query;
+pub mod response;
+
+pub use query::{AggregationOperation, FilterCondition};
+pub use response::{Response, ResponseData};
@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
+
+/// A single step of a `QUERY` command.
+///
+/// Operations are applied in order by the server; `Filter` narrows the dataset for every
+/// operation that follows it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum AggregationOperation {
+    Count,
+    Sum(String),
+    Average(String),
+    GroupBy(String),
+    Filter(FilterCondition),
+    Min(String),
+    Max(String),
+    Distinct(String),
+    TopN {
+        field: String,
+        n: usize,
+    },
+    BottomN {
+        field: String,
+        n: usize,
+    },
+    Median(String),
+    StdDev(String),
+    Sort {
+        field: String,
+        descending: bool,
+    },
+    Join {
+        source_key: String,
+        source_field: String,
+        target_field: String,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct FilterCondition {
+    pub field: String,    // field path in value
+    pub operator: String, // "eq", "gt", "lt", "gte", "lte", "contains"
+    pub value: JsonValue,
+}
+
+impl AggregationOperation {
+    /// *to_command* renders the operation in the text syntax the `QUERY` command parses
+    pub fn to_command(&self) -> String {
+        match self {
+            AggregationOperation::Count => "COUNT".to_string(),
+            AggregationOperation::Sum(field) => format!("SUM {}", field),
+            AggregationOperation::Average(field) => format!("AVG {}", field),
+            AggregationOperation::GroupBy(field) => format!("GROUPBY {}", field),
+            AggregationOperation::Filter(condition) => format!(
+                "FILTER {} {} {}",
+                condition.field,
+                condition.operator,
+                match &condition.value {
+                    JsonValue::String(s) => s.clone(),
+                    value => value.to_string(),
+                }
+            ),
+            AggregationOperation::Min(field) => format!("MIN {}", field),
+            AggregationOperation::Max(field) => format!("MAX {}", field),
+            AggregationOperation::Distinct(field) => format!("DISTINCT {}", field),
+            AggregationOperation::TopN { field, n } => format!("TOPN {} {}", n, field),
+            AggregationOperation::BottomN { field, n } => format!("BOTTOMN {} {}", n, field),
+            AggregationOperation::Median(field) => format!("MEDIAN {}", field),
+            AggregationOperation::StdDev(field) => format!("STDDEV {}", field),
+            AggregationOperation::Sort { field, descending } => format!(
+                "SORT {} {}",
+                field,
+                if *descending { "desc" } else { "asc" }
+            ),
+            AggregationOperation::Join {
+                source_key,
+                source_field,
+                target_field,
+            } => format!("JOIN {} {} {}", source_key, source_field, target_field),
+        }
+    }
+}